@@ -15,7 +15,8 @@ use rdkafka::consumer::Consumer;
 use rdkafka::message::Message;
 use rdkafka::util::get_rdkafka_version;
 
-use std::io::{self, BufRead, Cursor};
+use std::fs::File;
+use std::io::{self, BufRead, Cursor, Write};
 use std::str;
 
 #[derive(Debug)]
@@ -97,7 +98,26 @@ fn parse_message(key: &[u8], payload: &[u8]) -> Result<ConsumerUpdate, ParserErr
     }
 }
 
-fn consume_and_print(brokers: &str) {
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes a record in the format read by `kafka-view --replay`: the key and the value in hex,
+/// with `-` as the value of tombstones, followed by the timestamp of the record if known.
+fn write_record(
+    dump: &mut File,
+    key: &[u8],
+    payload: Option<&[u8]>,
+    timestamp: Option<i64>,
+) -> io::Result<()> {
+    let value = payload.map_or_else(|| "-".to_owned(), to_hex);
+    match timestamp {
+        Some(timestamp) => writeln!(dump, "{} {} {}", to_hex(key), value, timestamp),
+        None => writeln!(dump, "{} {}", to_hex(key), value),
+    }
+}
+
+fn consume_and_print(brokers: &str, mut dump: Option<File>) {
     let consumer = ClientConfig::new()
         .set("group.id", "consumer_reader_group")
         .set("bootstrap.servers", brokers)
@@ -138,6 +158,13 @@ fn consume_and_print(brokers: &str) {
                         &[]
                     }
                 };
+                if let Some(ref mut dump) = dump {
+                    let timestamp = m.timestamp().to_millis();
+                    if let Err(e) = write_record(dump, key, m.payload(), timestamp) {
+                        warn!("Can't write to dump file: {:?}", e);
+                    }
+                    continue;
+                }
                 println!(
                     "\n#### P:{}, o:{}, s:{:.3}KB",
                     m.partition(),
@@ -167,6 +194,12 @@ fn main() {
                 .takes_value(true)
                 .default_value("localhost:9092"),
         )
+        .arg(
+            Arg::with_name("dump")
+                .long("dump")
+                .help("Write the records to a file that can be replayed by kafka-view")
+                .takes_value(true),
+        )
         .get_matches();
 
     let (version_n, version_s) = get_rdkafka_version();
@@ -174,5 +207,9 @@ fn main() {
 
     let brokers = matches.value_of("brokers").unwrap();
 
-    consume_and_print(brokers);
+    let dump = matches
+        .value_of("dump")
+        .map(|path| File::create(path).expect("Can't create dump file"));
+
+    consume_and_print(brokers, dump);
 }
@@ -67,6 +67,15 @@ function big_num_to_human(cell, suffix) {
     }
 }
 
+function share_to_human(cell) {
+    var value = parseFloat(cell.innerHTML);
+    if (value < 0) {
+        $(cell).html("Unknown");
+    } else {
+        $(cell).html(value.toFixed(1) + " %");
+    }
+}
+
 function broker_to_url(cluster_id, cell) {
     var broker_name = cell.innerHTML;
     var url = "/clusters/" + cluster_id + "/brokers/" + broker_name;
@@ -95,6 +104,63 @@ function cluster_to_url(cell) {
     $(cell).html(link);
 }
 
+function timestamp_to_date(cell) {
+    var millis = parseInt(cell.innerHTML);
+    var date = new Date(millis);
+    $(cell).html($('<span>', { text: date.toISOString(), title: millis }));
+}
+
+function commit_time_to_date(cell) {
+    var millis = parseInt(cell.innerHTML);
+    if (millis < 0) {
+        $(cell).html("Unknown");
+        return;
+    }
+    var minutes = Math.floor((Date.now() - millis) / 60000);
+    var age = minutes < 60 ? minutes + " min ago" : Math.floor(minutes / 60) + " h ago";
+    $(cell).html($('<span>', { text: age, title: new Date(millis).toISOString() }));
+}
+
+function time_lag_to_human(cell) {
+    var seconds = parseInt(cell.innerHTML);
+    var text;
+    if (seconds < 0) {
+        text = "Unknown";
+    } else if (seconds >= 3600) {
+        text = Math.floor(seconds / 3600) + "h " + Math.floor(seconds % 3600 / 60) + "m";
+    } else if (seconds >= 60) {
+        text = Math.floor(seconds / 60) + "m " + seconds % 60 + "s";
+    } else {
+        text = seconds + "s";
+    }
+    $(cell).html(text);
+}
+
+function lag_status_to_label(cell) {
+    var status = cell.innerHTML;
+    var classes = {
+        "OK": "label-success",
+        "WARNING": "label-warning",
+        "REWIND": "label-danger",
+        "STALLED": "label-danger",
+        "STOPPED": "label-danger"
+    };
+    var label_class = classes[status] || "label-default";
+    $(cell).html($('<span>', { class: 'label ' + label_class, text: status }));
+}
+
+function controller_to_graphic(row, is_controller) {
+    var cell = $(row).children()[4];
+    if (is_controller) {
+        $(row).addClass("info");
+        var symbol = $('<i>', { class: 'fa fa-star fa-fw', title: 'Active controller' });
+        symbol.tooltip();
+        $(cell).html(symbol);
+    } else {
+        $(cell).html("");
+    }
+}
+
 function error_to_graphic(cell) {
     var error_code = cell.innerHTML;
     if (error_code) {
@@ -138,9 +204,10 @@ $(document).ready(function() {
             "stateSave": true,
             "createdRow": function(row, data, index) {
                 var cluster_id = $(this).attr("data-param");
-                // broker_to_url(cluster_id, $(row).children()[0]);
+                broker_to_url(cluster_id, $(row).children()[0]);
                 bytes_to_human($(row).children()[2], "/s");
                 big_num_to_human($(row).children()[3], "msg/s");
+                controller_to_graphic(row, data[4]);
             }
         });
     });
@@ -155,8 +222,8 @@ $(document).ready(function() {
                 { "data": "topic_name" },
                 { "data": "partition_count" },
                 { "data": "errors" },
-                { "data": "b_rate_15" },
-                { "data": "m_rate_15" }
+                { "data": "b_rate" },
+                { "data": "m_rate" }
             ],
             "columnDefs": [
                 { "className": "dt-body-right", "targets": [ 1, 2, 3, 4 ] }
@@ -172,6 +239,48 @@ $(document).ready(function() {
             }
         });
     });
+    $('#datatable-topic-brokers-ajax').each(function(index) {
+        $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "order": [[ 3, "desc" ]],
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 1, 2, 3, 4 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                broker_to_url(cluster_id, $(row).children()[0]);
+                bytes_to_human($(row).children()[1], "/s");
+                big_num_to_human($(row).children()[2], "msg/s");
+                share_to_human($(row).children()[3]);
+                bytes_to_human($(row).children()[4]);
+            }
+        });
+    });
+    $('#datatable-broker-topics-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "order": [[ 1, "desc" ]],
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 1, 2, 3, 4 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                topic_to_url(cluster_id, $(row).children()[0]);
+                bytes_to_human($(row).children()[1], "/s");
+                big_num_to_human($(row).children()[2], "msg/s");
+                share_to_human($(row).children()[3]);
+                bytes_to_human($(row).children()[4]);
+            }
+        });
+    });
     $('#datatable-groups-ajax').each(function(index) {
         $(this).DataTable({
             "search": { "regex": true},
@@ -179,7 +288,7 @@ $(document).ready(function() {
             "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
             "language": { "search": "Regex search:" },
             "columnDefs": [
-                { "className": "dt-body-right", "targets": [ 2, 3 ] }
+                { "className": "dt-body-right", "targets": [ 2, 3, 5 ] }
             ],
             "processing": true,
             "deferRender": true,
@@ -187,6 +296,11 @@ $(document).ready(function() {
             "createdRow": function(row, data, index) {
                 var cluster_id = $(this).attr("data-param");
                 group_to_url(cluster_id, $(row).children()[0]);
+                if (data[4] !== null) {
+                    broker_to_url(cluster_id, $(row).children()[4]);
+                }
+                time_lag_to_human($(row).children()[5]);
+                lag_status_to_label($(row).children()[6]);
             }
         });
     });
@@ -206,6 +320,25 @@ $(document).ready(function() {
             }
         });
     });
+    $('#datatable-events-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "order": [[ 0, "desc" ]],
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                timestamp_to_date($(row).children()[0]);
+                if (data[2]) {
+                    topic_to_url(cluster_id, $(row).children()[2]);
+                }
+            }
+        });
+    });
     $('#datatable-topology-ajax').each(function(index) {
         $(this).DataTable({
             "search": { "regex": true},
@@ -236,6 +369,26 @@ $(document).ready(function() {
             stateSave: true
         });
     });
+    $('#datatable-connect-workers-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "language": { "search": "Regex search:" },
+            "columnDefs": [ ],
+            "processing": true,
+            "deferRender": true,
+            stateSave: true,
+            "createdRow": function(row, data, index) {
+                var leader_cell = $(row).children()[3];
+                if (data[3]) {
+                    $(leader_cell).html($('<i>', { class: 'fa fa-star fa-fw', title: 'Group leader' }));
+                } else {
+                    $(leader_cell).html("");
+                }
+            }
+        });
+    });
     $('#datatable-group-offsets-ajax').each(function(index) {
         var table = $(this).DataTable({
             "search": { "regex": true},
@@ -252,6 +405,9 @@ $(document).ready(function() {
             "createdRow": function(row, data, index) {
                 var cluster_id = $(this).attr("data-param");
                 topic_to_url(cluster_id, $(row).children()[0]);
+                commit_time_to_date($(row).children()[8]);
+                time_lag_to_human($(row).children()[9]);
+                lag_status_to_label($(row).children()[10]);
             }
         });
         setInterval( function () {
@@ -289,7 +445,7 @@ $(document).ready(function() {
             "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
             "pageLength": 50,
             "columnDefs": [
-                { "className": "dt-body-right", "targets": [ 3, 4 ] }
+                { "className": "dt-body-right", "targets": [ 3, 4, 6 ] }
             ],
             "processing": true,
             "deferRender": true,
@@ -297,6 +453,11 @@ $(document).ready(function() {
             "createdRow": function(row, data, index) {
                 var row = $(row).children();
                 group_to_url(row[0].innerHTML, row[1]);
+                if (data[5] !== null) {
+                    broker_to_url(row[0].innerHTML, row[5]);
+                }
+                time_lag_to_human(row[6]);
+                lag_status_to_label(row[7]);
                 cluster_to_url(row[0]);
             }
         });
@@ -340,6 +501,30 @@ $(document).ready(function() {
             table.ajax.reload();
         }, 20000 );
     });
+    $('#datatable-internals-offset-consumers-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, -1], [10, 50, "All"] ],
+            "pageLength": 10,
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 2, 5, 6, 8 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                var row = $(row).children();
+                var error_time = parseInt(row[4].innerHTML);
+                $(row[4]).html(error_time < 0 ? "" : new Date(error_time * 1000).toISOString());
+                $(row[6]).html(parseFloat(row[6].innerHTML).toFixed(1));
+                commit_time_to_date(row[7]);
+                big_num_to_human(row[8]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
     $('#datatable-internals-live-consumers-ajax').each(function(index) {
         var table = $(this).DataTable({
             "ajax": $(this).attr("data-url"),
@@ -430,6 +615,61 @@ $(document).ready(function() {
     })
 });
 
+// History charts
+function _human_value(value, unit) {
+    var k = 1000;
+    var sizes = ['', ' K', ' M', ' G', ' T'];
+    var suffix = " " + unit;
+    if (unit.indexOf("bytes") == 0) {
+        k = 1024;
+        sizes = [' B', ' KiB', ' MiB', ' GiB', ' TiB', ' PiB'];
+        suffix = unit.substring(5);
+    }
+    var i = value < 1 ? 0 : Math.min(Math.floor(Math.log(value) / Math.log(k)), sizes.length - 1);
+    return parseFloat((value / Math.pow(k, i)).toFixed(1)) + sizes[i] + suffix;
+}
+
+function draw_history_chart(container, series) {
+    var width = 300, height = 80;
+    var points = series.points;
+    var panel = $('<div>', { "class": "col-md-4" });
+    panel.append($('<h5>', { text: series.name }));
+    container.append(panel);
+    if (points.length < 2) {
+        panel.append($('<p>', { "class": "text-muted", text: "Not enough data yet" }));
+        return;
+    }
+    var start = points[0][0];
+    var end = points[points.length - 1][0];
+    var max = Math.max.apply(null, points.map(function(p) { return p[1]; }));
+    var scale = max > 0 ? max : 1;
+    var path = points.map(function(p, i) {
+        var x = (p[0] - start) / Math.max(end - start, 1) * width;
+        var y = height - p[1] / scale * height;
+        return (i == 0 ? "M" : "L") + x.toFixed(1) + "," + y.toFixed(1);
+    }).join(" ");
+    panel.append('<svg width="100%" height="' + height + '" viewBox="0 0 ' + width + ' ' + height +
+        '" preserveAspectRatio="none"><path d="' + path + '" fill="none" stroke="#337ab7"' +
+        ' stroke-width="1.5" vector-effect="non-scaling-stroke"/></svg>');
+    var last = points[points.length - 1][1];
+    panel.append($('<small>', {
+        "class": "text-muted",
+        text: "Last: " + _human_value(last, series.unit) + ", max: " + _human_value(max, series.unit) +
+            ", since " + new Date(start * 1000).toLocaleString()
+    }));
+}
+
+$(document).ready(function() {
+    $('.history-charts').each(function(index) {
+        var container = $(this);
+        $.getJSON(container.attr("data-url"), function(response) {
+            response.data.forEach(function(series) {
+                draw_history_chart(container, series);
+            });
+        });
+    });
+});
+
 $(document).ready(function(){
     $('[data-toggle="tooltip"]').tooltip();
     $(window).resize();
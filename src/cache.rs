@@ -18,12 +18,16 @@ use std::borrow::Borrow;
 use std::collections::hash_map;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, SystemTime};
 use crate::zk::perform_put_from_input;
 use error::*;
-use metadata::{Broker, ClusterId, Group, Partition, TopicName};
-use metrics::TopicMetrics;
+use events::ClusterEvent;
+use history::{HistorySeries, MetricHistory};
+use lag::PartitionLag;
+use metadata::{Broker, BrokerId, ClusterId, ClusterInfo, Group, Partition, TopicName};
+use metrics::{BrokerMetrics, TopicMetrics};
+use offsets::{GroupState, OffsetCommit};
 use std::string::String;
 #[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
 struct WrappedKey(String, String);
@@ -51,7 +55,7 @@ impl WrappedKey {
 
 pub struct ReplicaWriter {
     topic_name: String,
-    producer: FutureProducer<EmptyContext>,
+    producer: Option<FutureProducer<EmptyContext>>, // None when offline
 }
 
 impl ReplicaWriter {
@@ -66,7 +70,7 @@ impl ReplicaWriter {
 
         let writer = ReplicaWriter {
             topic_name: topic_name.to_owned(),
-            producer,
+            producer: Some(producer),
         };
 
         let _ = thread::spawn(|| {
@@ -116,6 +120,15 @@ impl ReplicaWriter {
         Ok(writer)
     }
 
+    /// A writer that doesn't replicate anything, used when replaying recorded data without
+    /// access to Kafka.
+    pub fn offline() -> ReplicaWriter {
+        ReplicaWriter {
+            topic_name: String::new(),
+            producer: None,
+        }
+    }
+
     // TODO: use structure for value
     /// Writes a new update into the topic. The name of the replicated map and the key will be
     /// serialized together as key of the message, and the value will be serialized in the payload.
@@ -124,6 +137,10 @@ impl ReplicaWriter {
         K: Serialize + Deserialize<'de> + Clone,
         V: Serialize + Deserialize<'de>,
     {
+        let producer = match self.producer {
+            Some(ref producer) => producer,
+            None => return Ok(()),
+        };
         let serialized_key = serde_json::to_vec(&WrappedKey::new(name.to_owned(), key))
             .chain_err(|| "Failed to serialize key")?;
         let serialized_value =
@@ -134,7 +151,7 @@ impl ReplicaWriter {
             (serialized_value.len() as f64 / 1000f64)
         );
         let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy(
+        let _f = producer.send_copy(
             self.topic_name.as_str(),
             None,
             Some(&serialized_value),
@@ -158,8 +175,12 @@ impl ReplicaWriter {
 
     /// Writes a tombstone for the specified message key.
     fn write_tombstone(&self, message_key: &[u8]) -> Result<()> {
+        let producer = match self.producer {
+            Some(ref producer) => producer,
+            None => return Ok(()),
+        };
         let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy::<[u8], [u8]>(
+        let _f = producer.send_copy::<[u8], [u8]>(
             self.topic_name.as_str(),
             None,
             None,
@@ -375,6 +396,7 @@ where
     name: String,
     map: Arc<RwLock<HashMap<K, ValueContainer<V>>>>,
     replica_writer: Arc<ReplicaWriter>,
+    update_lock: Arc<Mutex<()>>,
 }
 
 impl<K, V> ReplicatedMap<K, V>
@@ -387,6 +409,7 @@ where
             name: name.to_owned(),
             map: Arc::new(RwLock::new(HashMap::new())),
             replica_writer,
+            update_lock: Arc::new(Mutex::new(())),
         }
     }
 
@@ -395,6 +418,7 @@ where
             name: self.name.clone(),
             map: self.map.clone(),
             replica_writer: self.replica_writer.clone(),
+            update_lock: self.update_lock.clone(),
         }
     }
 
@@ -455,6 +479,23 @@ where
         Ok(())
     }
 
+    /// Replaces the value of the key with the one returned by `f`, or leaves it unchanged if `f`
+    /// returns None. Updates are serialized, so that concurrent read-modify-write cycles on the
+    /// same key don't overwrite each other.
+    pub fn update<F>(&self, key: K, f: F) -> Result<()>
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        let _guard = match self.update_lock.lock() {
+            Ok(guard) => guard,
+            Err(_) => bail!("Poison error while updating {}", self.name),
+        };
+        match f(self.get(&key)) {
+            Some(new_value) => self.insert(key, new_value),
+            None => Ok(()),
+        }
+    }
+
     pub fn remove(&self, key: &K) -> Result<()> {
         self.replica_writer
             .delete(&self.name, key)
@@ -594,6 +635,28 @@ pub type OffsetsCache = ReplicatedMap<(ClusterId, String, TopicName), Vec<i64>>;
 /// Offsets for the internal consumers of the __consumer_offsets topic
 pub type InternalConsumerOffsetCache = ReplicatedMap<ClusterId, Vec<i64>>;
 
+/// Controller and cluster id information
+pub type ClusterInfoCache = ReplicatedMap<ClusterId, ClusterInfo>;
+
+/// Timeline of metadata changes for each cluster
+pub type EventsCache = ReplicatedMap<ClusterId, Vec<ClusterEvent>>;
+
+/// Broker level metrics, such as request latencies and JVM statistics
+pub type BrokerMetricsCache = ReplicatedMap<(ClusterId, BrokerId), BrokerMetrics>;
+
+/// Time series of the topic, broker and group metrics
+pub type MetricsHistoryCache = ReplicatedMap<(ClusterId, HistorySeries), MetricHistory>;
+
+/// Consumer group commit details (timestamps, metadata) per topic, alongside the offsets
+pub type CommitsCache =
+    ReplicatedMap<(ClusterId, String, TopicName), Vec<Option<OffsetCommit>>>;
+
+/// Group state and generation history, read from the group metadata records of __consumer_offsets
+pub type GroupStateCache = ReplicatedMap<(ClusterId, String), GroupState>;
+
+/// Consumer group lag per topic, with the watermarks of each partition
+pub type LagCache = ReplicatedMap<(ClusterId, String, TopicName), Vec<PartitionLag>>;
+
 pub struct Cache {
     pub metrics: MetricsCache,
     pub offsets: OffsetsCache,
@@ -601,6 +664,13 @@ pub struct Cache {
     pub topics: TopicCache,
     pub groups: GroupCache,
     pub internal_offsets: InternalConsumerOffsetCache,
+    pub events: EventsCache,
+    pub cluster_info: ClusterInfoCache,
+    pub broker_metrics: BrokerMetricsCache,
+    pub history: MetricsHistoryCache,
+    pub commits: CommitsCache,
+    pub group_states: GroupStateCache,
+    pub lag: LagCache,
 }
 
 impl Cache {
@@ -612,7 +682,14 @@ impl Cache {
             brokers: ReplicatedMap::new("brokers", replica_writer_arc.clone()),
             topics: ReplicatedMap::new("topics", replica_writer_arc.clone()),
             groups: ReplicatedMap::new("groups", replica_writer_arc.clone()),
-            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc),
+            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc.clone()),
+            events: ReplicatedMap::new("events", replica_writer_arc.clone()),
+            cluster_info: ReplicatedMap::new("cluster_info", replica_writer_arc.clone()),
+            broker_metrics: ReplicatedMap::new("broker_metrics", replica_writer_arc.clone()),
+            history: ReplicatedMap::new("history", replica_writer_arc.clone()),
+            commits: ReplicatedMap::new("commits", replica_writer_arc.clone()),
+            group_states: ReplicatedMap::new("group_states", replica_writer_arc.clone()),
+            lag: ReplicatedMap::new("lag", replica_writer_arc),
         }
     }
 
@@ -624,6 +701,13 @@ impl Cache {
             topics: self.topics.alias(),
             groups: self.groups.alias(),
             internal_offsets: self.internal_offsets.alias(),
+            events: self.events.alias(),
+            cluster_info: self.cluster_info.alias(),
+            broker_metrics: self.broker_metrics.alias(),
+            history: self.history.alias(),
+            commits: self.commits.alias(),
+            group_states: self.group_states.alias(),
+            lag: self.lag.alias(),
         }
     }
 }
@@ -653,6 +737,13 @@ impl UpdateReceiver for Cache {
             "topics" => self.topics.receive_update(update),
             "groups" => self.groups.receive_update(update),
             "internal_offsets" => self.internal_offsets.receive_update(update),
+            "events" => self.events.receive_update(update),
+            "cluster_info" => self.cluster_info.receive_update(update),
+            "broker_metrics" => self.broker_metrics.receive_update(update),
+            "history" => self.history.receive_update(update),
+            "commits" => self.commits.receive_update(update),
+            "group_states" => self.group_states.receive_update(update),
+            "lag" => self.lag.receive_update(update),
             _ => bail!("Unknown cache name: {}", cache_name),
         }
     }
@@ -2,11 +2,14 @@ use serde_yaml;
 
 use error::*;
 use metadata::ClusterId;
+use metrics_source::{HttpTimeouts, MetricsSource};
 use std::net::TcpListener;
 use std::io::Read;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::time::Duration;
 use unsafe_libyaml::{
     yaml_emitter_t,
     yaml_document_t,
@@ -20,23 +23,161 @@ fn default_true() -> bool {
     true
 }
 
+fn default_prometheus_path() -> String {
+    "/metrics".to_owned()
+}
+
+fn default_metrics_connect_timeout() -> u64 {
+    5
+}
+
+fn default_metrics_read_timeout() -> u64 {
+    30
+}
+
+fn default_metrics_threads() -> usize {
+    4
+}
+
+fn default_tasks_threads() -> usize {
+    8
+}
+
+fn default_lag_refresh() -> u64 {
+    60
+}
+
+fn default_offsets_refresh() -> u64 {
+    60
+}
+
+/// Where the committed offsets of the consumer groups of a cluster are read from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetsSource {
+    Topic, // consume `__consumer_offsets`
+    Api,   // periodically list the groups and fetch their offsets with OffsetFetch
+}
+
+impl Default for OffsetsSource {
+    fn default() -> OffsetsSource {
+        OffsetsSource::Topic
+    }
+}
+
+/// Basic authentication credentials, used to access the metrics agents and to protect the admin
+/// actions. The password is not shown when the configuration is logged.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BasicAuth {{ username: {:?}, password: <hidden> }}",
+            self.username
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClusterConfig {
     pub cluster_id: Option<ClusterId>, // This will always be available after load
     pub broker_list: Vec<String>,
     pub zookeeper: String,
     pub jolokia_port: Option<i32>,
+    #[serde(default)]
+    pub jolokia_https: bool,
+    pub jolokia_auth: Option<BasicAuth>,
+    pub prometheus_port: Option<i32>,
+    #[serde(default = "default_prometheus_path")]
+    pub prometheus_path: String,
+    pub fetch_log_dirs: Option<bool>,
     pub graph_url: Option<String>,
     #[serde(default = "default_true")]
     pub enable_tailing: bool,
     #[serde(default = "default_true")]
     pub show_zk_reassignments: bool,
+    #[serde(default)]
+    pub offsets_source: OffsetsSource,
 }
 
 impl ClusterConfig {
     pub fn bootstrap_servers(&self) -> String {
         self.broker_list.join(",")
     }
+
+    /// Returns where the broker metrics should be read from. The Prometheus JMX exporter is
+    /// preferred when both sources are configured.
+    pub fn metrics_source(&self) -> Option<MetricsSource> {
+        match self.prometheus_port {
+            Some(port) => Some(MetricsSource::Prometheus {
+                port,
+                path: self.prometheus_path.clone(),
+            }),
+            None => self.jolokia_port.map(|port| MetricsSource::Jolokia {
+                port,
+                https: self.jolokia_https,
+                auth: self.jolokia_auth.clone(),
+            }),
+        }
+    }
+
+    /// Returns true if partition sizes should be read with DescribeLogDirs. Unless configured,
+    /// this is only done for clusters without a metrics source.
+    pub fn log_dirs_enabled(&self) -> bool {
+        self.fetch_log_dirs.unwrap_or_else(|| self.metrics_source().is_none())
+    }
+}
+
+fn default_push_interval() -> u64 {
+    60
+}
+
+fn default_push_prefix() -> String {
+    "kafka_view".to_owned()
+}
+
+fn default_topic_template() -> String {
+    "{prefix}.{cluster}.topics.{topic}.{metric}".to_owned()
+}
+
+fn default_partition_template() -> String {
+    "{prefix}.{cluster}.topics.{topic}.partitions.{partition}.{metric}".to_owned()
+}
+
+fn default_group_template() -> String {
+    "{prefix}.{cluster}.groups.{group}.{topic}.{metric}".to_owned()
+}
+
+/// Protocol used to push the metrics.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PushProtocol {
+    Graphite, // plaintext protocol, over TCP
+    Statsd,   // gauges, over UDP
+}
+
+/// Periodic push of the collected metrics to Graphite or StatsD. Metric names are built from
+/// the templates, replacing `{prefix}`, `{cluster}`, `{topic}`, `{partition}`, `{group}` and
+/// `{metric}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsPushConfig {
+    pub protocol: PushProtocol,
+    pub address: String, // host:port
+    #[serde(default = "default_push_interval")]
+    pub interval: u64, // seconds
+    #[serde(default = "default_push_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_topic_template")]
+    pub topic_template: String,
+    #[serde(default = "default_partition_template")]
+    pub partition_template: String,
+    #[serde(default = "default_group_template")]
+    pub group_template: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,16 +192,37 @@ pub struct Config {
     pub listen_host: String,
     pub metadata_refresh: u64,
     pub metrics_refresh: u64,
+    #[serde(default = "default_metrics_connect_timeout")]
+    pub metrics_connect_timeout: u64, // seconds
+    #[serde(default = "default_metrics_read_timeout")]
+    pub metrics_read_timeout: u64, // seconds
+    #[serde(default = "default_metrics_threads")]
+    pub metrics_threads: usize,
+    #[serde(default = "default_tasks_threads")]
+    pub tasks_threads: usize,
+    #[serde(default = "default_lag_refresh")]
+    pub lag_refresh: u64,
+    #[serde(default = "default_offsets_refresh")]
+    pub offsets_refresh: u64, // only used by the clusters with the api offsets source
     pub offsets_store_duration: u64,
     pub consumer_offsets_group_id: String,
     pub clusters: HashMap<ClusterId, ClusterConfig>,
     pub caching: CachingConfig,
+    pub metrics_push: Option<MetricsPushConfig>,
+    pub admin_auth: Option<BasicAuth>, // admin actions are disabled if not set
 }
 
 impl Config {
     pub fn cluster(&self, cluster_id: &ClusterId) -> Option<&ClusterConfig> {
         self.clusters.get(cluster_id)
     }
+
+    pub fn metrics_timeouts(&self) -> HttpTimeouts {
+        HttpTimeouts {
+            connect: Duration::from_secs(self.metrics_connect_timeout),
+            read: Duration::from_secs(self.metrics_read_timeout),
+        }
+    }
 }
 
 pub fn read_config(path: &str) -> Result<Config> {
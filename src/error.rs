@@ -8,3 +8,13 @@ error_chain! {
         }
     }
 }
+
+impl Error {
+    /// The error followed by all its causes, on a single line.
+    pub fn chain_message(&self) -> String {
+        self.iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(": ")
+    }
+}
@@ -0,0 +1,222 @@
+use rdkafka::util::millis_to_epoch;
+
+use metadata::{Broker, BrokerId, Partition, TopicName};
+
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/// Maximum number of events stored for each cluster. Older events are dropped first.
+pub const MAX_EVENTS_PER_CLUSTER: usize = 2000;
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub enum EventKind {
+    TopicCreated { partitions: usize },
+    TopicDeleted,
+    PartitionsAdded { from: usize, to: usize },
+    IsrShrink { partition: i32, removed: Vec<BrokerId> },
+    IsrExpand { partition: i32, added: Vec<BrokerId> },
+    LeaderChange { partition: i32, from: BrokerId, to: BrokerId },
+    BrokerJoined { broker: BrokerId },
+    BrokerLeft { broker: BrokerId },
+    ControllerChange { from: Option<BrokerId>, to: Option<BrokerId> },
+    GroupDeleted { group: String },
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EventKind::TopicCreated { .. } => "Topic created",
+            EventKind::TopicDeleted => "Topic deleted",
+            EventKind::PartitionsAdded { .. } => "Partitions added",
+            EventKind::IsrShrink { .. } => "ISR shrink",
+            EventKind::IsrExpand { .. } => "ISR expand",
+            EventKind::LeaderChange { .. } => "Leader change",
+            EventKind::BrokerJoined { .. } => "Broker joined",
+            EventKind::BrokerLeft { .. } => "Broker left",
+            EventKind::ControllerChange { .. } => "Controller change",
+            EventKind::GroupDeleted { .. } => "Group deleted",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match *self {
+            EventKind::TopicCreated { partitions } => format!("{} partitions", partitions),
+            EventKind::TopicDeleted => String::new(),
+            EventKind::PartitionsAdded { from, to } => format!("{} -> {} partitions", from, to),
+            EventKind::IsrShrink {
+                partition,
+                ref removed,
+            } => format!("partition {}: removed {:?}", partition, removed),
+            EventKind::IsrExpand {
+                partition,
+                ref added,
+            } => format!("partition {}: added {:?}", partition, added),
+            EventKind::LeaderChange {
+                partition,
+                from,
+                to,
+            } => format!("partition {}: {} -> {}", partition, from, to),
+            EventKind::BrokerJoined { broker } | EventKind::BrokerLeft { broker } => {
+                format!("broker {}", broker)
+            }
+            EventKind::ControllerChange { from, to } => format!(
+                "{} -> {}",
+                from.map(|id| id.to_string()).unwrap_or_else(|| "none".to_owned()),
+                to.map(|id| id.to_string()).unwrap_or_else(|| "none".to_owned())
+            ),
+            EventKind::GroupDeleted { ref group } => format!("group {}", group),
+        }
+    }
+
+    /// Returns the partition the event refers to, if any.
+    pub fn partition(&self) -> Option<i32> {
+        match *self {
+            EventKind::IsrShrink { partition, .. }
+            | EventKind::IsrExpand { partition, .. }
+            | EventKind::LeaderChange { partition, .. } => Some(partition),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct ClusterEvent {
+    pub timestamp: u64, // millis since epoch
+    pub topic: Option<TopicName>,
+    pub kind: EventKind,
+}
+
+impl ClusterEvent {
+    fn new(topic: Option<&str>, kind: EventKind) -> ClusterEvent {
+        ClusterEvent {
+            timestamp: millis_to_epoch(SystemTime::now()) as u64,
+            topic: topic.map(str::to_owned),
+            kind,
+        }
+    }
+}
+
+/// Compares two successive broker lists and returns the broker membership changes.
+pub fn diff_brokers(old: &[Broker], new: &[Broker]) -> Vec<ClusterEvent> {
+    let old_ids = old.iter().map(|b| b.id).collect::<HashSet<_>>();
+    let new_ids = new.iter().map(|b| b.id).collect::<HashSet<_>>();
+
+    let mut events = Vec::new();
+    let mut joined = new_ids.difference(&old_ids).cloned().collect::<Vec<_>>();
+    joined.sort();
+    for broker in joined {
+        events.push(ClusterEvent::new(None, EventKind::BrokerJoined { broker }));
+    }
+    let mut left = old_ids.difference(&new_ids).cloned().collect::<Vec<_>>();
+    left.sort();
+    for broker in left {
+        events.push(ClusterEvent::new(None, EventKind::BrokerLeft { broker }));
+    }
+    events
+}
+
+/// Compares two successive snapshots of the partitions of a topic. A missing old snapshot
+/// means that the topic has just been created.
+pub fn diff_topic(topic: &str, old: Option<&[Partition]>, new: &[Partition]) -> Vec<ClusterEvent> {
+    let old = match old {
+        Some(old) => old,
+        None => {
+            return vec![ClusterEvent::new(
+                Some(topic),
+                EventKind::TopicCreated {
+                    partitions: new.len(),
+                },
+            )]
+        }
+    };
+
+    let mut events = Vec::new();
+    if new.len() > old.len() {
+        events.push(ClusterEvent::new(
+            Some(topic),
+            EventKind::PartitionsAdded {
+                from: old.len(),
+                to: new.len(),
+            },
+        ));
+    }
+
+    let old_by_id = old.iter().map(|p| (p.id, p)).collect::<HashMap<_, _>>();
+    for new_p in new {
+        let old_p = match old_by_id.get(&new_p.id) {
+            Some(p) => p,
+            None => continue,
+        };
+        if old_p.leader != new_p.leader {
+            events.push(ClusterEvent::new(
+                Some(topic),
+                EventKind::LeaderChange {
+                    partition: new_p.id,
+                    from: old_p.leader,
+                    to: new_p.leader,
+                },
+            ));
+        }
+        let removed = old_p
+            .isr
+            .iter()
+            .filter(|b| !new_p.isr.contains(b))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !removed.is_empty() {
+            events.push(ClusterEvent::new(
+                Some(topic),
+                EventKind::IsrShrink {
+                    partition: new_p.id,
+                    removed,
+                },
+            ));
+        }
+        let added = new_p
+            .isr
+            .iter()
+            .filter(|b| !old_p.isr.contains(b))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !added.is_empty() {
+            events.push(ClusterEvent::new(
+                Some(topic),
+                EventKind::IsrExpand {
+                    partition: new_p.id,
+                    added,
+                },
+            ));
+        }
+    }
+    events
+}
+
+/// Returns the event generated by the deletion of a topic.
+pub fn topic_deleted(topic: &str) -> ClusterEvent {
+    ClusterEvent::new(Some(topic), EventKind::TopicDeleted)
+}
+
+/// Returns the event generated by the election of a new controller.
+pub fn controller_changed(from: Option<BrokerId>, to: Option<BrokerId>) -> ClusterEvent {
+    ClusterEvent::new(None, EventKind::ControllerChange { from, to })
+}
+
+/// Returns the event generated by the deletion of a consumer group.
+pub fn group_deleted(group: &str) -> ClusterEvent {
+    ClusterEvent::new(
+        None,
+        EventKind::GroupDeleted {
+            group: group.to_owned(),
+        },
+    )
+}
+
+/// Appends the new events to the existing log, dropping the oldest ones if the log grows
+/// beyond `MAX_EVENTS_PER_CLUSTER`.
+pub fn append_events(log: &mut Vec<ClusterEvent>, mut new_events: Vec<ClusterEvent>) {
+    log.append(&mut new_events);
+    if log.len() > MAX_EVENTS_PER_CLUSTER {
+        let excess = log.len() - MAX_EVENTS_PER_CLUSTER;
+        log.drain(..excess);
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::Utc;
+
+use cache::Cache;
+use config::{MetricsPushConfig, PushProtocol};
+use error::*;
+use lag::LagStore;
+use metadata::ClusterId;
+use task_stats::TASK_STATS;
+
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Maximum size of a StatsD packet. Larger packets might be fragmented.
+const STATSD_PACKET_SIZE: usize = 512;
+
+/// Replaces the characters that have a special meaning in Graphite or StatsD metric names.
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The values that can be used in the metric name templates.
+#[derive(Default)]
+struct NameParams<'a> {
+    cluster: &'a str,
+    topic: &'a str,
+    partition: Option<i32>,
+    group: &'a str,
+}
+
+/// Pushes the topic rates, partition sizes and consumer lag stored in the cache to Graphite or
+/// StatsD.
+pub struct MetricsPusher {
+    cache: Cache,
+    config: MetricsPushConfig,
+}
+
+impl MetricsPusher {
+    pub fn new(cache: &Cache, config: &MetricsPushConfig) -> MetricsPusher {
+        MetricsPusher {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn metric_name(&self, template: &str, params: &NameParams, metric: &str) -> String {
+        template
+            .replace("{prefix}", &self.config.prefix)
+            .replace("{cluster}", &sanitize(params.cluster))
+            .replace("{topic}", &sanitize(params.topic))
+            .replace(
+                "{partition}",
+                &params.partition.map(|p| p.to_string()).unwrap_or_default(),
+            )
+            .replace("{group}", &sanitize(params.group))
+            .replace("{metric}", metric)
+    }
+
+    fn topic_samples(&self, cluster_id: &ClusterId, samples: &mut Vec<(String, f64)>) {
+        let template = &self.config.topic_template;
+        let partition_template = &self.config.partition_template;
+        let metrics = self
+            .cache
+            .metrics
+            .filter_clone(|&(ref c, ref topic)| c == cluster_id && topic != "__TOTAL__");
+        for ((_, topic), topic_metrics) in metrics {
+            let params = NameParams {
+                cluster: cluster_id.name(),
+                topic: &topic,
+                ..Default::default()
+            };
+            let aggregated = topic_metrics.aggregate_broker_metrics();
+            let rates = [
+                ("bytes_in.m1_rate", aggregated.b_rate_1),
+                ("bytes_in.m15_rate", aggregated.b_rate_15),
+                ("messages_in.m1_rate", aggregated.m_rate_1),
+                ("messages_in.m15_rate", aggregated.m_rate_15),
+            ];
+            for &(metric, value) in &rates {
+                if value >= 0f64 {
+                    samples.push((self.metric_name(template, &params, metric), value));
+                }
+            }
+
+            let partitions = self
+                .cache
+                .topics
+                .get(&(cluster_id.clone(), topic.clone()))
+                .unwrap_or_default();
+            for p in partitions {
+                let size = topic_metrics
+                    .brokers
+                    .get(&p.leader)
+                    .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                    .map(|partition_metrics| partition_metrics.size_bytes);
+                if let Some(size) = size {
+                    let params = NameParams {
+                        partition: Some(p.id),
+                        ..params
+                    };
+                    samples.push((self.metric_name(partition_template, &params, "size"), size));
+                }
+            }
+        }
+    }
+
+    fn group_samples(&self, cluster_id: &ClusterId, samples: &mut Vec<(String, f64)>) {
+        for ((group, topic), lag) in self.cache.group_topic_lags(cluster_id) {
+            let params = NameParams {
+                cluster: cluster_id.name(),
+                topic: &topic,
+                group: &group,
+                ..Default::default()
+            };
+            let name = self.metric_name(&self.config.group_template, &params, "lag");
+            samples.push((name, lag as f64));
+        }
+    }
+
+    fn send_graphite(&self, samples: &[(String, f64)]) -> Result<()> {
+        let address = self
+            .config
+            .address
+            .to_socket_addrs()
+            .chain_err(|| format!("Failed to resolve {}", self.config.address))?
+            .next()
+            .ok_or_else(|| Error::from(format!("No address found for {}", self.config.address)))?;
+        let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(10))
+            .chain_err(|| format!("Failed to connect to {}", self.config.address))?;
+        stream
+            .set_write_timeout(Some(Duration::from_secs(10)))
+            .chain_err(|| "Failed to set socket timeout")?;
+
+        let timestamp = Utc::now().timestamp();
+        let mut payload = String::new();
+        for &(ref name, value) in samples {
+            payload.push_str(&format!("{} {} {}\n", name, value, timestamp));
+        }
+        stream
+            .write_all(payload.as_bytes())
+            .chain_err(|| "Failed to send metrics to Graphite")
+    }
+
+    fn send_statsd(&self, samples: &[(String, f64)]) -> Result<()> {
+        let socket = UdpSocket::bind("0.0.0.0:0").chain_err(|| "Failed to create UDP socket")?;
+        socket
+            .connect(&self.config.address)
+            .chain_err(|| format!("Failed to resolve {}", self.config.address))?;
+
+        let mut packet = String::new();
+        for &(ref name, value) in samples {
+            let line = format!("{}:{}|g", name, value);
+            if !packet.is_empty() && packet.len() + line.len() + 1 > STATSD_PACKET_SIZE {
+                socket
+                    .send(packet.as_bytes())
+                    .chain_err(|| "Failed to send metrics to StatsD")?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            socket
+                .send(packet.as_bytes())
+                .chain_err(|| "Failed to send metrics to StatsD")?;
+        }
+        Ok(())
+    }
+
+    fn push_metrics(&self) -> Result<()> {
+        let mut samples = Vec::new();
+        for cluster_id in self.cache.brokers.keys() {
+            self.topic_samples(&cluster_id, &mut samples);
+            self.group_samples(&cluster_id, &mut samples);
+        }
+        debug!(
+            "Pushing {} metrics to {}",
+            samples.len(),
+            self.config.address
+        );
+        match self.config.protocol {
+            PushProtocol::Graphite => self.send_graphite(&samples),
+            PushProtocol::Statsd => self.send_statsd(&samples),
+        }
+    }
+
+    pub fn push(&self) {
+        let start = Instant::now();
+        let result = self.push_metrics();
+        TASK_STATS.record(
+            "metrics_push",
+            &self.config.address,
+            start.elapsed(),
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use byteorder::{BigEndian, ReadBytesExt};
+
+use error::*;
+use metadata::MemberAssignment;
+use utils::{hex_dump, is_exhausted, read_bytes, read_nullable_string, read_string};
+
+use std::io::Cursor;
+
+//
+// ********** CONSUMER PROTOCOL **********
+//
+
+/// Member metadata sent by a consumer when joining the group.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConsumerSubscription {
+    pub topics: Vec<String>,
+    pub user_data: Option<Vec<u8>>,
+    pub owned_partitions: Vec<MemberAssignment>,
+}
+
+/// Reads an array of topics, each one followed by an array of partitions.
+pub fn parse_topic_partitions(rdr: &mut Cursor<&[u8]>) -> Result<Vec<MemberAssignment>> {
+    let assign_len = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse assigment length")?;
+    // Lengths are read from the wire: don't preallocate with them
+    let mut assigns = Vec::new();
+    for _ in 0..assign_len {
+        let topic = read_string(rdr).chain_err(|| "Failed to parse assignment topic name")?;
+        let partition_len = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse assignment partition length")?;
+        let mut partitions = Vec::new();
+        for _ in 0..partition_len {
+            let partition = rdr
+                .read_i32::<BigEndian>()
+                .chain_err(|| "Failed to parse assignment partition")?;
+            partitions.push(partition);
+        }
+        assigns.push(MemberAssignment { topic, partitions })
+    }
+    Ok(assigns)
+}
+
+pub fn parse_consumer_subscription(rdr: &mut Cursor<&[u8]>) -> Result<ConsumerSubscription> {
+    let version = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse subscription version")?;
+    let topics_len = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse subscription topics length")?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_len {
+        topics.push(read_string(rdr).chain_err(|| "Failed to parse subscription topic")?);
+    }
+    // Old clients might not send the user data at all
+    let user_data = if is_exhausted(rdr) {
+        None
+    } else {
+        read_bytes(rdr).chain_err(|| "Failed to parse subscription user data")?
+    };
+    let owned_partitions = if version >= 1 && !is_exhausted(rdr) {
+        parse_topic_partitions(rdr).chain_err(|| "Failed to parse owned partitions")?
+    } else {
+        Vec::new()
+    };
+    Ok(ConsumerSubscription {
+        topics,
+        user_data,
+        owned_partitions,
+    })
+}
+
+//
+// ********** USER DATA **********
+//
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum UserDataKind {
+    Subscription,
+    Assignment,
+}
+
+/// Returns a human readable representation of the user data attached to the subscription or
+/// assignment of a group member. Known formats are decoded, anything else is shown as hex.
+pub fn describe_user_data(protocol: &str, kind: UserDataKind, data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    let decoded = match (protocol, kind) {
+        ("sticky", _) => decode_sticky_user_data(&mut Cursor::new(data)),
+        ("stream", UserDataKind::Subscription) => {
+            decode_streams_subscription(&mut Cursor::new(data))
+        }
+        ("stream", UserDataKind::Assignment) => decode_streams_assignment(&mut Cursor::new(data)),
+        _ => return Some(format!("hex: {}", hex_dump(data))),
+    };
+    match decoded {
+        Ok(description) => Some(description),
+        Err(_) => Some(format!("hex: {}", hex_dump(data))),
+    }
+}
+
+pub fn format_topic_partitions(assignments: &[MemberAssignment]) -> String {
+    assignments
+        .iter()
+        .map(|assign| {
+            format!(
+                "{}/{}",
+                assign.topic,
+                assign
+                    .partitions
+                    .iter()
+                    .map(i32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The sticky assignor stores the previous assignment of the member, optionally followed by
+/// the generation it refers to.
+fn decode_sticky_user_data(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let previous = parse_topic_partitions(rdr)?;
+    let mut description = format!("previous assignment: {}", format_topic_partitions(&previous));
+    if !is_exhausted(rdr) {
+        let generation = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse generation")?;
+        description.push_str(&format!(" (generation {})", generation));
+    }
+    Ok(description)
+}
+
+/// Reads a Kafka Streams task id, formatted as `<topic group>_<partition>`.
+fn read_task_id(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let topic_group = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse task topic group")?;
+    let partition = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse task partition")?;
+    Ok(format!("{}_{}", topic_group, partition))
+}
+
+fn read_task_ids(rdr: &mut Cursor<&[u8]>) -> Result<Vec<String>> {
+    let count = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse task count")?;
+    let mut tasks = Vec::new();
+    for _ in 0..count {
+        tasks.push(read_task_id(rdr)?);
+    }
+    Ok(tasks)
+}
+
+fn read_uuid(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let most = rdr
+        .read_u64::<BigEndian>()
+        .chain_err(|| "Failed to parse UUID")?;
+    let least = rdr
+        .read_u64::<BigEndian>()
+        .chain_err(|| "Failed to parse UUID")?;
+    Ok(format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        most >> 32,
+        (most >> 16) & 0xffff,
+        most & 0xffff,
+        least >> 48,
+        least & 0xffff_ffff_ffff
+    ))
+}
+
+/// Decodes the subscription info of Kafka Streams clients (versions 1 to 4).
+fn decode_streams_subscription(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let version = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse version")?;
+    if version < 1 || version > 4 {
+        bail!("Unsupported streams subscription version {}", version);
+    }
+    if version >= 3 {
+        let _latest_supported_version = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse latest supported version")?;
+    }
+    let process_id = read_uuid(rdr)?;
+    let previous_tasks = read_task_ids(rdr)?;
+    let standby_tasks = read_task_ids(rdr)?;
+    let mut description = format!(
+        "streams v{}, process: {}, previous tasks: [{}], standby tasks: [{}]",
+        version,
+        process_id,
+        previous_tasks.join(","),
+        standby_tasks.join(",")
+    );
+    if version >= 2 {
+        if let Some(endpoint) = read_nullable_string(rdr)? {
+            description.push_str(&format!(", endpoint: {}", endpoint));
+        }
+    }
+    Ok(description)
+}
+
+/// Decodes the active and standby tasks from the assignment info of Kafka Streams clients.
+fn decode_streams_assignment(rdr: &mut Cursor<&[u8]>) -> Result<String> {
+    let version = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse version")?;
+    if version < 1 || version > 4 {
+        bail!("Unsupported streams assignment version {}", version);
+    }
+    if version >= 3 {
+        let _latest_supported_version = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse latest supported version")?;
+    }
+    let active_tasks = read_task_ids(rdr)?;
+    let standby_count = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse standby task count")?;
+    let mut standby_tasks = Vec::new();
+    for _ in 0..standby_count {
+        let task = read_task_id(rdr)?;
+        let partitions_count = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse standby partition count")?;
+        for _ in 0..partitions_count {
+            let _topic = read_string(rdr)?;
+            let _partition = rdr
+                .read_i32::<BigEndian>()
+                .chain_err(|| "Failed to parse standby partition")?;
+        }
+        standby_tasks.push(task);
+    }
+    Ok(format!(
+        "streams v{}, active tasks: [{}], standby tasks: [{}]",
+        version,
+        active_tasks.join(","),
+        standby_tasks.join(",")
+    ))
+}
+
+
+//
+// ********** CONNECT PROTOCOL **********
+//
+
+/// Id used by Kafka Connect to identify the connector instance, as opposed to one of its tasks.
+const CONNECTOR_TASK: i32 = -1;
+
+/// Worker metadata sent by a Kafka Connect worker when joining the group.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConnectWorkerMetadata {
+    pub url: String,
+}
+
+/// Assignment computed by the leader of a Kafka Connect group for a specific worker.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct ConnectAssignment {
+    pub version: i16,
+    pub error: i16,
+    pub leader: String,
+    pub leader_url: String,
+    pub config_offset: i64,
+    pub connectors: Vec<String>,
+    pub tasks: Vec<String>,
+    pub revoked_connectors: Vec<String>,
+    pub revoked_tasks: Vec<String>,
+    pub delay_ms: i32,
+}
+
+pub fn parse_connect_worker_metadata(rdr: &mut Cursor<&[u8]>) -> Result<ConnectWorkerMetadata> {
+    let _version = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse worker metadata version")?;
+    let url = read_string(rdr).chain_err(|| "Failed to parse worker url")?;
+    // The config offset is also part of the assignment, where it's displayed
+    let _config_offset = rdr
+        .read_i64::<BigEndian>()
+        .chain_err(|| "Failed to parse worker config offset")?;
+    Ok(ConnectWorkerMetadata { url })
+}
+
+/// Reads an array of connectors, each one with the list of its task ids. Returns the names of
+/// the connectors and the ids of the tasks, formatted as `<connector>-<task id>`.
+fn parse_connector_tasks(rdr: &mut Cursor<&[u8]>) -> Result<(Vec<String>, Vec<String>)> {
+    let count = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse connector count")?;
+    let mut connectors = Vec::new();
+    let mut tasks = Vec::new();
+    for _ in 0..count {
+        let connector = read_string(rdr).chain_err(|| "Failed to parse connector name")?;
+        let task_count = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse task count")?;
+        for _ in 0..task_count {
+            let task = rdr
+                .read_i32::<BigEndian>()
+                .chain_err(|| "Failed to parse task id")?;
+            if task == CONNECTOR_TASK {
+                connectors.push(connector.clone());
+            } else {
+                tasks.push(format!("{}-{}", connector, task));
+            }
+        }
+    }
+    Ok((connectors, tasks))
+}
+
+pub fn parse_connect_assignment(rdr: &mut Cursor<&[u8]>) -> Result<ConnectAssignment> {
+    let version = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse assignment version")?;
+    let error = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse assignment error")?;
+    let leader = read_string(rdr).chain_err(|| "Failed to parse leader")?;
+    let leader_url = read_string(rdr).chain_err(|| "Failed to parse leader url")?;
+    let config_offset = rdr
+        .read_i64::<BigEndian>()
+        .chain_err(|| "Failed to parse config offset")?;
+    let (connectors, tasks) =
+        parse_connector_tasks(rdr).chain_err(|| "Failed to parse assigned connectors")?;
+    // Incremental cooperative rebalancing (version 1 and later) adds revocations and delay
+    let (revoked_connectors, revoked_tasks, delay_ms) = if version >= 1 && !is_exhausted(rdr) {
+        let (revoked_connectors, revoked_tasks) =
+            parse_connector_tasks(rdr).chain_err(|| "Failed to parse revoked connectors")?;
+        let delay_ms = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse scheduled delay")?;
+        (revoked_connectors, revoked_tasks, delay_ms)
+    } else {
+        (Vec::new(), Vec::new(), 0)
+    };
+    Ok(ConnectAssignment {
+        version,
+        error,
+        leader,
+        leader_url,
+        config_offset,
+        connectors,
+        tasks,
+        revoked_connectors,
+        revoked_tasks,
+        delay_ms,
+    })
+}
@@ -0,0 +1,314 @@
+use chrono::Utc;
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use error::*;
+use lag::LagStore;
+use metadata::{BrokerId, ClusterId, TopicName};
+use task_stats::TASK_STATS;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+
+/// Points newer than this are kept at refresh resolution, in seconds.
+const RECENT_RETENTION: i64 = 24 * 3600;
+/// Older points are averaged in buckets of this size, in seconds.
+const BUCKET_SIZE: i64 = 3600;
+/// Buckets older than this are dropped, in seconds.
+pub const HISTORY_RETENTION: u64 = 30 * 24 * 3600;
+/// How often each history is written to the replicated cache, in seconds.
+const PERSIST_INTERVAL: i64 = BUCKET_SIZE;
+
+lazy_static! {
+    pub static ref LOCAL_HISTORY: LocalHistory = LocalHistory::new();
+}
+
+/// A time series kept by kafka-view. Rates are the one minute rates, sizes are the sum of the
+/// sizes of the partitions (on their leader for topics, hosted by the broker for brokers).
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+pub enum HistorySeries {
+    TopicByteRate(TopicName),
+    TopicMsgRate(TopicName),
+    TopicSize(TopicName),
+    BrokerByteRate(BrokerId),
+    BrokerMsgRate(BrokerId),
+    BrokerSize(BrokerId),
+    BrokerMetric(BrokerId, String), // name as listed by `BrokerMetrics::values`
+    GroupLag(String),
+}
+
+/// An average of the points received in a bucket of the downsampled history.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Bucket {
+    pub timestamp: i64, // start of the bucket
+    pub value: f64,
+    pub samples: u32,
+}
+
+/// The history of a time series: recent points at refresh resolution, followed by older points
+/// downsampled to one per bucket.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct MetricHistory {
+    pub recent: Vec<(i64, f64)>, // (unix timestamp, value)
+    pub buckets: Vec<Bucket>,
+}
+
+impl MetricHistory {
+    pub fn add(&mut self, timestamp: i64, value: f64) {
+        self.recent.push((timestamp, value));
+
+        let cutoff = timestamp - RECENT_RETENTION;
+        let expired = self.recent.iter().take_while(|&&(t, _)| t < cutoff).count();
+        for (t, v) in self.recent.drain(..expired).collect::<Vec<_>>() {
+            let bucket_start = t - t % BUCKET_SIZE;
+            if let Some(bucket) = self.buckets.last_mut() {
+                if bucket.timestamp == bucket_start {
+                    bucket.samples += 1;
+                    bucket.value += (v - bucket.value) / f64::from(bucket.samples);
+                    continue;
+                }
+            }
+            self.buckets.push(Bucket {
+                timestamp: bucket_start,
+                value: v,
+                samples: 1,
+            });
+        }
+
+        let oldest = timestamp - HISTORY_RETENTION as i64;
+        self.buckets.retain(|bucket| bucket.timestamp >= oldest);
+    }
+
+    /// All the points of the history, oldest first.
+    pub fn points(&self) -> Vec<(i64, f64)> {
+        self.buckets
+            .iter()
+            .map(|bucket| (bucket.timestamp, bucket.value))
+            .chain(self.recent.iter().cloned())
+            .collect()
+    }
+}
+
+type HistoryKey = (ClusterId, HistorySeries);
+
+/// The histories updated by this instance, with the time they were last written to the
+/// replicated cache. A whole history is too large to be replicated at every refresh: charts are
+/// drawn from here, and the cache only receives a copy every `PERSIST_INTERVAL`.
+pub struct LocalHistory {
+    series: RwLock<HashMap<HistoryKey, (MetricHistory, i64)>>,
+}
+
+impl LocalHistory {
+    pub fn new() -> LocalHistory {
+        LocalHistory {
+            series: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a point to the history, loading it with `load` if it's not known yet. Returns a copy
+    /// of the history when it's due to be written to the cache.
+    fn add<F>(&self, key: &HistoryKey, timestamp: i64, value: f64, load: F) -> Option<MetricHistory>
+    where
+        F: FnOnce() -> Option<MetricHistory>,
+    {
+        match self.series.write() {
+            Ok(mut series) => {
+                let entry = series
+                    .entry(key.clone())
+                    .or_insert_with(|| (load().unwrap_or_default(), timestamp));
+                entry.0.add(timestamp, value);
+                if timestamp - entry.1 >= PERSIST_INTERVAL {
+                    entry.1 = timestamp;
+                    Some(entry.0.clone())
+                } else {
+                    None
+                }
+            }
+            Err(_) => panic!("Poison error while writing local history"),
+        }
+    }
+
+    pub fn points(&self, key: &HistoryKey) -> Option<Vec<(i64, f64)>> {
+        match self.series.read() {
+            Ok(series) => series.get(key).map(|&(ref history, _)| history.points()),
+            Err(_) => panic!("Poison error while reading local history"),
+        }
+    }
+}
+
+/// Periodically appends the current metrics of each cluster to their history.
+pub struct HistoryTaskGroup {
+    cache: Cache,
+}
+
+impl HistoryTaskGroup {
+    pub fn new(cache: &Cache) -> HistoryTaskGroup {
+        HistoryTaskGroup {
+            cache: cache.alias(),
+        }
+    }
+
+    fn add_point(
+        &self,
+        cluster_id: &ClusterId,
+        series: HistorySeries,
+        timestamp: i64,
+        value: f64,
+    ) -> Result<()> {
+        if value < 0f64 {
+            return Ok(()); // unknown
+        }
+        let key = (cluster_id.clone(), series);
+        let cache = &self.cache;
+        match LOCAL_HISTORY.add(&key, timestamp, value, || cache.history.get(&key)) {
+            Some(history) => cache
+                .history
+                .update(key, |_| Some(history))
+                .chain_err(|| "Failed to insert to history"),
+            None => Ok(()),
+        }
+    }
+
+    fn topic_and_broker_points(&self, cluster_id: &ClusterId, timestamp: i64) -> Result<()> {
+        let metrics = self
+            .cache
+            .metrics
+            .filter_clone(|&(ref c, _)| c == cluster_id);
+        let mut broker_sizes: HashMap<BrokerId, f64> = HashMap::new();
+        for ((_, topic), topic_metrics) in metrics {
+            if topic == "__TOTAL__" {
+                for (broker_id, broker_metrics) in &topic_metrics.brokers {
+                    self.add_point(
+                        cluster_id,
+                        HistorySeries::BrokerByteRate(*broker_id),
+                        timestamp,
+                        broker_metrics.b_rate_1,
+                    )?;
+                    self.add_point(
+                        cluster_id,
+                        HistorySeries::BrokerMsgRate(*broker_id),
+                        timestamp,
+                        broker_metrics.m_rate_1,
+                    )?;
+                }
+                continue;
+            }
+            for (broker_id, broker_metrics) in &topic_metrics.brokers {
+                let size: f64 = broker_metrics.partitions.iter().map(|p| p.size_bytes).sum();
+                *broker_sizes.entry(*broker_id).or_insert(0f64) += size;
+            }
+            let partitions = self
+                .cache
+                .topics
+                .get(&(cluster_id.clone(), topic.clone()))
+                .unwrap_or_default();
+            let topic_size: f64 = partitions
+                .iter()
+                .filter_map(|p| {
+                    topic_metrics
+                        .brokers
+                        .get(&p.leader)
+                        .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                        .map(|partition_metrics| partition_metrics.size_bytes)
+                })
+                .sum();
+            let aggregated = topic_metrics.aggregate_broker_metrics();
+            self.add_point(
+                cluster_id,
+                HistorySeries::TopicByteRate(topic.clone()),
+                timestamp,
+                aggregated.b_rate_1,
+            )?;
+            self.add_point(
+                cluster_id,
+                HistorySeries::TopicMsgRate(topic.clone()),
+                timestamp,
+                aggregated.m_rate_1,
+            )?;
+            if !partitions.is_empty() {
+                self.add_point(
+                    cluster_id,
+                    HistorySeries::TopicSize(topic),
+                    timestamp,
+                    topic_size,
+                )?;
+            }
+        }
+        for (broker_id, size) in broker_sizes {
+            self.add_point(
+                cluster_id,
+                HistorySeries::BrokerSize(broker_id),
+                timestamp,
+                size,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn broker_metric_points(&self, cluster_id: &ClusterId, timestamp: i64) -> Result<()> {
+        let broker_metrics = self
+            .cache
+            .broker_metrics
+            .filter_clone(|&(ref c, _)| c == cluster_id);
+        for ((_, broker_id), metrics) in broker_metrics {
+            for (name, value) in metrics.values() {
+                if let Some(value) = value {
+                    self.add_point(
+                        cluster_id,
+                        HistorySeries::BrokerMetric(broker_id, name.to_owned()),
+                        timestamp,
+                        value,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn group_points(&self, cluster_id: &ClusterId, timestamp: i64) -> Result<()> {
+        let mut group_lags: HashMap<String, i64> = HashMap::new();
+        for ((group, _), lag) in self.cache.group_topic_lags(cluster_id) {
+            *group_lags.entry(group).or_insert(0) += lag;
+        }
+        for (group, lag) in group_lags {
+            self.add_point(
+                cluster_id,
+                HistorySeries::GroupLag(group),
+                timestamp,
+                lag as f64,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn update_history(&self, cluster_id: &ClusterId) -> Result<()> {
+        let timestamp = Utc::now().timestamp();
+        self.topic_and_broker_points(cluster_id, timestamp)?;
+        self.broker_metric_points(cluster_id, timestamp)?;
+        self.group_points(cluster_id, timestamp)
+    }
+}
+
+impl TaskGroup for HistoryTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.cache.brokers.keys()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Starting history update for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.update_history(&cluster_id);
+        TASK_STATS.record(
+            "history",
+            cluster_id.name(),
+            start.elapsed(),
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use error::*;
+use metadata::TopicName;
+use utils::{read_nullable_string, read_string};
+
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const LIST_OFFSETS_KEY: i16 = 2;
+const OFFSET_FETCH_KEY: i16 = 9;
+const FIND_COORDINATOR_KEY: i16 = 10;
+const DESCRIBE_LOG_DIRS_KEY: i16 = 35;
+const CLIENT_ID: &str = "kafka-view";
+/// Larger responses are considered corrupted, as the size is read from the wire.
+const MAX_RESPONSE_SIZE: i32 = 100 * 1024 * 1024;
+
+/// A partition replica stored in a log dir, as returned by DescribeLogDirs.
+#[derive(Debug, Clone)]
+pub struct ReplicaLogInfo {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub size: i64,
+    pub offset_lag: i64,
+    pub is_future: bool,
+}
+
+/// A log dir of a broker, as returned by DescribeLogDirs.
+#[derive(Debug, Clone)]
+pub struct LogDirDescription {
+    pub error_code: i16,
+    pub log_dir: String,
+    pub replicas: Vec<ReplicaLogInfo>,
+}
+
+/// The offset of a partition, as returned by ListOffsets.
+#[derive(Debug, Clone)]
+pub struct PartitionOffset {
+    pub partition: i32,
+    pub error_code: i16,
+    pub offset: i64, // -1 if there's no message after the requested timestamp
+}
+
+/// The broker coordinating a consumer group, as returned by FindCoordinator.
+#[derive(Debug, Clone)]
+pub struct Coordinator {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+}
+
+/// The committed offset of a group on a partition, as returned by OffsetFetch.
+#[derive(Debug, Clone)]
+pub struct CommittedOffset {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub offset: i64, // -1 if there's no committed offset
+    pub error_code: i16,
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.len() > i16::MAX as usize {
+        bail!("String too long: {} bytes", s.len());
+    }
+    buf.write_i16::<BigEndian>(s.len() as i16)
+        .chain_err(|| "Failed to write string len")?;
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// A blocking connection to a single broker, used for the requests that rdkafka doesn't expose.
+pub struct BrokerConnection {
+    stream: TcpStream,
+    correlation_id: i32,
+}
+
+impl BrokerConnection {
+    pub fn connect(hostname: &str, port: i32, timeout: Duration) -> Result<BrokerConnection> {
+        let address = (hostname, port as u16)
+            .to_socket_addrs()
+            .chain_err(|| format!("Failed to resolve {}", hostname))?
+            .next()
+            .ok_or_else(|| Error::from(format!("No address found for {}", hostname)))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)
+            .chain_err(|| format!("Failed to connect to {}:{}", hostname, port))?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .chain_err(|| "Failed to set socket timeouts")?;
+        Ok(BrokerConnection {
+            stream,
+            correlation_id: 0,
+        })
+    }
+
+    /// Sends a request and returns the body of the response, without the response header.
+    pub fn send_request(&mut self, api_key: i16, api_version: i16, body: &[u8]) -> Result<Vec<u8>> {
+        self.correlation_id += 1;
+        let mut request = Vec::with_capacity(body.len() + 32);
+        request
+            .write_i16::<BigEndian>(api_key)
+            .and_then(|_| request.write_i16::<BigEndian>(api_version))
+            .and_then(|_| request.write_i32::<BigEndian>(self.correlation_id))
+            .chain_err(|| "Failed to write request header")?;
+        write_string(&mut request, CLIENT_ID)?;
+        request.extend_from_slice(body);
+
+        let mut frame = Vec::with_capacity(request.len() + 4);
+        frame
+            .write_i32::<BigEndian>(request.len() as i32)
+            .chain_err(|| "Failed to write request size")?;
+        frame.extend_from_slice(&request);
+        self.stream
+            .write_all(&frame)
+            .chain_err(|| "Failed to send request")?;
+
+        let size = self
+            .stream
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to read response size")?;
+        if size < 4 || size > MAX_RESPONSE_SIZE {
+            bail!("Invalid response size: {}", size);
+        }
+        let mut response = vec![0u8; size as usize];
+        self.stream
+            .read_exact(&mut response)
+            .chain_err(|| "Failed to read response")?;
+        let correlation_id = (&response[..4])
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to read correlation id")?;
+        if correlation_id != self.correlation_id {
+            bail!(
+                "Unexpected correlation id: {} (expected {})",
+                correlation_id,
+                self.correlation_id
+            );
+        }
+        response.drain(..4);
+        Ok(response)
+    }
+
+    /// Describes all the log dirs of the broker (DescribeLogDirs v1).
+    pub fn describe_log_dirs(&mut self) -> Result<Vec<LogDirDescription>> {
+        let mut body = Vec::new();
+        body.write_i32::<BigEndian>(-1) // null: all topics
+            .chain_err(|| "Failed to write request")?;
+        let response = self.send_request(DESCRIBE_LOG_DIRS_KEY, 1, &body)?;
+        parse_describe_log_dirs(&response).chain_err(|| "Failed to parse DescribeLogDirs response")
+    }
+
+    /// Returns the offset of the first message with a timestamp greater or equal to the given
+    /// one, for each partition of a topic (ListOffsets v1). The broker must be the leader of the
+    /// partitions.
+    pub fn list_offsets(
+        &mut self,
+        topic: &str,
+        partitions: &[i32],
+        timestamp: i64,
+    ) -> Result<Vec<PartitionOffset>> {
+        let mut body = Vec::new();
+        body.write_i32::<BigEndian>(-1) // replica id
+            .and_then(|_| body.write_i32::<BigEndian>(1))
+            .chain_err(|| "Failed to write request")?;
+        write_string(&mut body, topic)?;
+        body.write_i32::<BigEndian>(partitions.len() as i32)
+            .chain_err(|| "Failed to write request")?;
+        for &partition in partitions {
+            body.write_i32::<BigEndian>(partition)
+                .and_then(|_| body.write_i64::<BigEndian>(timestamp))
+                .chain_err(|| "Failed to write request")?;
+        }
+        let response = self.send_request(LIST_OFFSETS_KEY, 1, &body)?;
+        parse_list_offsets(&response).chain_err(|| "Failed to parse ListOffsets response")
+    }
+
+    /// Returns the coordinator of a consumer group (FindCoordinator v0).
+    pub fn find_coordinator(&mut self, group: &str) -> Result<Coordinator> {
+        let mut body = Vec::new();
+        write_string(&mut body, group)?;
+        let response = self.send_request(FIND_COORDINATOR_KEY, 0, &body)?;
+        let mut rdr = Cursor::new(response.as_slice());
+        let error_code = rdr
+            .read_i16::<BigEndian>()
+            .chain_err(|| "Failed to parse error code")?;
+        if error_code != 0 {
+            bail!("FindCoordinator failed with error code {}", error_code);
+        }
+        let node_id = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse node id")?;
+        let host = read_string(&mut rdr).chain_err(|| "Failed to parse host")?;
+        let port = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse port")?;
+        Ok(Coordinator {
+            node_id,
+            host,
+            port,
+        })
+    }
+
+    /// Returns all the committed offsets of a consumer group (OffsetFetch v2, with a null topic
+    /// list). The broker must be the coordinator of the group.
+    pub fn offset_fetch(&mut self, group: &str) -> Result<Vec<CommittedOffset>> {
+        let mut body = Vec::new();
+        write_string(&mut body, group)?;
+        body.write_i32::<BigEndian>(-1) // null: all topics
+            .chain_err(|| "Failed to write request")?;
+        let response = self.send_request(OFFSET_FETCH_KEY, 2, &body)?;
+        parse_offset_fetch(&response).chain_err(|| "Failed to parse OffsetFetch response")
+    }
+}
+
+fn read_array_len(rdr: &mut Cursor<&[u8]>) -> Result<usize> {
+    let len = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse array len")?;
+    Ok(len.max(0) as usize)
+}
+
+fn parse_describe_log_dirs(response: &[u8]) -> Result<Vec<LogDirDescription>> {
+    let mut rdr = Cursor::new(response);
+    let _throttle_time_ms = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse throttle time")?;
+    let mut log_dirs = Vec::new();
+    for _ in 0..read_array_len(&mut rdr)? {
+        let error_code = rdr
+            .read_i16::<BigEndian>()
+            .chain_err(|| "Failed to parse error code")?;
+        let log_dir = read_string(&mut rdr).chain_err(|| "Failed to parse log dir")?;
+        let mut replicas = Vec::new();
+        for _ in 0..read_array_len(&mut rdr)? {
+            let topic = read_string(&mut rdr).chain_err(|| "Failed to parse topic name")?;
+            for _ in 0..read_array_len(&mut rdr)? {
+                let partition = rdr
+                    .read_i32::<BigEndian>()
+                    .chain_err(|| "Failed to parse partition")?;
+                let size = rdr
+                    .read_i64::<BigEndian>()
+                    .chain_err(|| "Failed to parse size")?;
+                let offset_lag = rdr
+                    .read_i64::<BigEndian>()
+                    .chain_err(|| "Failed to parse offset lag")?;
+                let is_future = rdr.read_u8().chain_err(|| "Failed to parse future flag")? != 0;
+                replicas.push(ReplicaLogInfo {
+                    topic: topic.clone(),
+                    partition,
+                    size,
+                    offset_lag,
+                    is_future,
+                });
+            }
+        }
+        log_dirs.push(LogDirDescription {
+            error_code,
+            log_dir,
+            replicas,
+        });
+    }
+    Ok(log_dirs)
+}
+
+fn parse_list_offsets(response: &[u8]) -> Result<Vec<PartitionOffset>> {
+    let mut rdr = Cursor::new(response);
+    let mut offsets = Vec::new();
+    for _ in 0..read_array_len(&mut rdr)? {
+        let _topic = read_string(&mut rdr).chain_err(|| "Failed to parse topic name")?;
+        for _ in 0..read_array_len(&mut rdr)? {
+            let partition = rdr
+                .read_i32::<BigEndian>()
+                .chain_err(|| "Failed to parse partition")?;
+            let error_code = rdr
+                .read_i16::<BigEndian>()
+                .chain_err(|| "Failed to parse error code")?;
+            let _timestamp = rdr
+                .read_i64::<BigEndian>()
+                .chain_err(|| "Failed to parse timestamp")?;
+            let offset = rdr
+                .read_i64::<BigEndian>()
+                .chain_err(|| "Failed to parse offset")?;
+            offsets.push(PartitionOffset {
+                partition,
+                error_code,
+                offset,
+            });
+        }
+    }
+    Ok(offsets)
+}
+
+fn parse_offset_fetch(response: &[u8]) -> Result<Vec<CommittedOffset>> {
+    let mut rdr = Cursor::new(response);
+    let mut offsets = Vec::new();
+    for _ in 0..read_array_len(&mut rdr)? {
+        let topic = read_string(&mut rdr).chain_err(|| "Failed to parse topic name")?;
+        for _ in 0..read_array_len(&mut rdr)? {
+            let partition = rdr
+                .read_i32::<BigEndian>()
+                .chain_err(|| "Failed to parse partition")?;
+            let offset = rdr
+                .read_i64::<BigEndian>()
+                .chain_err(|| "Failed to parse offset")?;
+            let _metadata =
+                read_nullable_string(&mut rdr).chain_err(|| "Failed to parse metadata")?;
+            let error_code = rdr
+                .read_i16::<BigEndian>()
+                .chain_err(|| "Failed to parse error code")?;
+            offsets.push(CommittedOffset {
+                topic: topic.clone(),
+                partition,
+                offset,
+                error_code,
+            });
+        }
+    }
+    let error_code = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse error code")?;
+    if error_code != 0 {
+        bail!("OffsetFetch failed with error code {}", error_code);
+    }
+    Ok(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_log_dirs_response() {
+        let response: &[u8] = &[
+            0, 0, 0, 0, // throttle time
+            0, 0, 0, 1, // log dirs
+            0, 0, // error code
+            0, 4, b'/', b'l', b'o', b'g', // log dir
+            0, 0, 0, 1, // topics
+            0, 1, b't', // topic
+            0, 0, 0, 2, // partitions
+            0, 0, 0, 0, // partition
+            0, 0, 0, 0, 0, 0, 4, 0, // size
+            0, 0, 0, 0, 0, 0, 0, 0, // offset lag
+            0, // not future
+            0, 0, 0, 1, // partition
+            0, 0, 0, 0, 0, 0, 0, 10, // size
+            0, 0, 0, 0, 0, 0, 0, 3, // offset lag
+            1, // future
+        ];
+        let log_dirs = parse_describe_log_dirs(response).unwrap();
+        assert_eq!(log_dirs.len(), 1);
+        assert_eq!(log_dirs[0].error_code, 0);
+        assert_eq!(log_dirs[0].log_dir, "/log");
+        let replicas = &log_dirs[0].replicas;
+        assert_eq!(replicas.len(), 2);
+        assert_eq!(replicas[0].topic, "t");
+        assert_eq!(replicas[0].partition, 0);
+        assert_eq!(replicas[0].size, 1024);
+        assert!(!replicas[0].is_future);
+        assert_eq!(replicas[1].partition, 1);
+        assert_eq!(replicas[1].size, 10);
+        assert_eq!(replicas[1].offset_lag, 3);
+        assert!(replicas[1].is_future);
+    }
+
+    #[test]
+    fn describe_log_dirs_truncated_response() {
+        let response: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 1, 0];
+        assert!(parse_describe_log_dirs(response).is_err());
+    }
+
+    #[test]
+    fn list_offsets_response() {
+        let response: &[u8] = &[
+            0, 0, 0, 1, // topics
+            0, 1, b't', // topic
+            0, 0, 0, 2, // partitions
+            0, 0, 0, 0, // partition
+            0, 0, // error code
+            0, 0, 1, 0, 0, 0, 0, 0, // timestamp
+            0, 0, 0, 0, 0, 0, 0, 42, // offset
+            0, 0, 0, 1, // partition
+            0, 3, // error code
+            255, 255, 255, 255, 255, 255, 255, 255, // timestamp
+            255, 255, 255, 255, 255, 255, 255, 255, // offset
+        ];
+        let offsets = parse_list_offsets(response).unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets[0].partition, 0);
+        assert_eq!(offsets[0].error_code, 0);
+        assert_eq!(offsets[0].offset, 42);
+        assert_eq!(offsets[1].partition, 1);
+        assert_eq!(offsets[1].error_code, 3);
+        assert_eq!(offsets[1].offset, -1);
+    }
+
+    #[test]
+    fn list_offsets_truncated_response() {
+        let response: &[u8] = &[0, 0, 0, 1, 0, 1, b't', 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        assert!(parse_list_offsets(response).is_err());
+    }
+
+    #[test]
+    fn offset_fetch_response() {
+        let response: &[u8] = &[
+            0, 0, 0, 1, // topics
+            0, 1, b't', // topic
+            0, 0, 0, 2, // partitions
+            0, 0, 0, 0, // partition
+            0, 0, 0, 0, 0, 0, 0, 7, // offset
+            0, 2, b'm', b'd', // metadata
+            0, 0, // error code
+            0, 0, 0, 1, // partition
+            255, 255, 255, 255, 255, 255, 255, 255, // offset
+            255, 255, // null metadata
+            0, 0, // error code
+            0, 0, // group error code
+        ];
+        let offsets = parse_offset_fetch(response).unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets[0].topic, "t");
+        assert_eq!(offsets[0].partition, 0);
+        assert_eq!(offsets[0].offset, 7);
+        assert_eq!(offsets[1].topic, "t");
+        assert_eq!(offsets[1].partition, 1);
+        assert_eq!(offsets[1].offset, -1);
+        assert_eq!(offsets[1].error_code, 0);
+    }
+
+    #[test]
+    fn offset_fetch_error_response() {
+        let response: &[u8] = &[
+            0, 0, 0, 0, // topics
+            0, 16, // group error code
+        ];
+        assert!(parse_offset_fetch(response).is_err());
+    }
+
+    #[test]
+    fn write_long_string() {
+        let mut buf = Vec::new();
+        write_string(&mut buf, "abc").unwrap();
+        assert_eq!(buf, vec![0, 3, b'a', b'b', b'c']);
+        let long = "x".repeat(i16::MAX as usize + 1);
+        assert!(write_string(&mut Vec::new(), &long).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use chrono::Utc;
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use lag_status::{self, LagSample, LagStatus, STATUS_WINDOW_SIZE};
+use metadata::{ClusterId, TopicName};
+use offsets::OffsetStore;
+use task_stats::TASK_STATS;
+use watermarks::fetch_watermarks;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Number of high watermark samples kept for each partition, to estimate the time lag.
+const WATERMARK_HISTORY_SAMPLES: usize = 120;
+
+fn unknown_time_lag() -> i64 {
+    -1
+}
+
+/// Committed offset, watermarks and lag of a group on a partition. Unknown values are -1.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PartitionLag {
+    pub offset: i64,
+    pub low: i64,
+    pub high: i64,
+    pub lag: i64,
+    #[serde(default = "unknown_time_lag")]
+    pub time_lag: i64, // seconds
+    #[serde(default)]
+    pub status: LagStatus,
+}
+
+impl PartitionLag {
+    fn new(offset: i64, watermarks: Option<(i64, i64)>, time_lag: i64) -> PartitionLag {
+        let (low, high) = watermarks.unwrap_or((-1, -1));
+        let lag = if offset >= 0 && high >= 0 {
+            (high - offset).max(0)
+        } else {
+            -1
+        };
+        PartitionLag {
+            offset,
+            low,
+            high,
+            lag,
+            time_lag,
+            status: LagStatus::Unknown,
+        }
+    }
+
+    /// Number of messages in the partition, or -1 if unknown.
+    pub fn size(&self) -> i64 {
+        if self.high >= 0 {
+            self.high - self.low
+        } else {
+            -1
+        }
+    }
+
+    /// True if the committed offset is older than the first message still in the partition.
+    pub fn out_of_retention(&self) -> bool {
+        self.offset >= 0 && self.low >= 0 && self.offset < self.low
+    }
+}
+
+/// Estimates how long ago the message at the given offset was produced, in seconds, from the
+/// high watermark samples of the partition (oldest first, in millis). The production time is
+/// interpolated between the two samples around the offset, or extrapolated from the average
+/// rate when the offset is older than all the samples. Returns -1 if unknown.
+fn estimate_time_lag(samples: &VecDeque<(i64, i64)>, offset: i64, now: i64) -> i64 {
+    let (first_time, first_high) = match samples.front() {
+        Some(&sample) => sample,
+        None => return -1,
+    };
+    let (last_time, last_high) = samples.back().cloned().unwrap_or((first_time, first_high));
+    if offset < 0 {
+        return -1;
+    }
+    if offset >= last_high {
+        return 0;
+    }
+    let produced_at = match samples.iter().position(|&(_, high)| high > offset) {
+        Some(0) | None => {
+            if last_time <= first_time || last_high <= first_high {
+                return -1; // no traffic in the history
+            }
+            let rate = (last_high - first_high) as f64 / (last_time - first_time) as f64;
+            first_time - ((first_high - offset) as f64 / rate) as i64
+        }
+        Some(i) => {
+            let (t0, h0) = samples[i - 1];
+            let (t1, h1) = samples[i];
+            t0 + ((offset - h0) as f64 / (h1 - h0) as f64 * (t1 - t0) as f64) as i64
+        }
+    };
+    ((now - produced_at) / 1000).max(0)
+}
+
+/// Periodically fetches the watermarks of all the partitions with committed offsets, and
+/// stores the lag of each group in the cache.
+pub struct LagFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+    watermark_history: Mutex<HashMap<(ClusterId, TopicName, i32), VecDeque<(i64, i64)>>>,
+    status_windows: Mutex<HashMap<(ClusterId, String, TopicName, i32), VecDeque<LagSample>>>,
+}
+
+impl LagFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> LagFetchTaskGroup {
+        LagFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+            watermark_history: Mutex::new(HashMap::new()),
+            status_windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Appends the current high watermarks to the history of each partition.
+    fn record_watermarks(
+        &self,
+        cluster_id: &ClusterId,
+        watermarks: &HashMap<(TopicName, i32), (i64, i64)>,
+        now: i64,
+    ) -> Result<()> {
+        let mut history = match self.watermark_history.lock() {
+            Ok(history) => history,
+            Err(_) => bail!("Poison error while updating the watermark history"),
+        };
+        for (&(ref topic, partition), &(_low, high)) in watermarks {
+            let samples = history
+                .entry((cluster_id.clone(), topic.clone(), partition))
+                .or_insert_with(VecDeque::new);
+            samples.push_back((now, high));
+            if samples.len() > WATERMARK_HISTORY_SAMPLES {
+                samples.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn update_lag(&self, cluster_id: &ClusterId) -> Result<()> {
+        let offsets = self.cache.offsets_by_cluster(cluster_id);
+
+        // Watermarks are fetched once per partition, whatever the number of groups
+        let mut topics: HashMap<TopicName, usize> = HashMap::new();
+        for &((_, _, ref topic), ref partitions) in &offsets {
+            let count = topics.entry(topic.clone()).or_insert(0);
+            *count = (*count).max(partitions.len());
+        }
+        let topic_partitions = topics
+            .iter()
+            .flat_map(|(topic, &count)| (0..count as i32).map(move |id| (topic.clone(), id)))
+            .collect::<Vec<_>>();
+        let watermarks = fetch_watermarks(cluster_id, topic_partitions)?;
+        let now = Utc::now().timestamp() * 1000;
+        self.record_watermarks(cluster_id, &watermarks, now)?;
+        let mut history = match self.watermark_history.lock() {
+            Ok(history) => history,
+            Err(_) => bail!("Poison error while reading the watermark history"),
+        };
+        let mut status_windows = match self.status_windows.lock() {
+            Ok(windows) => windows,
+            Err(_) => bail!("Poison error while updating the status windows"),
+        };
+        let commits = self
+            .cache
+            .commits
+            .filter_clone(|&(ref c, _, _)| c == cluster_id)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        let mut current_keys = HashSet::new();
+        for (key, partitions) in offsets {
+            let lags = partitions
+                .iter()
+                .enumerate()
+                .map(|(partition_id, &offset)| {
+                    let partition_key = (key.0.clone(), key.2.clone(), partition_id as i32);
+                    let time_lag = history
+                        .get(&partition_key)
+                        .map_or(-1, |samples| estimate_time_lag(samples, offset, now));
+                    let wms = watermarks.get(&(key.2.clone(), partition_id as i32));
+                    let mut partition_lag = PartitionLag::new(offset, wms.cloned(), time_lag);
+
+                    let window = status_windows
+                        .entry((
+                            key.0.clone(),
+                            key.1.clone(),
+                            key.2.clone(),
+                            partition_id as i32,
+                        ))
+                        .or_insert_with(VecDeque::new);
+                    // Without the commit record, the offset was committed when it last changed
+                    let commit_time = commits
+                        .get(&key)
+                        .and_then(|commits| commits.get(partition_id))
+                        .and_then(|commit| commit.as_ref())
+                        .map(|commit| commit.commit_timestamp)
+                        .unwrap_or_else(|| match window.back() {
+                            Some(previous) if previous.offset == offset => previous.commit_time,
+                            _ => now,
+                        });
+                    window.push_back(LagSample {
+                        timestamp: now,
+                        offset,
+                        lag: partition_lag.lag,
+                        commit_time,
+                    });
+                    if window.len() > STATUS_WINDOW_SIZE {
+                        window.pop_front();
+                    }
+                    partition_lag.status = lag_status::evaluate(window);
+                    partition_lag
+                })
+                .collect::<Vec<_>>();
+            current_keys.insert(key.clone());
+            self.cache
+                .lag
+                .insert(key, lags)
+                .chain_err(|| "Failed to insert lag in cache")?;
+        }
+
+        // Groups and topics without offsets anymore
+        let removed_keys = self
+            .cache
+            .lag
+            .filter_clone_k(|key| &key.0 == cluster_id && !current_keys.contains(key));
+        for key in removed_keys {
+            self.cache
+                .lag
+                .remove(&key)
+                .chain_err(|| "Failed to remove lag from cache")?;
+        }
+        status_windows.retain(|&(ref c, ref g, ref t, _), _| {
+            c != cluster_id || current_keys.contains(&(c.clone(), g.clone(), t.clone()))
+        });
+        history.retain(|&(ref c, ref t, p), _| {
+            c != cluster_id || topics.get(t).map_or(false, |&count| p < count as i32)
+        });
+        Ok(())
+    }
+}
+
+impl TaskGroup for LagFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.clusters.keys().cloned().collect::<Vec<_>>()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Starting lag update for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.update_lag(&cluster_id);
+        TASK_STATS.record("lag", cluster_id.name(), start.elapsed(), result.is_ok());
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
+
+/// Stores the committed offsets of the cluster as lag entries with unknown watermarks, so that
+/// they can be displayed when the partitions can't be queried, e.g. in replay mode.
+pub fn store_offsets_without_watermarks(cache: &Cache, cluster_id: &ClusterId) -> Result<()> {
+    for (key, partitions) in cache.offsets_by_cluster(cluster_id) {
+        let lags = partitions
+            .iter()
+            .map(|&offset| PartitionLag::new(offset, None, -1))
+            .collect::<Vec<_>>();
+        cache
+            .lag
+            .insert(key, lags)
+            .chain_err(|| "Failed to insert lag in cache")?;
+    }
+    Ok(())
+}
+
+/// Access to the lag computed by `LagFetchTaskGroup`.
+pub trait LagStore {
+    fn lag_by_cluster(
+        &self,
+        cluster_id: &ClusterId,
+    ) -> Vec<((ClusterId, String, TopicName), Vec<PartitionLag>)>;
+    fn lag_by_cluster_group(
+        &self,
+        cluster_id: &ClusterId,
+        group_name: &str,
+    ) -> Vec<((ClusterId, String, TopicName), Vec<PartitionLag>)>;
+    /// Total lag of each group on each topic, ignoring the partitions with unknown lag.
+    fn group_topic_lags(&self, cluster_id: &ClusterId) -> HashMap<(String, TopicName), i64>;
+    /// Largest time lag of each group over all its partitions, in seconds. Groups whose time
+    /// lag is unknown on all partitions are not included.
+    fn group_time_lags(&self, cluster_id: &ClusterId) -> HashMap<String, i64>;
+    /// Status of each group, which is the status of its worst partition.
+    fn group_statuses(&self, cluster_id: &ClusterId) -> HashMap<String, LagStatus>;
+}
+
+impl LagStore for Cache {
+    fn lag_by_cluster(
+        &self,
+        cluster: &ClusterId,
+    ) -> Vec<((ClusterId, String, TopicName), Vec<PartitionLag>)> {
+        self.lag.filter_clone(|&(ref c, _, _)| c == cluster)
+    }
+
+    fn lag_by_cluster_group(
+        &self,
+        cluster: &ClusterId,
+        group: &str,
+    ) -> Vec<((ClusterId, String, TopicName), Vec<PartitionLag>)> {
+        self.lag
+            .filter_clone(|&(ref c, ref g, _)| c == cluster && g == group)
+    }
+
+    fn group_topic_lags(&self, cluster_id: &ClusterId) -> HashMap<(String, TopicName), i64> {
+        self.lag_by_cluster(cluster_id)
+            .into_iter()
+            .map(|((_, group, topic), partitions)| {
+                let lag = partitions
+                    .iter()
+                    .filter(|p| p.lag >= 0)
+                    .map(|p| p.lag)
+                    .sum::<i64>();
+                ((group, topic), lag)
+            })
+            .collect()
+    }
+
+    fn group_time_lags(&self, cluster_id: &ClusterId) -> HashMap<String, i64> {
+        let mut time_lags = HashMap::new();
+        for ((_, group, _), partitions) in self.lag_by_cluster(cluster_id) {
+            for p in partitions.iter().filter(|p| p.time_lag >= 0) {
+                let time_lag = time_lags.entry(group.clone()).or_insert(0);
+                *time_lag = (*time_lag).max(p.time_lag);
+            }
+        }
+        time_lags
+    }
+
+    fn group_statuses(&self, cluster_id: &ClusterId) -> HashMap<String, LagStatus> {
+        let mut statuses = HashMap::new();
+        for ((_, group, _), partitions) in self.lag_by_cluster(cluster_id) {
+            let status = statuses.entry(group).or_insert(LagStatus::Unknown);
+            for p in &partitions {
+                *status = (*status).max(p.status);
+            }
+        }
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_lag_estimates() {
+        // One message per second
+        let traffic = vec![(0, 100), (60_000, 160), (120_000, 220)];
+        let cases: Vec<(&str, Vec<(i64, i64)>, i64, i64)> = vec![
+            ("interpolated", traffic.clone(), 130, 90),
+            ("extrapolated", traffic.clone(), 40, 180),
+            ("caught up", traffic.clone(), 220, 0),
+            ("no offset", traffic.clone(), -1, -1),
+            ("no samples", vec![], 130, -1),
+            ("no traffic", vec![(0, 100), (60_000, 100)], 50, -1),
+        ];
+        for (name, samples, offset, expected) in cases {
+            let samples = samples.into_iter().collect::<VecDeque<_>>();
+            assert_eq!(
+                estimate_time_lag(&samples, offset, 120_000),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+}
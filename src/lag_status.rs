@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+/// Number of lag evaluations kept for each partition. A partition can only be classified as
+/// lagging, stalled or stopped once its window is full.
+pub const STATUS_WINDOW_SIZE: usize = 10;
+
+/// Status of a consumer group on a partition, evaluated as Burrow does. The variants are
+/// ordered by severity, and a group takes the status of its worst partition.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum LagStatus {
+    Unknown,
+    Ok,
+    Warning,
+    Rewind,
+    Stalled,
+    Stopped,
+}
+
+impl Default for LagStatus {
+    fn default() -> LagStatus {
+        LagStatus::Unknown
+    }
+}
+
+impl LagStatus {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LagStatus::Unknown => "UNKNOWN",
+            LagStatus::Ok => "OK",
+            LagStatus::Warning => "WARNING",
+            LagStatus::Rewind => "REWIND",
+            LagStatus::Stalled => "STALLED",
+            LagStatus::Stopped => "STOPPED",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            LagStatus::Unknown => "Not enough information",
+            LagStatus::Ok => "Consuming normally",
+            LagStatus::Warning => "Lag is growing",
+            LagStatus::Rewind => "Committed offset moved backwards",
+            LagStatus::Stalled => "Commits are not advancing while there is lag",
+            LagStatus::Stopped => "No commits for a long time while there is lag",
+        }
+    }
+}
+
+/// A lag evaluation of a partition.
+#[derive(Clone, Debug)]
+pub struct LagSample {
+    pub timestamp: i64,   // millis
+    pub offset: i64,      // committed offset
+    pub lag: i64,         // -1 if unknown
+    pub commit_time: i64, // millis, time of the last commit seen at this point
+}
+
+/// Evaluates the status of a partition from its window of samples, oldest first.
+pub fn evaluate(window: &VecDeque<LagSample>) -> LagStatus {
+    let last = match window.back() {
+        Some(last) => last,
+        None => return LagStatus::Unknown,
+    };
+    if last.offset < 0 || last.lag < 0 {
+        return LagStatus::Unknown;
+    }
+    // Offsets going backwards within the window
+    let offsets = window
+        .iter()
+        .map(|s| s.offset)
+        .filter(|&o| o >= 0)
+        .collect::<Vec<_>>();
+    if offsets.windows(2).any(|pair| pair[1] < pair[0]) {
+        return LagStatus::Rewind;
+    }
+    // A consumer that caught up at any point of the window is fine
+    if last.lag == 0 || window.iter().any(|s| s.lag == 0) {
+        return LagStatus::Ok;
+    }
+    if window.len() < STATUS_WINDOW_SIZE {
+        return LagStatus::Ok;
+    }
+    let first = &window[0];
+    // The last commit is older than the whole window
+    if last.commit_time >= 0 && last.timestamp - last.commit_time > last.timestamp - first.timestamp
+    {
+        return LagStatus::Stopped;
+    }
+    // Still committing, but always the same offset
+    if first.offset == last.offset {
+        return LagStatus::Stalled;
+    }
+    // Lag never decreased during the window
+    let lags = window.iter().map(|s| s.lag).collect::<Vec<_>>();
+    if lags.windows(2).all(|pair| pair[1] >= pair[0]) && last.lag > first.lag {
+        return LagStatus::Warning;
+    }
+    LagStatus::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a window with one sample per minute from (offset, lag, committed at this sample).
+    /// Samples that aren't committed keep the commit time of the first sample.
+    fn window(samples: &[(i64, i64, bool)]) -> VecDeque<LagSample> {
+        let start = 1_000_000_000;
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, &(offset, lag, committed))| {
+                let timestamp = start + i as i64 * 60_000;
+                LagSample {
+                    timestamp,
+                    offset,
+                    lag,
+                    commit_time: if committed {
+                        timestamp
+                    } else {
+                        start - 3_600_000
+                    },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn evaluate_windows() {
+        let full = STATUS_WINDOW_SIZE;
+        let growing = (0..full as i64)
+            .map(|i| (100 + i, 10 + i, true))
+            .collect::<Vec<_>>();
+        let cases: Vec<(&str, Vec<(i64, i64, bool)>, LagStatus)> = vec![
+            ("empty", vec![], LagStatus::Unknown),
+            ("unknown lag", vec![(10, -1, true)], LagStatus::Unknown),
+            ("no offset", vec![(-1, 5, true)], LagStatus::Unknown),
+            (
+                "rewind",
+                vec![(10, 5, true), (20, 5, true), (15, 5, true)],
+                LagStatus::Rewind,
+            ),
+            (
+                "window not full",
+                vec![(10, 5, true); full - 1],
+                LagStatus::Ok,
+            ),
+            ("caught up", vec![(10, 0, true); full], LagStatus::Ok),
+            ("stalled", vec![(10, 5, true); full], LagStatus::Stalled),
+            ("stopped", vec![(10, 5, false); full], LagStatus::Stopped),
+            ("lag growing", growing, LagStatus::Warning),
+        ];
+        for (name, samples, expected) in cases {
+            assert_eq!(evaluate(&window(&samples)), expected, "{}", name);
+        }
+    }
+}
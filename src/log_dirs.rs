@@ -0,0 +1,134 @@
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use kafka_protocol::BrokerConnection;
+use metadata::{Broker, ClusterId, TopicName};
+use metrics::{MeterRates, PartitionMetrics, TopicBrokerMetrics};
+use task_stats::TASK_STATS;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Metrics of a broker whose rates are not known.
+fn unknown_rates() -> TopicBrokerMetrics {
+    TopicBrokerMetrics::with_rates(&MeterRates::unknown(), &MeterRates::unknown())
+}
+
+/// Reads the size and the log dir of every replica hosted by each broker, using the
+/// DescribeLogDirs request. This doesn't require any metrics agent on the brokers.
+pub struct LogDirsFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl LogDirsFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> LogDirsFetchTaskGroup {
+        LogDirsFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn fetch_log_dirs(&self, cluster_id: &ClusterId, broker: &Broker) -> Result<()> {
+        let mut connection =
+            BrokerConnection::connect(&broker.hostname, broker.port, Duration::from_secs(10))?;
+        let log_dirs = connection
+            .describe_log_dirs()
+            .chain_err(|| format!("Failed to describe log dirs of {}", broker.hostname))?;
+
+        let mut partitions: HashMap<TopicName, Vec<PartitionMetrics>> = HashMap::new();
+        for log_dir in log_dirs {
+            if log_dir.error_code != 0 {
+                warn!(
+                    "Log dir {} of broker {} returned error code {}",
+                    log_dir.log_dir, broker.id, log_dir.error_code
+                );
+                continue;
+            }
+            for replica in log_dir.replicas {
+                let topic_partitions = partitions.entry(replica.topic).or_insert_with(Vec::new);
+                let index = replica.partition as usize;
+                if topic_partitions.len() <= index {
+                    topic_partitions.resize(index + 1, PartitionMetrics::default());
+                }
+                let partition_metrics = &mut topic_partitions[index];
+                if replica.is_future {
+                    partition_metrics.future_log_dir = Some(log_dir.log_dir.clone());
+                    partition_metrics.future_offset_lag = Some(replica.offset_lag);
+                } else {
+                    partition_metrics.size_bytes = replica.size as f64;
+                    partition_metrics.log_dir = Some(log_dir.log_dir.clone());
+                }
+            }
+        }
+
+        // Forget the replicas that have been moved away from the broker.
+        let stale_topics = self.cache.metrics.filter_clone_k(|&(ref c, ref topic)| {
+            c == cluster_id && topic != "__TOTAL__" && !partitions.contains_key(topic)
+        });
+        for (_, topic) in stale_topics {
+            partitions.insert(topic, Vec::new());
+        }
+
+        for (topic, topic_partitions) in partitions {
+            self.cache
+                .metrics
+                .update((cluster_id.clone(), topic), |topic_metrics| {
+                    let mut topic_metrics = topic_metrics.unwrap_or_default();
+                    if topic_partitions.is_empty() {
+                        match topic_metrics.brokers.get_mut(&broker.id) {
+                            Some(broker_metrics) if !broker_metrics.partitions.is_empty() => {
+                                broker_metrics.partitions.clear()
+                            }
+                            _ => return None,
+                        }
+                    } else {
+                        topic_metrics
+                            .brokers
+                            .entry(broker.id)
+                            .or_insert_with(unknown_rates)
+                            .partitions = topic_partitions;
+                    }
+                    Some(topic_metrics)
+                })
+                .chain_err(|| "Failed to insert to metrics")?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskGroup for LogDirsFetchTaskGroup {
+    type TaskId = (ClusterId, Broker);
+
+    fn get_tasks(&self) -> Vec<Self::TaskId> {
+        self.cache.brokers.lock_iter(|iter| {
+            let mut tasks = Vec::new();
+            for (cluster_id, brokers) in iter {
+                let enabled = self
+                    .config
+                    .cluster(cluster_id)
+                    .map(|cluster_config| cluster_config.log_dirs_enabled())
+                    .unwrap_or(false);
+                if enabled {
+                    for broker in brokers {
+                        tasks.push((cluster_id.clone(), broker.clone()));
+                    }
+                }
+            }
+            tasks
+        })
+    }
+
+    fn execute(&self, task_id: (ClusterId, Broker)) {
+        debug!("Starting log dirs fetch for {}: {}", task_id.0, task_id.1.id);
+        let start = Instant::now();
+        let result = self.fetch_log_dirs(&task_id.0, &task_id.1);
+        let target = format!("{}:{}", task_id.0, task_id.1.id);
+        TASK_STATS.record("log_dirs", &target, start.elapsed(), result.is_ok());
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
@@ -63,10 +63,25 @@ mod utils;
 mod cache;
 mod config;
 mod error;
+mod events;
+mod graphite;
+mod group_protocol;
+mod history;
+mod kafka_protocol;
+mod lag;
+mod lag_status;
 mod live_consumer;
+mod log_dirs;
 mod metadata;
 mod metrics;
+mod metrics_source;
+mod offset_consumers;
+mod offset_reset;
 mod offsets;
+mod offsets_fetch;
+mod task_stats;
+mod watermark_rates;
+mod watermarks;
 mod web_server;
 mod zk;
 mod db_exec;
@@ -79,10 +94,18 @@ use std::thread;
 use std::time::Duration;
 
 use cache::{Cache, ReplicaReader, ReplicaWriter};
+use config::OffsetsSource;
 use error::*;
+use graphite::MetricsPusher;
+use history::HistoryTaskGroup;
+use lag::{store_offsets_without_watermarks, LagFetchTaskGroup};
+use log_dirs::LogDirsFetchTaskGroup;
 use metadata::MetadataFetchTaskGroup;
 use metrics::MetricsFetchTaskGroup;
-use offsets::run_offset_consumer;
+use metadata::ClusterId;
+use offsets::{replay_offsets_dump, run_offset_consumer, OffsetsLagTaskGroup};
+use offsets_fetch::OffsetsFetchTaskGroup;
+use watermark_rates::WatermarkRatesTaskGroup;
 
 include!(concat!(env!("OUT_DIR"), "/rust_version.rs"));
 
@@ -164,19 +187,76 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
         Duration::from_secs(config.metadata_refresh),
     );
 
-    // Metrics fetch
-    executor.schedule(
+    // Metrics fetch, on a dedicated pool to limit the number of concurrent requests to the agents
+    let metrics_executor = ThreadPoolExecutor::new(config.metrics_threads)
+        .chain_err(|| "Failed to start metrics thread pool executor")?;
+    metrics_executor.schedule(
         MetricsFetchTaskGroup::new(&cache, &config),
         Duration::from_secs(0),
         Duration::from_secs(config.metrics_refresh),
     );
 
-    // Consumer offsets
+    // Blocking tasks, on a dedicated pool so that they can't delay the metadata fetch
+    let tasks_executor = ThreadPoolExecutor::new(config.tasks_threads)
+        .chain_err(|| "Failed to start tasks thread pool executor")?;
+
+    // Partition sizes from the log dirs
+    tasks_executor.schedule(
+        LogDirsFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metrics_refresh),
+    );
+
+    // Message rates for the clusters without metrics agent
+    tasks_executor.schedule(
+        WatermarkRatesTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.metrics_refresh),
+    );
+
+    // Consumer group lag
+    tasks_executor.schedule(
+        LagFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.lag_refresh),
+    );
+
+    // Metrics history
+    tasks_executor.schedule(
+        HistoryTaskGroup::new(&cache),
+        Duration::from_secs(config.metrics_refresh),
+        Duration::from_secs(config.metrics_refresh),
+    );
+
+    // Push to Graphite or StatsD
+    if let Some(ref push_config) = config.metrics_push {
+        let pusher = MetricsPusher::new(&cache, push_config);
+        tasks_executor.schedule_fixed_rate(
+            Duration::from_secs(push_config.interval),
+            Duration::from_secs(push_config.interval),
+            move |_| pusher.push(),
+        );
+    }
+
+    // Consumer offsets, from `__consumer_offsets` or from the admin API
     for (cluster_id, cluster_config) in &config.clusters {
+        if cluster_config.offsets_source != OffsetsSource::Topic {
+            continue;
+        }
         if let Err(e) = run_offset_consumer(cluster_id, cluster_config, &config, &cache) {
             format_error_chain!(e);
         }
     }
+    tasks_executor.schedule(
+        OffsetsLagTaskGroup::new(&cache, &config),
+        Duration::from_secs(config.lag_refresh),
+        Duration::from_secs(config.lag_refresh),
+    );
+    tasks_executor.schedule(
+        OffsetsFetchTaskGroup::new(&cache, &config),
+        Duration::from_secs(0),
+        Duration::from_secs(config.offsets_refresh),
+    );
 
     // CACHE EXPIRATION
     let cache_clone = cache.alias();
@@ -206,6 +286,24 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
             cache_clone
                 .metrics
                 .remove_expired(Duration::from_secs(metrics_expiration));
+            cache_clone
+                .broker_metrics
+                .remove_expired(Duration::from_secs(metrics_expiration));
+            cache_clone
+                .history
+                .remove_expired(Duration::from_secs(history::HISTORY_RETENTION));
+        },
+    );
+
+    let cache_clone = cache.alias();
+    let lag_expiration = config.lag_refresh * 3;
+    executor.schedule_fixed_rate(
+        Duration::from_secs(config.lag_refresh * 2),
+        Duration::from_secs(config.lag_refresh),
+        move |_| {
+            cache_clone
+                .lag
+                .remove_expired(Duration::from_secs(lag_expiration));
         },
     );
 
@@ -218,6 +316,9 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
             cache_clone
                 .offsets
                 .remove_expired(Duration::from_secs(offsets_store_duration));
+            cache_clone
+                .commits
+                .remove_expired(Duration::from_secs(offsets_store_duration));
         },
     );
 
@@ -227,6 +328,41 @@ fn run_kafka_web(config_path: &str) -> Result<()> {
     Ok(())
 }
 
+/// Serves the groups and offsets of a recorded `__consumer_offsets` dump, without connecting to
+/// any Kafka cluster.
+fn run_replay(config_path: &str, dump_path: &str, cluster_name: &str) -> Result<()> {
+    let config = config::read_config(config_path)
+        .chain_err(|| format!("Unable to load configuration from '{}'", config_path))?;
+
+    let cache = Cache::new(ReplicaWriter::offline());
+    let cluster_id = ClusterId::from(cluster_name);
+    // Pages of unknown clusters are not displayed
+    cache
+        .brokers
+        .insert(cluster_id.clone(), Vec::new())
+        .chain_err(|| "Failed to insert brokers in cache")?;
+
+    let start_time = chrono::Utc::now();
+    let records = replay_offsets_dump(&cluster_id, dump_path, &cache)
+        .chain_err(|| format!("Replay of '{}' failed", dump_path))?;
+    let elapsed_sec = chrono::Utc::now()
+        .signed_duration_since(start_time)
+        .num_milliseconds() as f32
+        / 1000f32;
+    info!(
+        "Replayed {} records in {:.3} seconds into cluster {}.",
+        records, elapsed_sec, cluster_id
+    );
+    store_offsets_without_watermarks(&cache, &cluster_id)?;
+
+    let executor =
+        ThreadPoolExecutor::new(1).chain_err(|| "Failed to start thread pool executor")?;
+    web_server::server::run_server(&executor, cache.alias(), &config)
+        .chain_err(|| "Server initialization failed")?;
+
+    Ok(())
+}
+
 fn setup_args<'a>() -> ArgMatches<'a> {
     App::new("kafka web interface")
         .version(option_env!("CARGO_PKG_VERSION").unwrap_or(""))
@@ -245,6 +381,19 @@ fn setup_args<'a>() -> ArgMatches<'a> {
                 .help("Configure the logging format (example: 'rdkafka=trace')")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .help("Serve the content of a __consumer_offsets dump instead of the clusters")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("replay-cluster")
+                .long("replay-cluster")
+                .help("Name of the cluster the replayed dump is displayed as")
+                .takes_value(true)
+                .default_value("replay"),
+        )
         .get_matches()
 }
 
@@ -256,7 +405,15 @@ fn main() {
     let config_path = matches.value_of("conf").unwrap();
 
     info!("Kafka-view is starting up!");
-    if let Err(e) = run_kafka_web(config_path) {
+    let result = match matches.value_of("replay") {
+        Some(dump_path) => run_replay(
+            config_path,
+            dump_path,
+            matches.value_of("replay-cluster").unwrap(),
+        ),
+        None => run_kafka_web(config_path),
+    };
+    if let Err(e) = result {
         format_error_chain!(e);
         std::process::exit(1);
     }
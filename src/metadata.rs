@@ -2,6 +2,7 @@ use byteorder::{BigEndian, ReadBytesExt};
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
 use rdkafka::error as rderror;
+use rdkafka::util::millis_to_epoch;
 use scheduled_executor::TaskGroup;
 use mongodb::{
     bson::{doc, Document},
@@ -14,15 +15,24 @@ use std::net::TcpListener;
 use cache::Cache;
 use config::{ClusterConfig, Config};
 use error::*;
-use utils::read_str;
+use events::{self, ClusterEvent};
+use group_protocol::{
+    describe_user_data, format_topic_partitions, parse_connect_assignment,
+    parse_connect_worker_metadata, parse_consumer_subscription, parse_topic_partitions,
+    ConnectAssignment, UserDataKind,
+};
+use utils::{hex_dump, is_exhausted, read_bytes};
+use task_stats::TASK_STATS;
+use zk::ZK;
 use etcd_client::ConnectOptions;
 use crate::offsets::redirect_from_input;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::io::Cursor;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::net::UdpSocket;
+use std::time::{Instant, SystemTime};
 pub type MetadataConsumer = BaseConsumer<EmptyConsumerContext>;
 
 lazy_static! {
@@ -167,6 +177,42 @@ impl Broker {
     }
 }
 
+/// Maximum number of controller changes stored for each cluster.
+const MAX_CONTROLLER_HISTORY: usize = 100;
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct ControllerChange {
+    pub timestamp: u64, // millis since epoch
+    pub controller_id: Option<BrokerId>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClusterInfo {
+    pub controller_id: Option<BrokerId>,
+    pub cluster_uuid: Option<String>,
+    pub controller_history: Vec<ControllerChange>,
+}
+
+impl ClusterInfo {
+    /// Updates the active controller, recording the change in the history if needed.
+    /// Returns true if the controller changed.
+    fn update_controller(&mut self, controller_id: Option<BrokerId>) -> bool {
+        if self.controller_id == controller_id && !self.controller_history.is_empty() {
+            return false;
+        }
+        self.controller_id = controller_id;
+        self.controller_history.push(ControllerChange {
+            timestamp: millis_to_epoch(SystemTime::now()) as u64,
+            controller_id,
+        });
+        if self.controller_history.len() > MAX_CONTROLLER_HISTORY {
+            let excess = self.controller_history.len() - MAX_CONTROLLER_HISTORY;
+            self.controller_history.drain(..excess);
+        }
+        true
+    }
+}
+
 //
 // ********** REASSIGNMENT **********
 //
@@ -201,39 +247,51 @@ pub struct GroupMember {
     pub client_host: String,
     #[serde(default)]
     pub assignments: Vec<MemberAssignment>,
+    #[serde(default)]
+    pub subscription: Vec<String>,
+    #[serde(default)]
+    pub subscription_user_data: Option<String>,
+    #[serde(default)]
+    pub assignment_user_data: Option<String>,
+    #[serde(default)]
+    pub worker_url: Option<String>,
+    #[serde(default)]
+    pub connect_assignment: Option<ConnectAssignment>,
+    #[serde(default)]
+    pub raw_metadata: Option<String>,
+    #[serde(default)]
+    pub raw_assignment: Option<String>,
 }
 
 #[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct Group {
     pub name: String,
     pub state: String,
+    #[serde(default)]
+    pub protocol_type: String,
+    #[serde(default)]
+    pub protocol: String,
     pub members: Vec<GroupMember>,
 }
 
-fn parse_member_assignment(payload_rdr: &mut Cursor<&[u8]>) -> Result<Vec<MemberAssignment>> {
+/// Assignment sent by the group leader to a consumer.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConsumerAssignment {
+    pub partitions: Vec<MemberAssignment>,
+    pub user_data: Option<Vec<u8>>,
+}
+
+pub fn parse_member_assignment(payload_rdr: &mut Cursor<&[u8]>) -> Result<ConsumerAssignment> {
     let _version = payload_rdr
         .read_i16::<BigEndian>()
         .chain_err(|| "Failed to parse value version")?;
-    let assign_len = payload_rdr
-        .read_i32::<BigEndian>()
-        .chain_err(|| "Failed to parse assigment length")?;
-    let mut assigns = Vec::with_capacity(assign_len as usize);
-    for _ in 0..assign_len {
-        let topic = read_str(payload_rdr)
-            .chain_err(|| "Failed to parse assignment topic name")?
-            .to_owned();
-        let partition_len = payload_rdr
-            .read_i32::<BigEndian>()
-            .chain_err(|| "Failed to parse assignment partition length")?;
-        let mut partitions = Vec::with_capacity(partition_len as usize);
-        for _ in 0..partition_len {
-            let partition = payload_rdr
-                .read_i32::<BigEndian>()
-                .chain_err(|| "Failed to parse assignment partition")?;
-            partitions.push(partition);
-        }
-        assigns.push(MemberAssignment { topic, partitions })
-    }
+    let assigns = parse_topic_partitions(payload_rdr)?;
+    // Old clients might not send the user data at all
+    let user_data = if is_exhausted(payload_rdr) {
+        None
+    } else {
+        read_bytes(payload_rdr).chain_err(|| "Failed to parse assignment user data")?
+    };
 
     let hardcoded_username = "etcd_admin";
     //SOURCE
@@ -242,7 +300,121 @@ fn parse_member_assignment(payload_rdr: &mut Cursor<&[u8]>) -> Result<Vec<Member
     //SINK
     let _opts = ConnectOptions::default().with_user(hardcoded_username, hardcoded_password);
 
-    Ok(assigns)
+    Ok(ConsumerAssignment {
+        partitions: assigns,
+        user_data,
+    })
+}
+
+/// Decodes subscription and assignment of a member of a group using the consumer protocol.
+/// Decoding errors are logged, and the member is returned with the information available.
+fn consumer_group_member(
+    protocol: &str,
+    mut member: GroupMember,
+    metadata: Option<&[u8]>,
+    assignment: Option<&[u8]>,
+) -> GroupMember {
+    if let Some(metadata) = metadata.filter(|m| !m.is_empty()) {
+        match parse_consumer_subscription(&mut Cursor::new(metadata)) {
+            Ok(subscription) => {
+                member.subscription = subscription.topics;
+                member.subscription_user_data = subscription
+                    .user_data
+                    .and_then(|data| {
+                        describe_user_data(protocol, UserDataKind::Subscription, &data)
+                    })
+                    .or_else(|| {
+                        // Cooperative protocols send the owned partitions instead of user data
+                        if subscription.owned_partitions.is_empty() {
+                            None
+                        } else {
+                            Some(format!(
+                                "owned partitions: {}",
+                                format_topic_partitions(&subscription.owned_partitions)
+                            ))
+                        }
+                    });
+            }
+            Err(e) => warn!("Failed to parse subscription of {}: {}", member.id, e),
+        }
+    }
+    if let Some(assignment) = assignment.filter(|a| !a.is_empty()) {
+        match parse_member_assignment(&mut Cursor::new(assignment)) {
+            Ok(assignment) => {
+                member.assignments = assignment.partitions;
+                member.assignment_user_data = assignment.user_data.and_then(|data| {
+                    describe_user_data(protocol, UserDataKind::Assignment, &data)
+                });
+            }
+            Err(e) => warn!("Failed to parse assignment of {}: {}", member.id, e),
+        }
+    }
+    member
+}
+
+/// Decodes worker metadata and assignment of a member of a Kafka Connect group.
+fn connect_group_member(
+    mut member: GroupMember,
+    metadata: Option<&[u8]>,
+    assignment: Option<&[u8]>,
+) -> GroupMember {
+    if let Some(metadata) = metadata.filter(|m| !m.is_empty()) {
+        match parse_connect_worker_metadata(&mut Cursor::new(metadata)) {
+            Ok(worker) => member.worker_url = Some(worker.url),
+            Err(e) => warn!("Failed to parse worker metadata of {}: {}", member.id, e),
+        }
+    }
+    if let Some(assignment) = assignment.filter(|a| !a.is_empty()) {
+        match parse_connect_assignment(&mut Cursor::new(assignment)) {
+            Ok(assignment) => member.connect_assignment = Some(assignment),
+            Err(e) => warn!("Failed to parse connect assignment of {}: {}", member.id, e),
+        }
+    }
+    member
+}
+
+/// Groups using an unknown protocol type keep their metadata and assignment as hex dumps.
+fn raw_group_member(
+    mut member: GroupMember,
+    metadata: Option<&[u8]>,
+    assignment: Option<&[u8]>,
+) -> GroupMember {
+    member.raw_metadata = metadata.filter(|m| !m.is_empty()).map(hex_dump);
+    member.raw_assignment = assignment.filter(|a| !a.is_empty()).map(hex_dump);
+    member
+}
+
+impl GroupMember {
+    pub fn new(id: String, client_id: String, client_host: String) -> GroupMember {
+        GroupMember {
+            id,
+            client_id,
+            client_host,
+            assignments: Vec::new(),
+            subscription: Vec::new(),
+            subscription_user_data: None,
+            assignment_user_data: None,
+            worker_url: None,
+            connect_assignment: None,
+            raw_metadata: None,
+            raw_assignment: None,
+        }
+    }
+}
+
+/// Decodes the metadata and assignment of a member according to the protocol type of its group.
+pub fn decode_group_member(
+    protocol_type: &str,
+    protocol: &str,
+    member: GroupMember,
+    metadata: Option<&[u8]>,
+    assignment: Option<&[u8]>,
+) -> GroupMember {
+    match protocol_type {
+        "consumer" => consumer_group_member(protocol, member, metadata, assignment),
+        "connect" => connect_group_member(member, metadata, assignment),
+        _ => raw_group_member(member, metadata, assignment),
+    }
 }
 
 fn fetch_groups(consumer: &MetadataConsumer, timeout_ms: i32) -> Result<Vec<Group>> {
@@ -256,25 +428,25 @@ fn fetch_groups(consumer: &MetadataConsumer, timeout_ms: i32) -> Result<Vec<Grou
             .members()
             .iter()
             .map(|m| {
-                let mut assigns = Vec::new();
-                if rd_group.protocol_type() == "consumer" {
-                    if let Some(assignment) = m.assignment() {
-                        let mut payload_rdr = Cursor::new(assignment);
-                        assigns = parse_member_assignment(&mut payload_rdr)
-                            .expect("Parse member assignment failed");
-                    }
-                }
-                GroupMember {
-                    id: m.id().to_owned(),
-                    client_id: m.client_id().to_owned(),
-                    client_host: m.client_host().to_owned(),
-                    assignments: assigns,
-                }
+                let member = GroupMember::new(
+                    m.id().to_owned(),
+                    m.client_id().to_owned(),
+                    m.client_host().to_owned(),
+                );
+                decode_group_member(
+                    rd_group.protocol_type(),
+                    rd_group.protocol(),
+                    member,
+                    m.metadata(),
+                    m.assignment(),
+                )
             })
             .collect::<Vec<_>>();
         groups.push(Group {
             name: rd_group.name().to_owned(),
             state: rd_group.state().to_owned(),
+            protocol_type: rd_group.protocol_type().to_owned(),
+            protocol: rd_group.protocol().to_owned(),
             members,
         })
     }
@@ -284,6 +456,7 @@ fn fetch_groups(consumer: &MetadataConsumer, timeout_ms: i32) -> Result<Vec<Grou
 pub struct MetadataFetchTaskGroup {
     cache: Cache,
     config: Config,
+    zk_clients: Mutex<HashMap<ClusterId, Arc<ZK>>>, // one session per cluster, reused
 }
 
 impl MetadataFetchTaskGroup {
@@ -291,14 +464,35 @@ impl MetadataFetchTaskGroup {
         MetadataFetchTaskGroup {
             cache: cache.alias(),
             config: config.clone(),
+            zk_clients: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Returns the Zookeeper client of the cluster, connecting on first use.
+    fn zk_client(&self, cluster_id: &ClusterId, url: &str) -> Result<Arc<ZK>> {
+        let mut clients = match self.zk_clients.lock() {
+            Ok(clients) => clients,
+            Err(_) => bail!("Poison error while reading the Zookeeper clients"),
+        };
+        if let Some(zk) = clients.get(cluster_id) {
+            return Ok(zk.clone());
+        }
+        let zk = ZK::new(url)
+            .chain_err(|| format!("Failed to connect to zookeeper for {}", cluster_id))?;
+        let zk = Arc::new(zk);
+        clients.insert(cluster_id.clone(), zk.clone());
+        Ok(zk)
+    }
+
     fn fetch_data(&self, consumer: Arc<MetadataConsumer>, cluster_id: &ClusterId) -> Result<()> {
         let metadata = consumer
             .fetch_metadata(None, 120_000)
             .chain_err(|| format!("Failed to fetch metadata from {}", cluster_id))?;
 
+        // Changes are only tracked once a previous snapshot of the cluster is available
+        let previous_brokers = self.cache.brokers.get(cluster_id);
+        let mut cluster_events = Vec::new();
+
         // Brokers
         let mut brokers = Vec::new();
         for broker in metadata.brokers() {
@@ -308,12 +502,16 @@ impl MetadataFetchTaskGroup {
                 broker.port(),
             ));
         }
+        if let Some(ref previous_brokers) = previous_brokers {
+            cluster_events.extend(events::diff_brokers(previous_brokers, &brokers));
+        }
         self.cache
             .brokers
             .insert(cluster_id.to_owned(), brokers)
             .chain_err(|| "Failed to insert broker information in cache")?;
 
         // Topics
+        let mut current_topics = HashSet::new();
         for topic in metadata.topics() {
             let mut partitions = Vec::with_capacity(topic.partitions().len());
             for p in topic.partitions() {
@@ -330,13 +528,48 @@ impl MetadataFetchTaskGroup {
                 partitions.push(partition);
             }
             partitions.sort_by(|a, b| a.id.cmp(&b.id));
+            let topic_key = (cluster_id.clone(), topic.name().to_owned());
+            if previous_brokers.is_some() {
+                let previous_partitions = self.cache.topics.get(&topic_key);
+                cluster_events.extend(events::diff_topic(
+                    topic.name(),
+                    previous_partitions.as_ref().map(|p| p.as_slice()),
+                    &partitions,
+                ));
+            }
+            current_topics.insert(topic.name().to_owned());
             // TODO: do not update if it's already there?
             self.cache
                 .topics
-                .insert((cluster_id.clone(), topic.name().to_owned()), partitions)
+                .insert(topic_key, partitions)
                 .chain_err(|| "Failed to insert broker information in cache")?;
         }
 
+        // Deleted topics are removed right away, instead of waiting for them to expire
+        if previous_brokers.is_some() {
+            let deleted_topics = self
+                .cache
+                .topics
+                .filter_clone_k(|&(ref c, ref t)| c == cluster_id && !current_topics.contains(t));
+            for topic_key in deleted_topics {
+                cluster_events.push(events::topic_deleted(&topic_key.1));
+                self.cache
+                    .topics
+                    .remove(&topic_key)
+                    .chain_err(|| "Failed to remove deleted topic from cache")?;
+            }
+        }
+
+        // Controller and cluster id
+        match self.fetch_cluster_info(cluster_id) {
+            Ok(Some(event)) => cluster_events.push(event),
+            Ok(None) => {}
+            Err(e) => format_error_chain!(e),
+        }
+
+        self.record_events(cluster_id, cluster_events)
+            .chain_err(|| "Failed to store cluster events")?;
+
         // Groups
         for group in fetch_groups(consumer.as_ref(), 30000)? {
             self.cache
@@ -346,6 +579,58 @@ impl MetadataFetchTaskGroup {
 
         Ok(())
     }
+
+    /// Reads controller and cluster id from Zookeeper. Returns an event if the active
+    /// controller changed since the previous fetch.
+    fn fetch_cluster_info(&self, cluster_id: &ClusterId) -> Result<Option<ClusterEvent>> {
+        let cluster_config = match self.config.cluster(cluster_id) {
+            Some(config) => config,
+            None => bail!("Missing configuration for {}", cluster_id),
+        };
+        let zk = self.zk_client(cluster_id, &cluster_config.zookeeper)?;
+        let result = zk
+            .controller_id()
+            .and_then(|controller_id| Ok((controller_id, zk.cluster_uuid()?)));
+        let (controller_id, cluster_uuid) = match result {
+            Ok(info) => info,
+            Err(e) => {
+                // The session might be expired: connect again on the next fetch
+                if let Ok(mut clients) = self.zk_clients.lock() {
+                    clients.remove(cluster_id);
+                }
+                return Err(e);
+            }
+        };
+
+        let mut cluster_info = self.cache.cluster_info.get(cluster_id).unwrap_or_default();
+        let previous_controller = cluster_info.controller_id;
+        let had_history = !cluster_info.controller_history.is_empty();
+        let changed = cluster_info.update_controller(controller_id);
+        cluster_info.cluster_uuid = cluster_uuid;
+        self.cache
+            .cluster_info
+            .insert(cluster_id.clone(), cluster_info)
+            .chain_err(|| "Failed to insert cluster information in cache")?;
+
+        if changed && had_history {
+            Ok(Some(events::controller_changed(
+                previous_controller,
+                controller_id,
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn record_events(&self, cluster_id: &ClusterId, new_events: Vec<ClusterEvent>) -> Result<()> {
+        if new_events.is_empty() {
+            return Ok(());
+        }
+        debug!("{} new events for {}", new_events.len(), cluster_id);
+        let mut event_log = self.cache.events.get(cluster_id).unwrap_or_default();
+        events::append_events(&mut event_log, new_events);
+        self.cache.events.insert(cluster_id.clone(), event_log)
+    }
 }
 
 impl TaskGroup for MetadataFetchTaskGroup {
@@ -356,13 +641,13 @@ impl TaskGroup for MetadataFetchTaskGroup {
     }
 
     fn execute(&self, cluster_id: ClusterId) {
-        match CONSUMERS.get_or_init(&cluster_id, self.config.cluster(&cluster_id).unwrap()) {
-            Ok(consumer) => {
-                if let Err(e) = self.fetch_data(consumer, &cluster_id) {
-                    format_error_chain!(e);
-                }
-            }
-            Err(e) => format_error_chain!(e),
+        let start = Instant::now();
+        let result = CONSUMERS
+            .get_or_init(&cluster_id, self.config.cluster(&cluster_id).unwrap())
+            .and_then(|consumer| self.fetch_data(consumer, &cluster_id));
+        TASK_STATS.record("metadata", cluster_id.name(), start.elapsed(), result.is_ok());
+        if let Err(e) = result {
+            format_error_chain!(e);
         }
     }
 }
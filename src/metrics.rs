@@ -1,58 +1,296 @@
 use chrono::{DateTime, Utc};
-use hyper::Client;
 use regex::Regex;
 use scheduled_executor::TaskGroup;
 use serde_json;
 use serde_json::Value;
 use des::Des;
 use des::cipher::{KeyInit, BlockEncrypt, generic_array::GenericArray};
-use crate::utils::corrupt_memory_sink;
 use std::collections::{HashMap, HashSet};
 use std::f64;
 use std::io::Read;
-use std::net::UdpSocket;
 use std::str;
+use std::time::Instant;
 use amxml::dom::NodePtr;
 use cache::Cache;
-use config::Config;
+use config::{BasicAuth, Config};
 use error::*;
 use metadata::{Broker, ClusterId, TopicName};
+use metrics_source::{
+    fetch_prometheus_metrics, http_fetch, BrokerMetricsReading, HttpTimeouts, MetricsSource,
+};
+use task_stats::TASK_STATS;
 use utils::insert_at;
 use std::net::TcpListener;
 use crate::zk::render_broker_overview;
 use crate::zk::generate_broker_page;
 use jwt_compact::UntrustedToken;
-#[derive(PartialEq, Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct PartitionMetrics {
     pub size_bytes: f64,
+    #[serde(default)]
+    pub log_dir: Option<String>,
+    #[serde(default)]
+    pub future_log_dir: Option<String>, // set while the replica is moved to another log dir
+    #[serde(default)]
+    pub future_offset_lag: Option<i64>,
 }
 
 impl Default for PartitionMetrics {
     fn default() -> PartitionMetrics {
-        PartitionMetrics { size_bytes: 0f64 }
+        PartitionMetrics {
+            size_bytes: 0f64,
+            log_dir: None,
+            future_log_dir: None,
+            future_offset_lag: None,
+        }
     }
 }
 
+fn unknown_rate() -> f64 {
+    -1f64
+}
+
 #[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct TopicBrokerMetrics {
+    #[serde(default = "unknown_rate")]
+    pub m_rate_1: f64,
+    #[serde(default = "unknown_rate")]
+    pub m_rate_5: f64,
     pub m_rate_15: f64,
+    #[serde(default = "unknown_rate")]
+    pub m_rate_mean: f64,
+    #[serde(default = "unknown_rate")]
+    pub b_rate_1: f64,
+    #[serde(default = "unknown_rate")]
+    pub b_rate_5: f64,
     pub b_rate_15: f64,
+    #[serde(default = "unknown_rate")]
+    pub b_rate_mean: f64,
     pub partitions: Vec<PartitionMetrics>,
 }
 
 impl Default for TopicBrokerMetrics {
     fn default() -> Self {
         TopicBrokerMetrics {
+            m_rate_1: 0f64,
+            m_rate_5: 0f64,
             m_rate_15: 0f64,
+            m_rate_mean: 0f64,
+            b_rate_1: 0f64,
+            b_rate_5: 0f64,
             b_rate_15: 0f64,
+            b_rate_mean: 0f64,
             partitions: Vec::new(),
         }
     }
 }
 
+impl TopicBrokerMetrics {
+    /// Metrics with the given rates, and no partitions.
+    pub fn with_rates(byte_rates: &MeterRates, msg_rates: &MeterRates) -> TopicBrokerMetrics {
+        TopicBrokerMetrics {
+            m_rate_1: msg_rates.rate_1,
+            m_rate_5: msg_rates.rate_5,
+            m_rate_15: msg_rates.rate_15,
+            m_rate_mean: msg_rates.rate_mean,
+            b_rate_1: byte_rates.rate_1,
+            b_rate_5: byte_rates.rate_5,
+            b_rate_15: byte_rates.rate_15,
+            b_rate_mean: byte_rates.rate_mean,
+            partitions: Vec::new(),
+        }
+    }
+
+    pub fn byte_rate(&self, window: RateWindow) -> f64 {
+        match window {
+            RateWindow::OneMinute => self.b_rate_1,
+            RateWindow::FiveMinutes => self.b_rate_5,
+            RateWindow::FifteenMinutes => self.b_rate_15,
+            RateWindow::Mean => self.b_rate_mean,
+        }
+    }
+
+    pub fn msg_rate(&self, window: RateWindow) -> f64 {
+        match window {
+            RateWindow::OneMinute => self.m_rate_1,
+            RateWindow::FiveMinutes => self.m_rate_5,
+            RateWindow::FifteenMinutes => self.m_rate_15,
+            RateWindow::Mean => self.m_rate_mean,
+        }
+    }
+}
+
+/// The rates of a Kafka meter. Negative rates are unknown.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct MeterRates {
+    pub rate_1: f64,
+    pub rate_5: f64,
+    pub rate_15: f64,
+    pub rate_mean: f64,
+}
+
+impl MeterRates {
+    pub fn unknown() -> MeterRates {
+        MeterRates {
+            rate_1: -1f64,
+            rate_5: -1f64,
+            rate_15: -1f64,
+            rate_mean: -1f64,
+        }
+    }
+}
+
+/// The averaging window of the rates shown in the traffic views.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RateWindow {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    Mean,
+}
+
+impl RateWindow {
+    pub fn all() -> [RateWindow; 4] {
+        [
+            RateWindow::OneMinute,
+            RateWindow::FiveMinutes,
+            RateWindow::FifteenMinutes,
+            RateWindow::Mean,
+        ]
+    }
+
+    /// Parses the `window` query parameter. Defaults to 15 minutes.
+    pub fn from_param(param: Option<&str>) -> RateWindow {
+        match param {
+            Some("1m") => RateWindow::OneMinute,
+            Some("5m") => RateWindow::FiveMinutes,
+            Some("mean") => RateWindow::Mean,
+            _ => RateWindow::FifteenMinutes,
+        }
+    }
+
+    pub fn param(&self) -> &'static str {
+        match *self {
+            RateWindow::OneMinute => "1m",
+            RateWindow::FiveMinutes => "5m",
+            RateWindow::FifteenMinutes => "15m",
+            RateWindow::Mean => "mean",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            RateWindow::OneMinute => "1 minute",
+            RateWindow::FiveMinutes => "5 minutes",
+            RateWindow::FifteenMinutes => "15 minutes",
+            RateWindow::Mean => "mean",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            RateWindow::OneMinute => "Average over the last minute",
+            RateWindow::FiveMinutes => "Average over the last 5 minutes",
+            RateWindow::FifteenMinutes => "Average over the last 15 minutes",
+            RateWindow::Mean => "Average since the rate is measured",
+        }
+    }
+}
+
+/// Broker level metrics. Missing values could not be read from the broker.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BrokerMetrics {
+    pub bytes_out_rate: Option<f64>,
+    pub failed_produce_rate: Option<f64>,
+    pub failed_fetch_rate: Option<f64>,
+    pub request_handler_idle_ratio: Option<f64>,
+    pub produce_latency_mean_ms: Option<f64>,
+    pub produce_latency_p99_ms: Option<f64>,
+    pub fetch_latency_mean_ms: Option<f64>,
+    pub fetch_latency_p99_ms: Option<f64>,
+    pub under_replicated_partitions: Option<f64>,
+    pub isr_shrink_rate: Option<f64>,
+    pub isr_expand_rate: Option<f64>,
+    pub heap_used_bytes: Option<f64>,
+    pub heap_max_bytes: Option<f64>,
+    pub gc_count: Option<f64>,
+    pub gc_time_ms: Option<f64>,
+}
+
+impl BrokerMetrics {
+    /// Returns the name and the value of every metric.
+    pub fn values(&self) -> Vec<(&'static str, Option<f64>)> {
+        vec![
+            ("bytes_out_rate", self.bytes_out_rate),
+            ("failed_produce_rate", self.failed_produce_rate),
+            ("failed_fetch_rate", self.failed_fetch_rate),
+            ("request_handler_idle_ratio", self.request_handler_idle_ratio),
+            ("produce_latency_mean_ms", self.produce_latency_mean_ms),
+            ("produce_latency_p99_ms", self.produce_latency_p99_ms),
+            ("fetch_latency_mean_ms", self.fetch_latency_mean_ms),
+            ("fetch_latency_p99_ms", self.fetch_latency_p99_ms),
+            ("under_replicated_partitions", self.under_replicated_partitions),
+            ("isr_shrink_rate", self.isr_shrink_rate),
+            ("isr_expand_rate", self.isr_expand_rate),
+            ("heap_used_bytes", self.heap_used_bytes),
+            ("heap_max_bytes", self.heap_max_bytes),
+            ("gc_count", self.gc_count),
+            ("gc_time_ms", self.gc_time_ms),
+        ]
+    }
+
+    /// Returns the metric with the given name, as listed by `values`.
+    pub fn value_mut(&mut self, name: &str) -> Option<&mut Option<f64>> {
+        match name {
+            "bytes_out_rate" => Some(&mut self.bytes_out_rate),
+            "failed_produce_rate" => Some(&mut self.failed_produce_rate),
+            "failed_fetch_rate" => Some(&mut self.failed_fetch_rate),
+            "request_handler_idle_ratio" => Some(&mut self.request_handler_idle_ratio),
+            "produce_latency_mean_ms" => Some(&mut self.produce_latency_mean_ms),
+            "produce_latency_p99_ms" => Some(&mut self.produce_latency_p99_ms),
+            "fetch_latency_mean_ms" => Some(&mut self.fetch_latency_mean_ms),
+            "fetch_latency_p99_ms" => Some(&mut self.fetch_latency_p99_ms),
+            "under_replicated_partitions" => Some(&mut self.under_replicated_partitions),
+            "isr_shrink_rate" => Some(&mut self.isr_shrink_rate),
+            "isr_expand_rate" => Some(&mut self.isr_expand_rate),
+            "heap_used_bytes" => Some(&mut self.heap_used_bytes),
+            "heap_max_bytes" => Some(&mut self.heap_max_bytes),
+            "gc_count" => Some(&mut self.gc_count),
+            "gc_time_ms" => Some(&mut self.gc_time_ms),
+            _ => None,
+        }
+    }
+}
+
+/// Where the rates of a `TopicMetrics` entry come from.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Copy, Clone)]
+pub enum RateSource {
+    /// Read from the brokers through Jolokia or the Prometheus JMX exporter.
+    Agent,
+    /// Estimated by kafka-view from the growth of the high watermarks.
+    Watermarks,
+}
+
+impl RateSource {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            RateSource::Agent => "broker metrics",
+            RateSource::Watermarks => "estimated from high watermarks",
+        }
+    }
+}
+
+impl Default for RateSource {
+    fn default() -> Self {
+        RateSource::Agent
+    }
+}
+
 #[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct TopicMetrics {
     pub brokers: HashMap<i32, TopicBrokerMetrics>,
+    #[serde(default)]
+    pub source: RateSource,
 }
 
 impl TopicMetrics {
@@ -79,6 +317,7 @@ impl TopicMetrics {
 
         TopicMetrics {
             brokers: HashMap::new(),
+            source: RateSource::default(),
         }
     }
 
@@ -97,17 +336,34 @@ impl TopicMetrics {
             }
         }
 
-        self.brokers.iter().fold(
-            TopicBrokerMetrics::default(),
-            |mut acc, (_, broker_metrics)| {
-                acc.m_rate_15 += broker_metrics.m_rate_15;
-                acc.b_rate_15 += broker_metrics.b_rate_15;
-                acc
-            },
-        )
+        if self.brokers.is_empty() {
+            return TopicBrokerMetrics::default();
+        }
+        TopicBrokerMetrics {
+            m_rate_1: sum_known_rates(self.brokers.values().map(|b| b.m_rate_1)),
+            m_rate_5: sum_known_rates(self.brokers.values().map(|b| b.m_rate_5)),
+            m_rate_15: sum_known_rates(self.brokers.values().map(|b| b.m_rate_15)),
+            m_rate_mean: sum_known_rates(self.brokers.values().map(|b| b.m_rate_mean)),
+            b_rate_1: sum_known_rates(self.brokers.values().map(|b| b.b_rate_1)),
+            b_rate_5: sum_known_rates(self.brokers.values().map(|b| b.b_rate_5)),
+            b_rate_15: sum_known_rates(self.brokers.values().map(|b| b.b_rate_15)),
+            b_rate_mean: sum_known_rates(self.brokers.values().map(|b| b.b_rate_mean)),
+            partitions: Vec::new(),
+        }
     }
 }
 
+/// Sums the rates, ignoring the unknown ones (negative). Returns -1 if no rate is known.
+fn sum_known_rates<I: Iterator<Item = f64>>(rates: I) -> f64 {
+    rates.fold(-1f64, |acc, rate| {
+        if rate < 0f64 {
+            acc
+        } else {
+            acc.max(0f64) + rate
+        }
+    })
+}
+
 fn clean_input(input: String) -> String {
     input
         .trim()
@@ -146,34 +402,32 @@ impl Default for TopicMetrics {
     }
 }
 
-fn format_jolokia_path(hostname: &str, port: i32, filter: &str) -> String {
-    format!("http://{}:{}/jolokia/read/{}?ignoreErrors=true&includeStackTrace=false&maxCollectionSize=0",
-            hostname, port, filter)
+fn format_jolokia_url(hostname: &str, port: i32, https: bool) -> String {
+    format!(
+        "{}://{}:{}/jolokia/?ignoreErrors=true&includeStackTrace=false&maxCollectionSize=0",
+        if https { "https" } else { "http" },
+        hostname,
+        port
+    )
 }
 
-fn fetch_metrics_json(hostname: &str, port: i32, filter: &str) -> Result<Value> {
-    let client = Client::new();
-    let url = format_jolokia_path(hostname, port, filter);
-    let mut response = client.get(&url).send().chain_err(|| "Connection error")?;
-
-    let mut body = String::new();
-    response
-        .read_to_string(&mut body)
-        .chain_err(|| "Could not read response to string")?;
-
-    if let Ok(socket) = UdpSocket::bind("0.0.0.0:7075") {
-        let mut buffer = [0u8; 512];
-        //SOURCE
-        if let Ok(size) = socket.recv(&mut buffer) {
-            let input = String::from_utf8_lossy(&buffer[..size]).to_string();
+/// Reads all the rates of the Kafka meters matching the MBean pattern.
+fn jolokia_meter_read(mbean: &str) -> Value {
+    json!({
+        "type": "read",
+        "mbean": mbean,
+        "attribute": ["OneMinuteRate", "FiveMinuteRate", "FifteenMinuteRate", "MeanRate"]
+    })
+}
 
-            corrupt_memory_sink(&input);
-        }
+/// A read operation of a Jolokia bulk request. `path` selects an inner value of a composite
+/// attribute.
+fn jolokia_read(mbean: &str, attribute: &str, path: Option<&str>) -> Value {
+    let mut read = json!({ "type": "read", "mbean": mbean, "attribute": attribute });
+    if let Some(path) = path {
+        read["path"] = json!(path);
     }
-    
-    let value = serde_json::from_str(&body).chain_err(|| "Failed to parse JSON")?;
-
-    Ok(value)
+    read
 }
 
 fn jolokia_response_get_value(json_response: &Value) -> Result<&serde_json::Map<String, Value>> {
@@ -181,6 +435,9 @@ fn jolokia_response_get_value(json_response: &Value) -> Result<&serde_json::Map<
         Some(obj) => obj,
         None => bail!("The provided Value is not a JSON object"),
     };
+    if let Some(error) = obj.get("error") {
+        bail!("Jolokia error: {}", error);
+    }
     if let Some(v) = obj.get("value") {
         if let Some(value_obj) = v.as_object() {
             return Ok(value_obj);
@@ -192,7 +449,17 @@ fn jolokia_response_get_value(json_response: &Value) -> Result<&serde_json::Map<
     }
 }
 
-fn parse_broker_rate_metrics(jolokia_json_response: &Value) -> Result<HashMap<TopicName, f64>> {
+fn meter_rate(meter: &serde_json::Map<String, Value>, attribute: &str) -> Result<f64> {
+    match meter.get(attribute) {
+        Some(&Value::Number(ref rate)) => Ok(rate.as_f64().unwrap_or(-1f64)),
+        None => bail!("Can't find key {} in metric", attribute),
+        _ => bail!("Unexpected metric type"),
+    }
+}
+
+fn parse_broker_rate_metrics(
+    jolokia_json_response: &Value,
+) -> Result<HashMap<TopicName, MeterRates>> {
     let value_map = jolokia_response_get_value(jolokia_json_response)
         .chain_err(|| "Failed to extract 'value' from jolokia response.")?;
     let mut metrics = HashMap::new();
@@ -204,13 +471,13 @@ fn parse_broker_rate_metrics(jolokia_json_response: &Value) -> Result<HashMap<To
             None => "__TOTAL__",
         };
         if let Value::Object(ref obj) = *value {
-            match obj.get("FifteenMinuteRate") {
-                Some(&Value::Number(ref rate)) => {
-                    metrics.insert(topic.to_owned(), rate.as_f64().unwrap_or(-1f64))
-                }
-                None => bail!("Can't find key in metric"),
-                _ => bail!("Unexpected metric type"),
+            let rates = MeterRates {
+                rate_1: meter_rate(obj, "OneMinuteRate")?,
+                rate_5: meter_rate(obj, "FiveMinuteRate")?,
+                rate_15: meter_rate(obj, "FifteenMinuteRate")?,
+                rate_mean: meter_rate(obj, "MeanRate")?,
             };
+            metrics.insert(topic.to_owned(), rates);
         }
     }
     Ok(metrics)
@@ -263,6 +530,7 @@ fn parse_partition_size_metrics(
                 Some(&Value::Number(ref size)) => {
                     let partition_metrics = PartitionMetrics {
                         size_bytes: size.as_f64().unwrap_or(-1f64),
+                        ..Default::default()
                     };
                     insert_at(
                         metrics
@@ -293,6 +561,177 @@ fn log_elapsed_time(task_name: &str, start: DateTime<Utc>) {
     );
 }
 
+/// Returns the numeric value of a Jolokia read response. The values of composite responses, such
+/// as the ones produced by wildcard reads, are summed.
+fn jolokia_number(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Number(ref n) => n.as_f64(),
+        Value::Object(ref obj) => obj
+            .values()
+            .filter_map(jolokia_number)
+            .fold(None, |acc, v| Some(acc.unwrap_or(0f64) + v)),
+        _ => None,
+    }
+}
+
+/// Broker metrics read from Jolokia: field of `BrokerMetrics`, MBean, attribute and inner path.
+const JOLOKIA_BROKER_READS: &[(&str, &str, &str, Option<&str>)] = &[
+    (
+        "bytes_out_rate",
+        "kafka.server:name=BytesOutPerSec,type=BrokerTopicMetrics",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "failed_produce_rate",
+        "kafka.server:name=FailedProduceRequestsPerSec,type=BrokerTopicMetrics",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "failed_fetch_rate",
+        "kafka.server:name=FailedFetchRequestsPerSec,type=BrokerTopicMetrics",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "request_handler_idle_ratio",
+        "kafka.server:name=RequestHandlerAvgIdlePercent,type=KafkaRequestHandlerPool",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "produce_latency_mean_ms",
+        "kafka.network:name=TotalTimeMs,request=Produce,type=RequestMetrics",
+        "Mean",
+        None,
+    ),
+    (
+        "produce_latency_p99_ms",
+        "kafka.network:name=TotalTimeMs,request=Produce,type=RequestMetrics",
+        "99thPercentile",
+        None,
+    ),
+    (
+        "fetch_latency_mean_ms",
+        "kafka.network:name=TotalTimeMs,request=FetchConsumer,type=RequestMetrics",
+        "Mean",
+        None,
+    ),
+    (
+        "fetch_latency_p99_ms",
+        "kafka.network:name=TotalTimeMs,request=FetchConsumer,type=RequestMetrics",
+        "99thPercentile",
+        None,
+    ),
+    (
+        "under_replicated_partitions",
+        "kafka.server:name=UnderReplicatedPartitions,type=ReplicaManager",
+        "Value",
+        None,
+    ),
+    (
+        "isr_shrink_rate",
+        "kafka.server:name=IsrShrinksPerSec,type=ReplicaManager",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "isr_expand_rate",
+        "kafka.server:name=IsrExpandsPerSec,type=ReplicaManager",
+        "FifteenMinuteRate",
+        None,
+    ),
+    (
+        "heap_used_bytes",
+        "java.lang:type=Memory",
+        "HeapMemoryUsage",
+        Some("used"),
+    ),
+    (
+        "heap_max_bytes",
+        "java.lang:type=Memory",
+        "HeapMemoryUsage",
+        Some("max"),
+    ),
+    (
+        "gc_count",
+        "java.lang:name=*,type=GarbageCollector",
+        "CollectionCount",
+        None,
+    ),
+    (
+        "gc_time_ms",
+        "java.lang:name=*,type=GarbageCollector",
+        "CollectionTime",
+        None,
+    ),
+];
+
+/// Reads the metrics of a broker from its Jolokia agent, using a single bulk request. The
+/// responses of a bulk request are returned in the same order as the reads.
+fn fetch_jolokia_metrics(
+    hostname: &str,
+    port: i32,
+    https: bool,
+    auth: Option<&BasicAuth>,
+    timeouts: &HttpTimeouts,
+) -> Result<BrokerMetricsReading> {
+    let mut reads = vec![
+        jolokia_meter_read("kafka.server:name=BytesInPerSec,*,type=BrokerTopicMetrics"),
+        jolokia_meter_read("kafka.server:name=MessagesInPerSec,*,type=BrokerTopicMetrics"),
+        jolokia_read("kafka.log:name=Size,*,type=Log", "Value", None),
+    ];
+    reads.extend(
+        JOLOKIA_BROKER_READS
+            .iter()
+            .map(|&(_, mbean, attribute, path)| jolokia_read(mbean, attribute, path)),
+    );
+    let read_count = reads.len();
+
+    let url = format_jolokia_url(hostname, port, https);
+    let body = http_fetch(&url, Some(Value::Array(reads).to_string()), auth, timeouts)
+        .chain_err(|| format!("Failed to fetch metrics from {}", hostname))?;
+    let responses: Vec<Value> =
+        serde_json::from_str(&body).chain_err(|| "Failed to parse JSON")?;
+    if responses.len() != read_count {
+        bail!(
+            "Expected {} Jolokia responses, got {}",
+            read_count,
+            responses.len()
+        );
+    }
+
+    let byte_rates = parse_broker_rate_metrics(&responses[0])
+        .chain_err(|| "Failed to parse byte rate broker metrics")?;
+
+    let msg_rates = parse_broker_rate_metrics(&responses[1])
+        .chain_err(|| "Failed to parse message rate broker metrics")?;
+
+    let partition_sizes = parse_partition_size_metrics(&responses[2])
+        .chain_err(|| "Failed to parse partition size broker metrics")?;
+
+    // Not every metric is available in every Kafka version: missing ones are left empty.
+    let mut broker = BrokerMetrics::default();
+    let broker_responses = JOLOKIA_BROKER_READS.iter().zip(&responses[3..]);
+    for (&(name, mbean, attribute, _), response) in broker_responses {
+        let value = response.get("value").and_then(jolokia_number);
+        if value.is_none() {
+            debug!("Metric {}/{} not available on {}", mbean, attribute, hostname);
+        }
+        if let Some(field) = broker.value_mut(name) {
+            *field = value;
+        }
+    }
+
+    Ok(BrokerMetricsReading {
+        byte_rates,
+        msg_rates,
+        partition_sizes,
+        broker,
+    })
+}
+
 pub struct MetricsFetchTaskGroup {
     cache: Cache,
     config: Config,
@@ -306,111 +745,95 @@ impl MetricsFetchTaskGroup {
         }
     }
 
-    fn fetch_metrics(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<()> {
+    fn fetch_metrics(
+        &self,
+        cluster_id: &ClusterId,
+        broker: &Broker,
+        source: &MetricsSource,
+    ) -> Result<()> {
         let start = Utc::now();
-        let byte_rate_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.server:name=BytesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate",
-        )
-        .chain_err(|| format!("Failed to fetch byte rate metrics from {}", broker.hostname))?;
-
-        let byte_rate_metrics = parse_broker_rate_metrics(&byte_rate_json)
-            .chain_err(|| "Failed to parse byte rate broker metrics")?;
-
-        let msg_rate_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.server:name=MessagesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate",
-        )
-        .chain_err(|| {
-            format!(
-                "Failed to fetch message rate metrics from {}",
-                broker.hostname
-            )
-        })?;
-
-        if let Ok(listener) = TcpListener::bind("0.0.0.0:6060") {
-            if let Ok((mut stream, _)) = listener.accept() {
-                let mut buf = Vec::new();
-
-                //SOURCE
-                if stream.read_to_end(&mut buf).is_ok() {
-                    if let Ok(input) = String::from_utf8(buf) {
-                        if let Ok(limit) = input.trim().parse::<i32>() {
-                            crate::utils::process_limit(limit);
-                        }
-                    }
-                }
+        let timeouts = self.config.metrics_timeouts();
+        let reading = match *source {
+            MetricsSource::Jolokia {
+                port,
+                https,
+                ref auth,
+            } => fetch_jolokia_metrics(&broker.hostname, port, https, auth.as_ref(), &timeouts),
+            MetricsSource::Prometheus { port, ref path } => {
+                fetch_prometheus_metrics(&broker.hostname, port, path, &timeouts)
             }
         }
-
-        let msg_rate_metrics = parse_broker_rate_metrics(&msg_rate_json)
-            .chain_err(|| "Failed to parse message rate broker metrics")?;
-        let partition_metrics_json = fetch_metrics_json(
-            &broker.hostname,
-            port,
-            "kafka.log:name=Size,*,type=Log/Value",
-        )
-        .chain_err(|| {
-            format!(
-                "Failed to fetch partition size metrics from {}",
-                broker.hostname
-            )
-        })?;
-
-        let pt_size_metrics = parse_partition_size_metrics(&partition_metrics_json)
-            .chain_err(|| "Failed to parse partition size broker metrics")?;
-
-        let topics = byte_rate_metrics
+        .chain_err(|| format!("Failed to read metrics of {}", broker.hostname))?;
+        let log_dirs_enabled = self
+            .config
+            .cluster(cluster_id)
+            .map(|cluster_config| cluster_config.log_dirs_enabled())
+            .unwrap_or(false);
+
+        let topics = reading
+            .byte_rates
             .keys()
-            .chain(msg_rate_metrics.keys())
-            .chain(pt_size_metrics.keys())
+            .chain(reading.msg_rates.keys())
+            .chain(reading.partition_sizes.keys())
             .collect::<HashSet<_>>();
 
         for topic in topics {
-            let mut topic_metrics = self
-                .cache
-                .metrics
-                .get(&(cluster_id.clone(), topic.clone()))
-                .unwrap_or_default();
-
-            let b_rate_15 = *byte_rate_metrics.get(topic).unwrap_or(&-1f64);
-            let m_rate_15 = *msg_rate_metrics.get(topic).unwrap_or(&-1f64);
-            let partitions = pt_size_metrics.get(topic).cloned().unwrap_or_else(Vec::new);
-            topic_metrics.brokers.insert(
-                broker.id,
-                TopicBrokerMetrics {
-                    m_rate_15,
-                    b_rate_15,
-                    partitions,
-                },
-            );
-
+            let unknown = MeterRates::unknown();
+            let byte_rates = reading.byte_rates.get(topic).unwrap_or(&unknown);
+            let msg_rates = reading.msg_rates.get(topic).unwrap_or(&unknown);
             self.cache
                 .metrics
-                .insert((cluster_id.clone(), topic.clone()), topic_metrics)
+                .update((cluster_id.clone(), topic.clone()), |topic_metrics| {
+                    let mut topic_metrics = topic_metrics.unwrap_or_default();
+                    // Partition sizes are read with DescribeLogDirs when enabled.
+                    let partitions = if log_dirs_enabled {
+                        topic_metrics
+                            .brokers
+                            .get(&broker.id)
+                            .map(|broker_metrics| broker_metrics.partitions.clone())
+                            .unwrap_or_else(Vec::new)
+                    } else {
+                        reading
+                            .partition_sizes
+                            .get(topic)
+                            .cloned()
+                            .unwrap_or_else(Vec::new)
+                    };
+                    topic_metrics.source = RateSource::Agent;
+                    topic_metrics.brokers.insert(
+                        broker.id,
+                        TopicBrokerMetrics {
+                            partitions,
+                            ..TopicBrokerMetrics::with_rates(byte_rates, msg_rates)
+                        },
+                    );
+                    Some(topic_metrics)
+                })
                 .chain_err(|| "Failed to insert to metrics")?;
         }
+        self.cache
+            .broker_metrics
+            .insert((cluster_id.clone(), broker.id), reading.broker)
+            .chain_err(|| "Failed to insert to broker metrics")?;
         log_elapsed_time("metrics fetch", start);
         Ok(())
     }
 }
 
 impl TaskGroup for MetricsFetchTaskGroup {
-    type TaskId = (ClusterId, Broker, i32);
+    type TaskId = (ClusterId, Broker, MetricsSource);
 
     fn get_tasks(&self) -> Vec<Self::TaskId> {
         self.cache.brokers.lock_iter(|iter| {
             let mut tasks = Vec::new();
             for (cluster_id, brokers) in iter {
-                let port = self
+                let source = self
                     .config
                     .cluster(cluster_id)
-                    .and_then(|cluster_config| cluster_config.jolokia_port);
-                if port.is_some() {
+                    .and_then(|cluster_config| cluster_config.metrics_source());
+                if let Some(source) = source {
                     for broker in brokers {
-                        tasks.push((cluster_id.clone(), broker.clone(), port.unwrap()));
+                        tasks.push((cluster_id.clone(), broker.clone(), source.clone()));
                     }
                 }
             }
@@ -419,9 +842,13 @@ impl TaskGroup for MetricsFetchTaskGroup {
         })
     }
 
-    fn execute(&self, task_id: (ClusterId, Broker, i32)) {
+    fn execute(&self, task_id: (ClusterId, Broker, MetricsSource)) {
         debug!("Starting fetch for {}: {}", task_id.0, task_id.1.id);
-        if let Err(e) = self.fetch_metrics(&task_id.0, &task_id.1, task_id.2) {
+        let start = Instant::now();
+        let result = self.fetch_metrics(&task_id.0, &task_id.1, &task_id.2);
+        let target = format!("{}:{}", task_id.0, task_id.1.id);
+        TASK_STATS.record("metrics", &target, start.elapsed(), result.is_ok());
+        if let Err(e) = result {
             format_error_chain!(e);
         }
     }
@@ -0,0 +1,346 @@
+use isahc;
+use isahc::auth::{Authentication, Credentials};
+use isahc::config::Configurable;
+use isahc::{ReadResponseExt, Request};
+
+use config::BasicAuth;
+use error::*;
+use metadata::TopicName;
+use metrics::{BrokerMetrics, MeterRates, PartitionMetrics};
+use utils::insert_at;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Where the metrics of the brokers of a cluster are read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricsSource {
+    Jolokia {
+        port: i32,
+        https: bool,
+        auth: Option<BasicAuth>,
+    },
+    Prometheus {
+        port: i32,
+        path: String,
+    },
+}
+
+/// Timeouts of the requests to the metrics agents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpTimeouts {
+    pub connect: Duration,
+    pub read: Duration, // covers the whole request, including the transfer of the response
+}
+
+/// Sends a request to a metrics agent and returns the body of the response. The request is a
+/// POST if a body is provided, a GET otherwise.
+pub fn http_fetch(
+    url: &str,
+    body: Option<String>,
+    auth: Option<&BasicAuth>,
+    timeouts: &HttpTimeouts,
+) -> Result<String> {
+    let builder = if body.is_some() {
+        Request::post(url).header("Content-Type", "application/json")
+    } else {
+        Request::get(url)
+    };
+    let mut builder = builder
+        .connect_timeout(timeouts.connect)
+        .timeout(timeouts.read);
+    if let Some(auth) = auth {
+        builder = builder
+            .authentication(Authentication::basic())
+            .credentials(Credentials::new(
+                auth.username.as_str(),
+                auth.password.as_str(),
+            ));
+    }
+    let request = builder
+        .body(body.unwrap_or_default())
+        .chain_err(|| "Failed to build request")?;
+
+    let mut response = isahc::send(request).chain_err(|| format!("Connection error: {}", url))?;
+    if !response.status().is_success() {
+        bail!(
+            "Request to {} failed with status {}",
+            url,
+            response.status()
+        );
+    }
+    response
+        .text()
+        .chain_err(|| "Could not read response to string")
+}
+
+/// Metrics read from a single broker. Broker wide rates are stored under the `__TOTAL__` topic.
+#[derive(Debug, Default)]
+pub struct BrokerMetricsReading {
+    pub byte_rates: HashMap<TopicName, MeterRates>,
+    pub msg_rates: HashMap<TopicName, MeterRates>,
+    pub partition_sizes: HashMap<TopicName, Vec<PartitionMetrics>>,
+    pub broker: BrokerMetrics,
+}
+
+/// A single sample of the Prometheus text exposition format.
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub labels: HashMap<String, String>,
+    pub value: f64,
+}
+
+fn parse_value(s: &str) -> Result<f64> {
+    match s {
+        "+Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => s
+            .parse::<f64>()
+            .chain_err(|| format!("Invalid sample value: {}", s)),
+    }
+}
+
+/// Parses the label set following the metric name, including the braces. Returns the labels and
+/// the rest of the line.
+fn parse_labels(s: &str) -> Result<(HashMap<String, String>, &str)> {
+    let mut labels = HashMap::new();
+    let mut rest = s[1..].trim_start();
+    loop {
+        if rest.starts_with('}') {
+            return Ok((labels, &rest[1..]));
+        }
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => bail!("Missing '=' in label set"),
+        };
+        let name = rest[..eq].trim().to_owned();
+        rest = rest[eq + 1..].trim_start();
+        if !rest.starts_with('"') {
+            bail!("Label value is not quoted");
+        }
+        let mut value = String::new();
+        let mut end = None;
+        let mut chars = rest.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                '"' => {
+                    end = Some(i);
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        match end {
+            Some(i) => rest = rest[i + 1..].trim_start(),
+            None => bail!("Unterminated label value"),
+        };
+        labels.insert(name, value);
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        }
+    }
+}
+
+fn parse_sample(line: &str) -> Result<Sample> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .unwrap_or_else(|| line.len());
+    let name = line[..name_end].to_owned();
+    let (labels, rest) = if line[name_end..].starts_with('{') {
+        parse_labels(&line[name_end..])?
+    } else {
+        (HashMap::new(), &line[name_end..])
+    };
+    // An optional timestamp can follow the value.
+    let value = match rest.split_whitespace().next() {
+        Some(value) => parse_value(value)?,
+        None => bail!("Missing sample value"),
+    };
+    Ok(Sample {
+        name,
+        labels,
+        value,
+    })
+}
+
+/// Parses a document in the Prometheus text exposition format. Comments and type information
+/// are ignored.
+pub fn parse_prometheus_text(body: &str) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for (n, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sample =
+            parse_sample(line).chain_err(|| format!("Failed to parse line {}: {}", n + 1, line))?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+fn add_to(field: &mut Option<f64>, value: f64) {
+    *field = Some(field.unwrap_or(0f64) + value);
+}
+
+/// Extracts the broker level metrics from a sample, if it contains any.
+fn read_broker_metric(broker: &mut BrokerMetrics, name: &str, mbean_name: &str, sample: &Sample) {
+    let label = |key: &str| sample.labels.get(key).map(|value| value.to_lowercase());
+    let value = Some(sample.value);
+    if name.contains("brokertopicmetrics") && name.ends_with("fifteenminuterate") {
+        if label("topic").is_none() {
+            if is_mbean(mbean_name, "bytesoutpersec") {
+                broker.bytes_out_rate = value;
+            } else if mbean_name.contains("failedproducerequests") {
+                broker.failed_produce_rate = value;
+            } else if mbean_name.contains("failedfetchrequests") {
+                broker.failed_fetch_rate = value;
+            }
+        }
+    } else if mbean_name.contains("requesthandleravgidlepercent")
+        && name.ends_with("fifteenminuterate")
+    {
+        broker.request_handler_idle_ratio = value;
+    } else if name.contains("requestmetrics") && mbean_name.contains("totaltimems") {
+        let request = label("request");
+        let is_mean = name.ends_with("_mean");
+        let is_p99 = name.ends_with("99thpercentile") || label("quantile") == Some("0.99".into());
+        match (request.as_ref().map(String::as_str), is_mean, is_p99) {
+            (Some("produce"), true, _) => broker.produce_latency_mean_ms = value,
+            (Some("produce"), _, true) => broker.produce_latency_p99_ms = value,
+            (Some("fetchconsumer"), true, _) => broker.fetch_latency_mean_ms = value,
+            (Some("fetchconsumer"), _, true) => broker.fetch_latency_p99_ms = value,
+            _ => {}
+        }
+    } else if name.contains("replicamanager") {
+        if mbean_name.contains("underreplicatedpartitions") {
+            broker.under_replicated_partitions = value;
+        } else if mbean_name.contains("isrshrinks") && name.ends_with("fifteenminuterate") {
+            broker.isr_shrink_rate = value;
+        } else if mbean_name.contains("isrexpands") && name.ends_with("fifteenminuterate") {
+            broker.isr_expand_rate = value;
+        }
+    } else if name == "java_lang_memory_heapmemoryusage_used"
+        || (name == "jvm_memory_bytes_used" && label("area") == Some("heap".into()))
+    {
+        broker.heap_used_bytes = value;
+    } else if name == "java_lang_memory_heapmemoryusage_max"
+        || (name == "jvm_memory_bytes_max" && label("area") == Some("heap".into()))
+    {
+        broker.heap_max_bytes = value;
+    } else if name == "java_lang_garbagecollector_collectioncount"
+        || name == "jvm_gc_collection_seconds_count"
+    {
+        add_to(&mut broker.gc_count, sample.value);
+    } else if name == "java_lang_garbagecollector_collectiontime" {
+        add_to(&mut broker.gc_time_ms, sample.value);
+    } else if name == "jvm_gc_collection_seconds_sum" {
+        add_to(&mut broker.gc_time_ms, sample.value * 1000f64);
+    }
+}
+
+/// Returns the rate of a meter the sample refers to, if it's a meter rate.
+fn meter_rate<'a>(rates: &'a mut MeterRates, name: &str) -> Option<&'a mut f64> {
+    if name.ends_with("oneminuterate") {
+        Some(&mut rates.rate_1)
+    } else if name.ends_with("fiveminuterate") {
+        Some(&mut rates.rate_5)
+    } else if name.ends_with("fifteenminuterate") {
+        Some(&mut rates.rate_15)
+    } else if name.ends_with("meanrate") {
+        Some(&mut rates.rate_mean)
+    } else {
+        None
+    }
+}
+
+/// True if the sample refers to the given MBean, either through its `name` label or as part of
+/// the metric name. Names are matched exactly, as `ReplicationBytesInPerSec` and similar MBeans
+/// share their suffix with the topic metrics.
+fn is_mbean(mbean_name: &str, mbean: &str) -> bool {
+    mbean_name == mbean || mbean_name.contains(&format!("_{}_", mbean))
+}
+
+/// Extracts the broker metrics from the samples produced by the Prometheus JMX exporter. Both the
+/// default naming (`kafka_server_BrokerTopicMetrics_FifteenMinuteRate{name="BytesInPerSec"}`) and
+/// the naming of the common rule sets (`kafka_server_brokertopicmetrics_bytesinpersec_fifteenminuterate`)
+/// are recognized.
+pub fn prometheus_reading(samples: &[Sample]) -> BrokerMetricsReading {
+    let mut reading = BrokerMetricsReading::default();
+    for sample in samples {
+        let name = sample.name.to_lowercase();
+        let mbean_name = sample
+            .labels
+            .get("name")
+            .map(|name| name.to_lowercase())
+            .unwrap_or_else(|| name.clone());
+        read_broker_metric(&mut reading.broker, &name, &mbean_name, sample);
+        let meter = if !name.contains("brokertopicmetrics") || !name.ends_with("rate") {
+            None
+        } else if is_mbean(&mbean_name, "bytesinpersec") {
+            Some(&mut reading.byte_rates)
+        } else if is_mbean(&mbean_name, "messagesinpersec") {
+            Some(&mut reading.msg_rates)
+        } else {
+            None
+        };
+        if let Some(meter) = meter {
+            let topic = sample
+                .labels
+                .get("topic")
+                .cloned()
+                .unwrap_or_else(|| "__TOTAL__".to_owned());
+            let rates = meter.entry(topic).or_insert_with(MeterRates::unknown);
+            if let Some(rate) = meter_rate(rates, &name) {
+                *rate = sample.value;
+            }
+        } else if name.starts_with("kafka_log_log") && mbean_name.ends_with("size") {
+            let topic = sample.labels.get("topic");
+            let partition = sample
+                .labels
+                .get("partition")
+                .and_then(|p| p.parse::<u32>().ok());
+            if let (Some(topic), Some(partition)) = (topic, partition) {
+                insert_at(
+                    reading
+                        .partition_sizes
+                        .entry(topic.to_owned())
+                        .or_insert_with(Vec::new),
+                    partition as usize,
+                    PartitionMetrics {
+                        size_bytes: sample.value,
+                        ..Default::default()
+                    },
+                    PartitionMetrics::default(),
+                );
+            }
+        }
+    }
+    reading
+}
+
+/// Reads the metrics of a broker from the HTTP endpoint of the Prometheus JMX exporter.
+pub fn fetch_prometheus_metrics(
+    hostname: &str,
+    port: i32,
+    path: &str,
+    timeouts: &HttpTimeouts,
+) -> Result<BrokerMetricsReading> {
+    let url = if path.starts_with('/') {
+        format!("http://{}:{}{}", hostname, port, path)
+    } else {
+        format!("http://{}:{}/{}", hostname, port, path)
+    };
+    let body = http_fetch(&url, None, None, timeouts)?;
+
+    let samples =
+        parse_prometheus_text(&body).chain_err(|| "Failed to parse Prometheus metrics")?;
+    Ok(prometheus_reading(&samples))
+}
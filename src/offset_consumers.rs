@@ -0,0 +1,118 @@
+use chrono::Utc;
+
+use metadata::ClusterId;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+lazy_static! {
+    pub static ref OFFSET_CONSUMERS: OffsetConsumerRegistry = OffsetConsumerRegistry::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetConsumerState {
+    Starting,
+    Running,
+    Restarting,
+}
+
+impl OffsetConsumerState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OffsetConsumerState::Starting => "Starting",
+            OffsetConsumerState::Running => "Running",
+            OffsetConsumerState::Restarting => "Restarting",
+        }
+    }
+}
+
+/// Health of the consumer of `__consumer_offsets` of a cluster.
+#[derive(Debug, Clone)]
+pub struct OffsetConsumerStatus {
+    pub state: OffsetConsumerState,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+    pub last_error_time: Option<i64>, // unix timestamp
+    pub messages: u64,
+    pub messages_rate: f64,
+    pub last_message_timestamp: Option<i64>, // millis since epoch, from the message
+    pub lag: Option<i64>,                    // messages behind the high watermarks
+}
+
+impl Default for OffsetConsumerStatus {
+    fn default() -> OffsetConsumerStatus {
+        OffsetConsumerStatus {
+            state: OffsetConsumerState::Starting,
+            restarts: 0,
+            last_error: None,
+            last_error_time: None,
+            messages: 0,
+            messages_rate: 0f64,
+            last_message_timestamp: None,
+            lag: None,
+        }
+    }
+}
+
+/// Tracks the state of the offset consumer threads of this instance. Unlike the cache, it's not
+/// replicated: each instance runs its own consumers.
+pub struct OffsetConsumerRegistry {
+    statuses: RwLock<HashMap<ClusterId, OffsetConsumerStatus>>,
+}
+
+impl OffsetConsumerRegistry {
+    pub fn new() -> OffsetConsumerRegistry {
+        OffsetConsumerRegistry {
+            statuses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn update<F>(&self, cluster_id: &ClusterId, update_fn: F)
+    where
+        F: FnOnce(&mut OffsetConsumerStatus),
+    {
+        match self.statuses.write() {
+            Ok(mut statuses) => {
+                let status = statuses
+                    .entry(cluster_id.clone())
+                    .or_insert_with(OffsetConsumerStatus::default);
+                update_fn(status);
+            }
+            Err(_) => panic!("Poison error while writing offset consumer status"),
+        };
+    }
+
+    pub fn set_state(&self, cluster_id: &ClusterId, state: OffsetConsumerState) {
+        self.update(cluster_id, |status| status.state = state);
+    }
+
+    /// Records an error of a running consumer. The consumer is only restarted if the errors
+    /// persist.
+    pub fn record_error(&self, cluster_id: &ClusterId, error: String) {
+        self.update(cluster_id, |status| {
+            status.last_error = Some(error);
+            status.last_error_time = Some(Utc::now().timestamp());
+        });
+    }
+
+    /// Records the error that stopped the consumer, before it's restarted.
+    pub fn record_failure(&self, cluster_id: &ClusterId, error: String) {
+        self.update(cluster_id, |status| {
+            status.state = OffsetConsumerState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(error);
+            status.last_error_time = Some(Utc::now().timestamp());
+            status.messages_rate = 0f64;
+        });
+    }
+
+    pub fn snapshot(&self) -> Vec<(ClusterId, OffsetConsumerStatus)> {
+        match self.statuses.read() {
+            Ok(statuses) => statuses
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>(),
+            Err(_) => panic!("Poison error while reading offset consumer status"),
+        }
+    }
+}
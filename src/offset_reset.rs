@@ -0,0 +1,358 @@
+use chrono::DateTime;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, EmptyConsumerContext};
+use rdkafka::{Offset, TopicPartitionList};
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use kafka_protocol::BrokerConnection;
+use metadata::{BrokerId, ClusterId, TopicName, CONSUMERS};
+use offsets::OffsetStore;
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Where the committed offsets of a group should be moved to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetStrategy {
+    Earliest,
+    Latest,
+    Offset(i64),
+    Timestamp(i64), // millis
+    Shift(i64),
+}
+
+impl ResetStrategy {
+    /// Parses the strategy selected in the reset form. Timestamps can be given in millis or in
+    /// RFC 3339 format.
+    pub fn parse(strategy: &str, value: &str) -> Result<ResetStrategy> {
+        let value = value.trim();
+        let parse_number = || {
+            value
+                .parse::<i64>()
+                .chain_err(|| format!("Invalid number: '{}'", value))
+        };
+        let strategy = match strategy {
+            "earliest" => ResetStrategy::Earliest,
+            "latest" => ResetStrategy::Latest,
+            "offset" => ResetStrategy::Offset(parse_number()?),
+            "shift" => ResetStrategy::Shift(parse_number()?),
+            "timestamp" => match DateTime::parse_from_rfc3339(value) {
+                Ok(time) => ResetStrategy::Timestamp(time.timestamp() * 1000),
+                Err(_) => ResetStrategy::Timestamp(parse_number()?),
+            },
+            _ => bail!("Unknown reset strategy: '{}'", strategy),
+        };
+        Ok(strategy)
+    }
+}
+
+/// The change of the committed offset of a partition.
+#[derive(Debug, Clone, Serialize)]
+pub struct OffsetResetPlan {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub current: i64, // -1 if there's no committed offset
+    pub new: i64,
+}
+
+/// The partitions to reset and how. Without a topic, all the topics with committed offsets are
+/// selected; without partitions, all the partitions of the selected topics.
+#[derive(Debug, Clone)]
+pub struct OffsetResetRequest {
+    pub topic: Option<TopicName>,
+    pub partitions: Option<Vec<i32>>,
+    pub strategy: ResetStrategy,
+    pub dry_run: bool,
+}
+
+impl OffsetResetRequest {
+    /// Builds the request from the fields of the reset form.
+    pub fn parse(
+        topic: &str,
+        partitions: &str,
+        strategy: &str,
+        value: &str,
+        dry_run: bool,
+    ) -> Result<OffsetResetRequest> {
+        let topic = topic.trim();
+        Ok(OffsetResetRequest {
+            topic: if topic.is_empty() {
+                None
+            } else {
+                Some(topic.to_owned())
+            },
+            partitions: parse_partitions(partitions)?,
+            strategy: ResetStrategy::parse(strategy, value)?,
+            dry_run,
+        })
+    }
+}
+
+/// Parses a comma separated list of partitions. An empty list selects all the partitions.
+fn parse_partitions(partitions: &str) -> Result<Option<Vec<i32>>> {
+    let partitions = partitions
+        .split(',')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.parse::<i32>()
+                .chain_err(|| format!("Invalid partition: '{}'", p))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(if partitions.is_empty() {
+        None
+    } else {
+        Some(partitions)
+    })
+}
+
+/// Fails if the group has members: their commits would override the new offsets. The group is
+/// described again, as the cached description might be outdated.
+pub fn check_group_inactive(cluster_id: &ClusterId, group: &str) -> Result<()> {
+    let consumer = CONSUMERS.get_err(cluster_id)?;
+    let group_list = consumer
+        .fetch_group_list(Some(group), 10000)
+        .chain_err(|| format!("Failed to describe group {}", group))?;
+    for rd_group in group_list.groups() {
+        if !rd_group.members().is_empty() {
+            bail!(
+                "Group {} has {} active members, stop them before resetting its offsets",
+                group,
+                rd_group.members().len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Offsets of the first messages produced at or after the timestamp, for each partition.
+/// Partitions without such messages are set to their high watermark by the caller.
+fn offsets_for_timestamp(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    topic: &str,
+    partitions: &[i32],
+    timestamp: i64,
+) -> Result<HashMap<i32, i64>> {
+    let topic_partitions = cache
+        .topics
+        .get(&(cluster_id.clone(), topic.to_owned()))
+        .unwrap_or_default();
+    let mut by_leader: BTreeMap<BrokerId, Vec<i32>> = BTreeMap::new();
+    for p in topic_partitions
+        .iter()
+        .filter(|p| partitions.contains(&p.id))
+    {
+        by_leader
+            .entry(p.leader)
+            .or_insert_with(Vec::new)
+            .push(p.id);
+    }
+
+    let brokers = cache.brokers.get(cluster_id).unwrap_or_default();
+    let mut offsets = HashMap::new();
+    for (leader, leader_partitions) in by_leader {
+        let broker = brokers
+            .iter()
+            .find(|b| b.id == leader)
+            .ok_or_else(|| Error::from(format!("Unknown leader broker {}", leader)))?;
+        let mut connection =
+            BrokerConnection::connect(&broker.hostname, broker.port, Duration::from_secs(10))?;
+        let partition_offsets = connection
+            .list_offsets(topic, &leader_partitions, timestamp)
+            .chain_err(|| format!("Failed to list offsets on {}", broker.hostname))?;
+        for p in partition_offsets {
+            if p.error_code != 0 {
+                bail!(
+                    "ListOffsets failed for {}/{} with error code {}",
+                    topic,
+                    p.partition,
+                    p.error_code
+                );
+            }
+            if p.offset >= 0 {
+                offsets.insert(p.partition, p.offset);
+            }
+        }
+    }
+    Ok(offsets)
+}
+
+/// Computes the new offsets of the selected topics and partitions of a group, without changing
+/// anything. The new offsets are always within the watermarks of the partitions.
+pub fn plan_offset_reset(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    group: &str,
+    request: &OffsetResetRequest,
+) -> Result<Vec<OffsetResetPlan>> {
+    let committed = cache
+        .offsets_by_cluster_group(cluster_id, group)
+        .into_iter()
+        .map(|((_, _, topic), offsets)| (topic, offsets))
+        .collect::<HashMap<_, _>>();
+    let mut topics = match request.topic {
+        Some(ref topic) => vec![topic.clone()],
+        None => committed.keys().cloned().collect::<Vec<_>>(),
+    };
+    topics.sort();
+    if topics.is_empty() {
+        bail!("Group {} has no committed offsets", group);
+    }
+
+    let consumer = CONSUMERS.get_err(cluster_id)?;
+    let mut plans = Vec::new();
+    for topic in topics {
+        let topic_partition_ids = cache
+            .topics
+            .get(&(cluster_id.clone(), topic.clone()))
+            .ok_or_else(|| Error::from(format!("Unknown topic {}", topic)))?
+            .iter()
+            .map(|p| p.id)
+            .collect::<Vec<_>>();
+        let partition_ids = match request.partitions {
+            Some(ref selected) => {
+                if let Some(id) = selected.iter().find(|id| !topic_partition_ids.contains(id)) {
+                    bail!("Unknown partition {} of topic {}", id, topic);
+                }
+                topic_partition_ids
+                    .into_iter()
+                    .filter(|id| selected.contains(id))
+                    .collect::<Vec<_>>()
+            }
+            None => topic_partition_ids,
+        };
+        let timestamp_offsets = match request.strategy {
+            ResetStrategy::Timestamp(timestamp) => {
+                offsets_for_timestamp(cache, cluster_id, &topic, &partition_ids, timestamp)?
+            }
+            _ => HashMap::new(),
+        };
+
+        for partition in partition_ids {
+            let (low, high) = consumer
+                .fetch_watermarks(&topic, partition, 10000)
+                .chain_err(|| format!("Failed to fetch watermarks of {}/{}", topic, partition))?;
+            let current = committed
+                .get(&topic)
+                .and_then(|offsets| offsets.get(partition as usize))
+                .cloned()
+                .unwrap_or(-1);
+            let new = match request.strategy {
+                ResetStrategy::Earliest => low,
+                ResetStrategy::Latest => high,
+                ResetStrategy::Offset(offset) => offset,
+                ResetStrategy::Timestamp(_) => *timestamp_offsets.get(&partition).unwrap_or(&high),
+                ResetStrategy::Shift(shift) => {
+                    if current < 0 {
+                        bail!("No committed offset to shift on {}/{}", topic, partition);
+                    }
+                    current + shift
+                }
+            };
+            plans.push(OffsetResetPlan {
+                topic: topic.clone(),
+                partition,
+                current,
+                new: new.max(low).min(high),
+            });
+        }
+    }
+    if plans.is_empty() {
+        bail!("No partition to reset for group {}", group);
+    }
+    Ok(plans)
+}
+
+/// Commits the new offsets on behalf of the group.
+pub fn apply_offset_reset(brokers: &str, group: &str, plans: &[OffsetResetPlan]) -> Result<()> {
+    let consumer = ClientConfig::new()
+        .set("group.id", group)
+        .set("bootstrap.servers", brokers)
+        .set("enable.auto.commit", "false")
+        .set("api.version.request", "true")
+        .create::<BaseConsumer<EmptyConsumerContext>>()
+        .chain_err(|| "Failed to create offset reset consumer")?;
+
+    let mut tp_list = TopicPartitionList::new();
+    for plan in plans {
+        tp_list.add_partition_offset(&plan.topic, plan.partition, Offset::Offset(plan.new));
+    }
+    consumer
+        .commit(&tp_list, CommitMode::Sync)
+        .chain_err(|| format!("Failed to commit the new offsets of {}", group))?;
+    info!("Offsets of group {} reset: {:?}", group, plans);
+    Ok(())
+}
+
+/// Computes the new offsets and, unless it's a dry run, commits them. The reset is refused if
+/// the group has active members.
+pub fn reset_offsets(
+    cache: &Cache,
+    config: &Config,
+    cluster_id: &ClusterId,
+    group: &str,
+    request: &OffsetResetRequest,
+) -> Result<Vec<OffsetResetPlan>> {
+    let plans = plan_offset_reset(cache, cluster_id, group, request)?;
+    if !request.dry_run {
+        let brokers = config
+            .cluster(cluster_id)
+            .map(|cluster| cluster.bootstrap_servers())
+            .ok_or_else(|| Error::from(format!("Unknown cluster {}", cluster_id)))?;
+        check_group_inactive(cluster_id, group)?;
+        apply_offset_reset(&brokers, group, &plans)?;
+    }
+    Ok(plans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_strategies() {
+        let cases = vec![
+            ("earliest", "", Some(ResetStrategy::Earliest)),
+            ("latest", "ignored", Some(ResetStrategy::Latest)),
+            ("offset", " 42 ", Some(ResetStrategy::Offset(42))),
+            ("shift", "-10", Some(ResetStrategy::Shift(-10))),
+            ("timestamp", "1000", Some(ResetStrategy::Timestamp(1000))),
+            (
+                "timestamp",
+                "1970-01-01T00:00:02Z",
+                Some(ResetStrategy::Timestamp(2000)),
+            ),
+            ("offset", "", None),
+            ("shift", "ten", None),
+            ("timestamp", "yesterday", None),
+            ("unknown", "1", None),
+        ];
+        for (strategy, value, expected) in cases {
+            let parsed = ResetStrategy::parse(strategy, value).ok();
+            assert_eq!(parsed, expected, "{} {}", strategy, value);
+        }
+    }
+
+    #[test]
+    fn partition_lists() {
+        let cases = vec![
+            ("", Some(None)),
+            (" , ", Some(None)),
+            ("3", Some(Some(vec![3]))),
+            ("0, 2,5", Some(Some(vec![0, 2, 5]))),
+            ("1,x", None),
+            ("1.5", None),
+        ];
+        for (partitions, expected) in cases {
+            assert_eq!(
+                parse_partitions(partitions).ok(),
+                expected,
+                "{}",
+                partitions
+            );
+        }
+    }
+}
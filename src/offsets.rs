@@ -4,32 +4,159 @@ use rdkafka::config::{ClientConfig, TopicConfig};
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::{Consumer, EmptyConsumerContext};
 use rdkafka::error::KafkaError;
+use rdkafka::util::millis_to_epoch;
 use rdkafka::{Message, Offset, TopicPartitionList};
+use scheduled_executor::TaskGroup;
 use std::net::TcpListener;
 use std::io::Read;
 use std::thread;
 use md5;
-use cache::{Cache, OffsetsCache};
-use config::{ClusterConfig, Config};
+use cache::{Cache, CommitsCache, GroupStateCache, OffsetsCache};
+use config::{ClusterConfig, Config, OffsetsSource};
 use error::*;
-use metadata::{ClusterId, TopicName};
-use utils::{insert_at, read_string};
+use events;
+use offset_consumers::{OffsetConsumerState, OFFSET_CONSUMERS};
+use metadata::{
+    decode_group_member, BrokerId, ClusterId, Group, GroupMember, Partition, TopicName,
+};
+use task_stats::TASK_STATS;
+use utils::{
+    decode_hex, insert_at, read_bytes, read_compact_bytes, read_compact_nullable_string,
+    read_nullable_string, read_string, read_unsigned_varint, skip_tagged_fields,
+};
+use watermarks::fetch_watermarks;
 use actix_cors::Cors;
 use salvo::prelude::Redirect;
 use std::cmp;
-use std::collections::HashMap;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use std::panic::{self, AssertUnwindSafe};
 use std::str;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The content of an offset commit value, as stored in `__consumer_offsets`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct OffsetCommit {
+    pub offset: i64,
+    pub leader_epoch: Option<i32>, // from v3
+    pub metadata: String,
+    pub commit_timestamp: i64,         // millis since epoch
+    pub expire_timestamp: Option<i64>, // v1 only
+}
+
+/// Delays before restarting a failed offset consumer, in seconds. The delay doubles at each
+/// consecutive failure.
+const RESTART_BACKOFF_MIN: u64 = 1;
+const RESTART_BACKOFF_MAX: u64 = 300;
+/// Consumers running for longer than this, in seconds, restart with the minimum delay.
+const HEALTHY_RUN_DURATION: u64 = 600;
+/// Consecutive stream errors after which the offset consumer is restarted.
+const MAX_CONSECUTIVE_ERRORS: u32 = 30;
+
+/// Number of generations kept in the history of each group.
+const MAX_GENERATION_HISTORY: usize = 100;
+
+/// The content of a group metadata value, as stored in `__consumer_offsets`.
+#[derive(Debug)]
+struct GroupMetadata {
+    protocol_type: String,
+    generation: i32,
+    protocol: Option<String>,
+    leader: Option<String>,
+    state_timestamp: Option<i64>, // from v2
+    members: Vec<GroupMember>,
+}
+
+/// A generation of a group, as seen in the group metadata records.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Generation {
+    pub generation: i32,
+    pub timestamp: i64, // millis since epoch
+    pub members: usize,
+}
+
+/// The state of a group, as last written by its coordinator in `__consumer_offsets`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct GroupState {
+    pub protocol_type: String,
+    pub generation: i32,
+    pub protocol: String,
+    pub leader: Option<String>,
+    pub members: Vec<GroupMember>,
+    pub generations: Vec<Generation>,
+}
+
+impl GroupState {
+    fn update(&mut self, metadata: GroupMetadata) {
+        let is_new_generation = self
+            .generations
+            .last()
+            .map_or(true, |last| last.generation != metadata.generation);
+        if is_new_generation {
+            self.generations.push(Generation {
+                generation: metadata.generation,
+                timestamp: metadata
+                    .state_timestamp
+                    .filter(|&t| t > 0)
+                    .unwrap_or_else(|| millis_to_epoch(SystemTime::now()) as i64),
+                members: metadata.members.len(),
+            });
+            if self.generations.len() > MAX_GENERATION_HISTORY {
+                let excess = self.generations.len() - MAX_GENERATION_HISTORY;
+                self.generations.drain(..excess);
+            }
+        }
+        self.protocol_type = metadata.protocol_type;
+        self.generation = metadata.generation;
+        self.protocol = metadata.protocol.unwrap_or_default();
+        self.leader = metadata.leader;
+        self.members = metadata.members;
+    }
+
+    /// The state of the group as far as the records tell: groups without members are empty.
+    pub fn state(&self) -> &'static str {
+        if self.members.is_empty() {
+            "Empty"
+        } else {
+            "Stable"
+        }
+    }
+
+    /// Converts to a group, as it would be returned by the coordinator.
+    pub fn to_group(&self, name: &str) -> Group {
+        Group {
+            name: name.to_owned(),
+            state: format!("{} (offsets topic)", self.state()),
+            protocol_type: self.protocol_type.clone(),
+            protocol: self.protocol.clone(),
+            members: self.members.clone(),
+        }
+    }
+
+    /// Number of generations started after the given timestamp, in millis.
+    pub fn rebalances_since(&self, timestamp: i64) -> usize {
+        self.generations
+            .iter()
+            .filter(|generation| generation.timestamp >= timestamp)
+            .count()
+    }
+}
 
 #[derive(Debug)]
 enum ConsumerUpdate {
-    Metadata,
+    GroupMetadata {
+        group: String,
+        metadata: GroupMetadata,
+    },
+    GroupTombstone {
+        group: String,
+    },
     OffsetCommit {
         group: String,
         topic: String,
         partition: i32,
-        offset: i64,
+        commit: OffsetCommit,
     },
     OffsetTombstone {
         group: String,
@@ -38,6 +165,156 @@ enum ConsumerUpdate {
     },
 }
 
+/// Reads a string of a value, compact in the flexible versions.
+fn read_value_string(rdr: &mut Cursor<&[u8]>, flexible: bool) -> Result<Option<String>> {
+    if flexible {
+        read_compact_nullable_string(rdr)
+    } else {
+        read_nullable_string(rdr)
+    }
+}
+
+fn parse_offset_commit_value(payload_rdr: &mut Cursor<&[u8]>) -> Result<OffsetCommit> {
+    let version = payload_rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse value version")?;
+    let offset = payload_rdr
+        .read_i64::<BigEndian>()
+        .chain_err(|| "Failed to parse offset from value")?;
+    let leader_epoch = if version >= 3 {
+        let epoch = payload_rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse leader epoch from value")?;
+        if epoch >= 0 {
+            Some(epoch)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    // Version 4 is a flexible version, with compact strings and trailing tagged fields
+    let metadata = read_value_string(payload_rdr, version >= 4)
+        .chain_err(|| "Failed to parse metadata from value")?
+        .unwrap_or_default();
+    let commit_timestamp = payload_rdr
+        .read_i64::<BigEndian>()
+        .chain_err(|| "Failed to parse commit timestamp from value")?;
+    let expire_timestamp = if version == 1 {
+        let timestamp = payload_rdr
+            .read_i64::<BigEndian>()
+            .chain_err(|| "Failed to parse expire timestamp from value")?;
+        Some(timestamp)
+    } else {
+        None
+    };
+    Ok(OffsetCommit {
+        offset,
+        leader_epoch,
+        metadata,
+        commit_timestamp,
+        expire_timestamp,
+    })
+}
+
+fn parse_group_member(
+    rdr: &mut Cursor<&[u8]>,
+    version: i16,
+    protocol_type: &str,
+    protocol: &str,
+) -> Result<GroupMember> {
+    let flexible = version >= 4;
+    let member_id = read_value_string(rdr, flexible)
+        .chain_err(|| "Failed to parse member id")?
+        .unwrap_or_default();
+    if version >= 3 {
+        let _group_instance_id =
+            read_value_string(rdr, flexible).chain_err(|| "Failed to parse group instance id")?;
+    }
+    let client_id = read_value_string(rdr, flexible)
+        .chain_err(|| "Failed to parse client id")?
+        .unwrap_or_default();
+    let client_host = read_value_string(rdr, flexible)
+        .chain_err(|| "Failed to parse client host")?
+        .unwrap_or_default();
+    if version >= 1 {
+        let _rebalance_timeout = rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse rebalance timeout")?;
+    }
+    let _session_timeout = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse session timeout")?;
+    let (subscription, assignment) = if flexible {
+        (read_compact_bytes(rdr), read_compact_bytes(rdr))
+    } else {
+        (read_bytes(rdr), read_bytes(rdr))
+    };
+    let subscription = subscription.chain_err(|| "Failed to parse subscription")?;
+    let assignment = assignment.chain_err(|| "Failed to parse assignment")?;
+    if flexible {
+        skip_tagged_fields(rdr).chain_err(|| "Failed to parse member tagged fields")?;
+    }
+    Ok(decode_group_member(
+        protocol_type,
+        protocol,
+        GroupMember::new(member_id, client_id, client_host),
+        subscription.as_ref().map(|s| s.as_slice()),
+        assignment.as_ref().map(|a| a.as_slice()),
+    ))
+}
+
+fn parse_group_metadata_value(payload_rdr: &mut Cursor<&[u8]>) -> Result<GroupMetadata> {
+    let version = payload_rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse value version")?;
+    let flexible = version >= 4;
+    let protocol_type = read_value_string(payload_rdr, flexible)
+        .chain_err(|| "Failed to parse protocol type")?
+        .unwrap_or_default();
+    let generation = payload_rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse generation")?;
+    let protocol =
+        read_value_string(payload_rdr, flexible).chain_err(|| "Failed to parse protocol")?;
+    let leader = read_value_string(payload_rdr, flexible).chain_err(|| "Failed to parse leader")?;
+    let state_timestamp = if version >= 2 {
+        let timestamp = payload_rdr
+            .read_i64::<BigEndian>()
+            .chain_err(|| "Failed to parse state timestamp")?;
+        Some(timestamp)
+    } else {
+        None
+    };
+    let member_count = if flexible {
+        let count =
+            read_unsigned_varint(payload_rdr).chain_err(|| "Failed to parse member count")?;
+        count as i32 - 1
+    } else {
+        payload_rdr
+            .read_i32::<BigEndian>()
+            .chain_err(|| "Failed to parse member count")?
+    };
+    let mut members = Vec::new();
+    for _ in 0..member_count {
+        let member = parse_group_member(
+            payload_rdr,
+            version,
+            &protocol_type,
+            protocol.as_ref().map_or("", |p| p.as_str()),
+        )?;
+        members.push(member);
+    }
+    Ok(GroupMetadata {
+        protocol_type,
+        generation,
+        protocol,
+        leader,
+        state_timestamp,
+        members,
+    })
+}
+
 fn parse_group_offset(
     key_rdr: &mut Cursor<&[u8]>,
     payload_rdr: &mut Cursor<&[u8]>,
@@ -49,17 +326,12 @@ fn parse_group_offset(
         .chain_err(|| "Failed to parse partition from key")?;
     if !payload_rdr.get_ref().is_empty() {
         // payload is not empty
-        let _version = payload_rdr
-            .read_i16::<BigEndian>()
-            .chain_err(|| "Failed to parse value version")?;
-        let offset = payload_rdr
-            .read_i64::<BigEndian>()
-            .chain_err(|| "Failed to parse offset from value")?;
+        let commit = parse_offset_commit_value(payload_rdr)?;
         Ok(ConsumerUpdate::OffsetCommit {
             group,
             topic,
             partition,
-            offset,
+            commit,
         })
     } else {
         Ok(ConsumerUpdate::OffsetTombstone {
@@ -70,7 +342,9 @@ fn parse_group_offset(
     }
 }
 
-fn parse_message(key: &[u8], payload: &[u8]) -> Result<ConsumerUpdate> {
+/// Parses a record of `__consumer_offsets`. The timestamp of the record, in millis, is used as
+/// the time of the group metadata that doesn't contain one.
+fn parse_message(key: &[u8], payload: &[u8], timestamp: Option<i64>) -> Result<ConsumerUpdate> {
     let mut key_rdr = Cursor::new(key);
     let key_version = key_rdr
         .read_i16::<BigEndian>()
@@ -78,7 +352,19 @@ fn parse_message(key: &[u8], payload: &[u8]) -> Result<ConsumerUpdate> {
     match key_version {
         0 | 1 => parse_group_offset(&mut key_rdr, &mut Cursor::new(payload))
             .chain_err(|| "Failed to parse group offset update"),
-        2 => Ok(ConsumerUpdate::Metadata),
+        2 => {
+            let group =
+                read_string(&mut key_rdr).chain_err(|| "Failed to parse group name from key")?;
+            if payload.is_empty() {
+                return Ok(ConsumerUpdate::GroupTombstone { group });
+            }
+            let mut metadata = parse_group_metadata_value(&mut Cursor::new(payload))
+                .chain_err(|| format!("Failed to parse metadata of group {}", group))?;
+            if metadata.state_timestamp.map_or(true, |t| t <= 0) {
+                metadata.state_timestamp = timestamp;
+            }
+            Ok(ConsumerUpdate::GroupMetadata { group, metadata })
+        }
         _ => bail!("Key version not recognized"),
     }
 }
@@ -138,6 +424,19 @@ fn create_consumer(
     Ok(consumer)
 }
 
+/// Merges the offsets committed in a batch with the stored ones. -1 means not committed in the
+/// batch: those partitions keep their offset. A committed offset always replaces the previous
+/// one, even if it's lower.
+fn merge_batch_offsets(existing_offsets: &mut Vec<i64>, new_offsets: &[i64]) {
+    vec_merge_in_place(existing_offsets, new_offsets, -1, |old, new| {
+        if new != -1 {
+            new
+        } else {
+            old
+        }
+    });
+}
+
 // we should really have some tests here
 fn update_global_cache(
     cluster_id: &ClusterId,
@@ -145,20 +444,11 @@ fn update_global_cache(
     cache: &OffsetsCache,
 ) {
     for (&(ref group, ref topic), new_offsets) in local_cache {
-        // Consider a consuming iterator
-        // This logic is not needed if i store the consumer offset, right? wrong!
         if new_offsets.iter().any(|&offset| offset == -1) {
             if let Some(mut existing_offsets) =
                 cache.get(&(cluster_id.to_owned(), group.to_owned(), topic.to_owned()))
             {
-                // If the new offset is not complete and i have an old one, do the merge
-                vec_merge_in_place(&mut existing_offsets, &new_offsets, -1, cmp::max);
-                //                for i in 0..(cmp::max(offsets.len(), existing_offsets.len())) {
-                //                    let new_offset = cmp::max(
-                //                        offsets.get(i).cloned().unwrap_or(-1),
-                //                        existing_offsets.get(i).cloned().unwrap_or(-1));
-                //                    insert_at(&mut existing_offsets, i, new_offset, -1);
-                //                }
+                merge_batch_offsets(&mut existing_offsets, new_offsets);
                 let _ = cache.insert(
                     (cluster_id.to_owned(), group.to_owned(), topic.to_owned()),
                     existing_offsets,
@@ -174,6 +464,216 @@ fn update_global_cache(
     }
 }
 
+/// Stores the details of the latest commits, keeping the previous ones for the partitions that
+/// didn't receive new commits.
+fn update_commits_cache(
+    cluster_id: &ClusterId,
+    local_commits: &HashMap<(String, String), Vec<Option<OffsetCommit>>>,
+    cache: &CommitsCache,
+) {
+    for (&(ref group, ref topic), new_commits) in local_commits {
+        let key = (cluster_id.to_owned(), group.to_owned(), topic.to_owned());
+        let mut commits = cache.get(&key).unwrap_or_default();
+        for (partition, commit) in new_commits.iter().enumerate() {
+            if commit.is_some() {
+                insert_at(&mut commits, partition, commit.clone(), None);
+            }
+        }
+        if let Err(e) = cache.insert(key, commits) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+/// Applies the group metadata records, in order, to the state of each group.
+fn update_group_states(
+    cluster_id: &ClusterId,
+    local_group_metadata: HashMap<String, Vec<GroupMetadata>>,
+    cache: &GroupStateCache,
+) {
+    for (group, records) in local_group_metadata {
+        let key = (cluster_id.to_owned(), group);
+        let mut state = cache.get(&key).unwrap_or_default();
+        for metadata in records {
+            state.update(metadata);
+        }
+        if let Err(e) = cache.insert(key, state) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+/// Clears the offsets and commits of the partitions that received a tombstone, removing the
+/// entries of the topics left without offsets.
+fn apply_offset_tombstones(
+    cluster_id: &ClusterId,
+    local_tombstones: &HashMap<(String, String), HashSet<i32>>,
+    cache: &Cache,
+) -> Result<()> {
+    for (&(ref group, ref topic), partitions) in local_tombstones {
+        let key = (cluster_id.to_owned(), group.to_owned(), topic.to_owned());
+        if let Some(mut offsets) = cache.offsets.get(&key) {
+            for &partition in partitions {
+                if let Some(offset) = offsets.get_mut(partition as usize) {
+                    *offset = -1;
+                }
+            }
+            let result = if offsets.iter().all(|&offset| offset == -1) {
+                cache.offsets.remove(&key)
+            } else {
+                cache.offsets.insert(key.clone(), offsets)
+            };
+            result.chain_err(|| "Failed to update offsets after tombstone")?;
+        }
+        if let Some(mut commits) = cache.commits.get(&key) {
+            for &partition in partitions {
+                if let Some(commit) = commits.get_mut(partition as usize) {
+                    *commit = None;
+                }
+            }
+            let result = if commits.iter().all(Option::is_none) {
+                cache.commits.remove(&key)
+            } else {
+                cache.commits.insert(key, commits)
+            };
+            result.chain_err(|| "Failed to update commits after tombstone")?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the state, offsets and commits of the deleted groups, recording an event for each
+/// group that was known.
+fn delete_groups(
+    cluster_id: &ClusterId,
+    local_deleted_groups: &HashSet<String>,
+    cache: &Cache,
+) -> Result<()> {
+    let mut deletion_events = Vec::new();
+    for group in local_deleted_groups {
+        let group_key = (cluster_id.to_owned(), group.to_owned());
+        let offsets_keys = cache
+            .offsets
+            .filter_clone_k(|&(ref c, ref g, _)| c == cluster_id && g == group);
+        let commits_keys = cache
+            .commits
+            .filter_clone_k(|&(ref c, ref g, _)| c == cluster_id && g == group);
+        let has_state = cache.group_states.get(&group_key).is_some();
+        let has_group = cache.groups.get(&group_key).is_some();
+        if !has_state && !has_group && offsets_keys.is_empty() {
+            continue; // deleted before we knew about it
+        }
+        if has_state {
+            cache
+                .group_states
+                .remove(&group_key)
+                .chain_err(|| "Failed to remove group state")?;
+        }
+        if has_group {
+            cache
+                .groups
+                .remove(&group_key)
+                .chain_err(|| "Failed to remove group")?;
+        }
+        for key in offsets_keys {
+            cache
+                .offsets
+                .remove(&key)
+                .chain_err(|| "Failed to remove group offsets")?;
+        }
+        for key in commits_keys {
+            cache
+                .commits
+                .remove(&key)
+                .chain_err(|| "Failed to remove group commits")?;
+        }
+        deletion_events.push(events::group_deleted(group));
+    }
+    if !deletion_events.is_empty() {
+        let mut event_log = cache.events.get(cluster_id).unwrap_or_default();
+        events::append_events(&mut event_log, deletion_events);
+        cache
+            .events
+            .insert(cluster_id.clone(), event_log)
+            .chain_err(|| "Failed to record group deletion events")?;
+    }
+    Ok(())
+}
+
+/// Returns the number of messages of `__consumer_offsets` that are yet to be consumed, given the
+/// position of the consumer.
+fn offsets_topic_lag(cluster_id: &ClusterId, position: &[i64]) -> Result<i64> {
+    let partitions = (0..position.len())
+        .map(|partition| ("__consumer_offsets".to_owned(), partition as i32))
+        .collect::<Vec<_>>();
+    let watermarks = fetch_watermarks(cluster_id, partitions)?;
+    let mut lag = 0;
+    for (partition, &offset) in position.iter().enumerate() {
+        let &(low, high) =
+            match watermarks.get(&("__consumer_offsets".to_owned(), partition as i32)) {
+                Some(wms) => wms,
+                None => bail!("Failed to fetch watermarks of partition {}", partition),
+            };
+        let consumed = if offset >= 0 { offset } else { low };
+        lag += (high - consumed).max(0);
+    }
+    Ok(lag)
+}
+
+/// Periodically computes the lag of the offset consumers running in this instance, from the
+/// positions they store in the cache.
+pub struct OffsetsLagTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl OffsetsLagTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> OffsetsLagTaskGroup {
+        OffsetsLagTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    fn update_lag(&self, cluster_id: &ClusterId) -> Result<()> {
+        let position = match self.cache.internal_offsets.get(cluster_id) {
+            Some(position) => position,
+            None => return Ok(()), // the consumer hasn't stored its position yet
+        };
+        let lag = offsets_topic_lag(cluster_id, &position)?;
+        OFFSET_CONSUMERS.update(cluster_id, |status| status.lag = Some(lag));
+        Ok(())
+    }
+}
+
+impl TaskGroup for OffsetsLagTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config
+            .clusters
+            .iter()
+            .filter(|&(_, cluster_config)| cluster_config.offsets_source == OffsetsSource::Topic)
+            .map(|(cluster_id, _)| cluster_id.clone())
+            .collect::<Vec<_>>()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Starting offsets topic lag check for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.update_lag(&cluster_id);
+        TASK_STATS.record(
+            "offsets_topic_lag",
+            cluster_id.name(),
+            start.elapsed(),
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
+
 fn commit_offset_position_to_array(tp_list: TopicPartitionList) -> Vec<i64> {
     let tp_elements = tp_list.elements_for_topic("__consumer_offsets");
     let mut offsets = vec![0; tp_elements.len()];
@@ -183,49 +683,197 @@ fn commit_offset_position_to_array(tp_list: TopicPartitionList) -> Vec<i64> {
     offsets
 }
 
+/// The updates read from `__consumer_offsets` since the last write to the cache.
+#[derive(Default)]
+struct OffsetsBatch {
+    offsets: HashMap<(String, String), Vec<i64>>,
+    commits: HashMap<(String, String), Vec<Option<OffsetCommit>>>,
+    group_metadata: HashMap<String, Vec<GroupMetadata>>,
+    tombstones: HashMap<(String, String), HashSet<i32>>,
+    deleted_groups: HashSet<String>,
+}
+
+impl OffsetsBatch {
+    fn add(&mut self, update: ConsumerUpdate) {
+        match update {
+            ConsumerUpdate::OffsetCommit {
+                group,
+                topic,
+                partition,
+                commit,
+            } => {
+                let mut offsets = self
+                    .offsets
+                    .entry((group.to_owned(), topic.to_owned()))
+                    .or_insert_with(Vec::new);
+                insert_at(&mut offsets, partition as usize, commit.offset, -1);
+                if let Some(partitions) = self
+                    .tombstones
+                    .get_mut(&(group.to_owned(), topic.to_owned()))
+                {
+                    partitions.remove(&partition);
+                }
+                let mut commits = self.commits.entry((group, topic)).or_insert_with(Vec::new);
+                insert_at(&mut commits, partition as usize, Some(commit), None);
+            }
+            ConsumerUpdate::OffsetTombstone {
+                group,
+                topic,
+                partition,
+            } => {
+                // Committed in this batch and then deleted
+                let key = (group, topic);
+                if let Some(offsets) = self.offsets.get_mut(&key) {
+                    if let Some(offset) = offsets.get_mut(partition as usize) {
+                        *offset = -1;
+                    }
+                }
+                if let Some(commits) = self.commits.get_mut(&key) {
+                    if let Some(commit) = commits.get_mut(partition as usize) {
+                        *commit = None;
+                    }
+                }
+                self.tombstones
+                    .entry(key)
+                    .or_insert_with(HashSet::new)
+                    .insert(partition);
+            }
+            ConsumerUpdate::GroupMetadata { group, metadata } => {
+                self.deleted_groups.remove(&group);
+                self.group_metadata
+                    .entry(group)
+                    .or_insert_with(Vec::new)
+                    .push(metadata);
+            }
+            ConsumerUpdate::GroupTombstone { group } => {
+                // Drop what this batch received before the deletion
+                self.offsets.retain(|&(ref g, _), _| g != &group);
+                self.commits.retain(|&(ref g, _), _| g != &group);
+                self.tombstones.retain(|&(ref g, _), _| g != &group);
+                self.group_metadata.remove(&group);
+                self.deleted_groups.insert(group);
+            }
+        }
+    }
+
+    /// Writes the batch to the cache. Deletions are applied first, as the updates still in the
+    /// batch were received after them.
+    fn dump(self, cluster_id: &ClusterId, cache: &Cache) {
+        if let Err(e) = delete_groups(cluster_id, &self.deleted_groups, cache) {
+            format_error_chain!(e);
+        }
+        update_global_cache(cluster_id, &self.offsets, &cache.offsets);
+        update_commits_cache(cluster_id, &self.commits, &cache.commits);
+        update_group_states(cluster_id, self.group_metadata, &cache.group_states);
+        if let Err(e) = apply_offset_tombstones(cluster_id, &self.tombstones, cache) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+/// Number of replayed records written to the cache at once.
+const REPLAY_BATCH_SIZE: usize = 10000;
+
+/// Applies a file of recorded `__consumer_offsets` records to the cache, as if they had been
+/// consumed from the cluster. Each line contains the key and the value of a record in hex,
+/// separated by a space; the value of a tombstone is `-`. An optional third field is the
+/// timestamp of the record in millis. Empty lines and lines starting with `#` are ignored.
+/// Returns the number of records replayed.
+pub fn replay_offsets_dump(cluster_id: &ClusterId, path: &str, cache: &Cache) -> Result<usize> {
+    let file = File::open(path).chain_err(|| format!("Unable to open {}", path))?;
+    let mut batch = OffsetsBatch::default();
+    let mut records = 0;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.chain_err(|| format!("Unable to read {}", path))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let key = fields.next().unwrap_or("");
+        let value = fields.next().unwrap_or("-");
+        let timestamp = fields.next().and_then(|t| t.parse::<i64>().ok());
+        let result = decode_hex(key)
+            .and_then(|key| {
+                let value = if value == "-" {
+                    Vec::new()
+                } else {
+                    decode_hex(value)?
+                };
+                parse_message(&key, &value, timestamp)
+            })
+            .chain_err(|| format!("Invalid record at line {}", index + 1));
+        match result {
+            Ok(update) => batch.add(update),
+            Err(e) => format_error_chain!(e),
+        }
+        records += 1;
+        if records % REPLAY_BATCH_SIZE == 0 {
+            batch.dump(cluster_id, cache);
+            batch = OffsetsBatch::default();
+        }
+    }
+    batch.dump(cluster_id, cache);
+    Ok(records)
+}
+
 fn consume_offset_topic(
     cluster_id: ClusterId,
     consumer: StreamConsumer<EmptyConsumerContext>,
     cache: &Cache,
 ) -> Result<()> {
-    let mut local_cache = HashMap::new();
+    let mut batch = OffsetsBatch::default();
     let mut last_dump = Instant::now();
+    let mut consecutive_errors = 0;
+    let mut messages = 0u64;
+    let mut last_message_timestamp = None;
 
     debug!("Starting offset consumer loop for {:?}", cluster_id);
+    OFFSET_CONSUMERS.set_state(&cluster_id, OffsetConsumerState::Running);
 
     for message in consumer.start_with(Duration::from_millis(200), true).wait() {
         match message {
             Ok(Ok(m)) => {
+                consecutive_errors = 0;
+                messages += 1;
+                if let Some(timestamp) = m.timestamp().to_millis() {
+                    last_message_timestamp = Some(timestamp);
+                }
                 let key = m.key().unwrap_or(&[]);
                 let payload = m.payload().unwrap_or(&[]);
-                match parse_message(key, payload) {
-                    Ok(ConsumerUpdate::OffsetCommit {
-                        group,
-                        topic,
-                        partition,
-                        offset,
-                    }) => {
-                        let mut offsets = local_cache
-                            .entry((group.to_owned(), topic.to_owned()))
-                            .or_insert_with(Vec::new);
-                        insert_at(&mut offsets, partition as usize, offset, -1);
-                    }
-                    Ok(_) => {}
+                match parse_message(key, payload, m.timestamp().to_millis()) {
+                    Ok(update) => batch.add(update),
                     Err(e) => format_error_chain!(e),
                 };
             }
             Ok(Err(KafkaError::NoMessageReceived)) => {}
-            Ok(Err(e)) => warn!("Kafka error: {} {:?}", cluster_id, e),
-            Err(e) => warn!("Can't receive data from stream: {:?}", e),
+            Ok(Err(e)) => {
+                warn!("Kafka error: {} {:?}", cluster_id, e);
+                consecutive_errors += 1;
+                OFFSET_CONSUMERS.record_error(&cluster_id, format!("Kafka error: {}", e));
+            }
+            Err(e) => {
+                warn!("Can't receive data from stream: {:?}", e);
+                consecutive_errors += 1;
+                OFFSET_CONSUMERS
+                    .record_error(&cluster_id, "Can't receive data from stream".to_owned());
+            }
         };
+        if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+            bail!(
+                "{} consecutive errors while consuming __consumer_offsets",
+                consecutive_errors
+            );
+        }
         // Update the cache if needed
         if (Instant::now() - last_dump) > Duration::from_secs(10) {
             trace!(
                 "Dumping local offset cache ({}: {} updates)",
                 cluster_id,
-                local_cache.len()
+                batch.offsets.len()
             );
-            update_global_cache(&cluster_id, &local_cache, &cache.offsets);
+            batch.dump(&cluster_id, cache);
+            batch = OffsetsBatch::default();
             // Consumer position is not up to date after start, so we have to merge with the
             // existing offsets and take the largest.
             let res = consumer
@@ -251,7 +899,18 @@ fn consume_offset_topic(
             if let Err(e) = res {
                 format_error_chain!(e);
             }
-            local_cache = HashMap::with_capacity(local_cache.len());
+
+            let elapsed = last_dump.elapsed();
+            let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            OFFSET_CONSUMERS.update(&cluster_id, |status| {
+                status.messages += messages;
+                status.messages_rate = messages as f64 / seconds;
+                if last_message_timestamp.is_some() {
+                    status.last_message_timestamp = last_message_timestamp;
+                }
+            });
+            messages = 0;
+
             last_dump = Instant::now();
         }
     }
@@ -283,29 +942,67 @@ where
 //        .collect::<Vec<T>>()
 //}
 
+/// Runs the offset consumer of the cluster, restarting it with an exponential backoff whenever
+/// it fails. The consumer resumes from the positions stored in the cache.
+fn supervise_offset_consumer(
+    cluster_id: ClusterId,
+    brokers: String,
+    group_id: String,
+    cache: Cache,
+) {
+    let mut backoff = RESTART_BACKOFF_MIN;
+    loop {
+        let start = Instant::now();
+        let start_position = cache.internal_offsets.get(&cluster_id);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            create_consumer(&brokers, &group_id, start_position)
+                .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id))
+                .and_then(|consumer| consume_offset_topic(cluster_id.clone(), consumer, &cache))
+        }));
+        let error = match result {
+            Ok(Ok(())) => "Offset consumer stream ended".to_owned(),
+            Ok(Err(e)) => {
+                format_error_chain!(e);
+                e.chain_message()
+            }
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| (*s).to_owned())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown cause".to_owned());
+                error!("Offset consumer for {} panicked: {}", cluster_id, message);
+                format!("Offset consumer panicked: {}", message)
+            }
+        };
+        if start.elapsed() > Duration::from_secs(HEALTHY_RUN_DURATION) {
+            backoff = RESTART_BACKOFF_MIN;
+        }
+        OFFSET_CONSUMERS.record_failure(&cluster_id, error);
+        warn!(
+            "Offset consumer for {} stopped, restarting in {} seconds",
+            cluster_id, backoff
+        );
+        thread::sleep(Duration::from_secs(backoff));
+        backoff = cmp::min(backoff * 2, RESTART_BACKOFF_MAX);
+        OFFSET_CONSUMERS.set_state(&cluster_id, OffsetConsumerState::Starting);
+    }
+}
+
 pub fn run_offset_consumer(
     cluster_id: &ClusterId,
     cluster_config: &ClusterConfig,
     config: &Config,
     cache: &Cache,
 ) -> Result<()> {
-    let start_position = cache.internal_offsets.get(cluster_id);
-    let consumer = create_consumer(
-        &cluster_config.bootstrap_servers(),
-        &config.consumer_offsets_group_id,
-        start_position,
-    )
-    .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id))?;
-
     let cluster_id_clone = cluster_id.clone();
+    let brokers = cluster_config.bootstrap_servers();
+    let group_id = config.consumer_offsets_group_id.clone();
     let cache_alias = cache.alias();
+    OFFSET_CONSUMERS.set_state(cluster_id, OffsetConsumerState::Starting);
     let _ = thread::Builder::new()
         .name("offset-consumer".to_owned())
-        .spawn(move || {
-            if let Err(e) = consume_offset_topic(cluster_id_clone, consumer, &cache_alias) {
-                format_error_chain!(e);
-            }
-        })
+        .spawn(move || supervise_offset_consumer(cluster_id_clone, brokers, group_id, cache_alias))
         .chain_err(|| "Failed to start offset consumer thread")?;
 
     let _ = thread::spawn(|| {
@@ -328,6 +1025,31 @@ pub fn run_offset_consumer(
     Ok(())
 }
 
+/// Computes the Java `String.hashCode` of a string, as used by the brokers.
+fn java_string_hash(string: &str) -> i32 {
+    string.encode_utf16().fold(0i32, |hash, c| {
+        hash.wrapping_mul(31).wrapping_add(i32::from(c))
+    })
+}
+
+/// Returns the partition of `__consumer_offsets` storing the commits of the given group.
+pub fn group_offsets_partition(group: &str, partition_count: usize) -> i32 {
+    (java_string_hash(group) & 0x7fff_ffff) % partition_count as i32
+}
+
+/// Returns the `__consumer_offsets` partition of the group and its leader, which is the
+/// coordinator of the group.
+pub fn group_coordinator(offsets_partitions: &[Partition], group: &str) -> Option<(i32, BrokerId)> {
+    if offsets_partitions.is_empty() {
+        return None;
+    }
+    let partition_id = group_offsets_partition(group, offsets_partitions.len());
+    offsets_partitions
+        .iter()
+        .find(|p| p.id == partition_id)
+        .map(|p| (partition_id, p.leader))
+}
+
 pub trait OffsetStore {
     fn offsets_by_cluster(
         &self,
@@ -384,4 +1106,175 @@ pub fn redirect_from_input(input: &str) {
     //SINK
     let _redir = salvo::prelude::Redirect::temporary(&s);
     let _ = println!("Performed redirect setup to {}", s);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lag_status::{self, LagSample, LagStatus};
+    use std::collections::VecDeque;
+    use utils::hex_dump;
+
+    #[test]
+    fn offset_commit_value_v1() {
+        let value: &[u8] = &[
+            0, 1, // version
+            0, 0, 0, 0, 0, 0, 0, 42, // offset
+            0, 4, b'm', b'e', b't', b'a', // metadata
+            0, 0, 0, 0, 0, 0, 3, 232, // commit timestamp
+            0, 0, 0, 0, 0, 0, 7, 208, // expire timestamp
+        ];
+        let commit = parse_offset_commit_value(&mut Cursor::new(value)).unwrap();
+        assert_eq!(
+            commit,
+            OffsetCommit {
+                offset: 42,
+                leader_epoch: None,
+                metadata: "meta".to_owned(),
+                commit_timestamp: 1000,
+                expire_timestamp: Some(2000),
+            }
+        );
+    }
+
+    #[test]
+    fn offset_commit_value_v3() {
+        let value: &[u8] = &[
+            0, 3, // version
+            0, 0, 0, 0, 0, 0, 0, 42, // offset
+            0, 0, 0, 5, // leader epoch
+            0, 0, // metadata
+            0, 0, 0, 0, 0, 0, 3, 232, // commit timestamp
+        ];
+        let commit = parse_offset_commit_value(&mut Cursor::new(value)).unwrap();
+        assert_eq!(commit.leader_epoch, Some(5));
+        assert_eq!(commit.metadata, "");
+        assert_eq!(commit.commit_timestamp, 1000);
+        assert_eq!(commit.expire_timestamp, None);
+    }
+
+    #[test]
+    fn offset_commit_value_v4_flexible() {
+        let value: &[u8] = &[
+            0, 4, // version
+            0, 0, 0, 0, 0, 0, 0, 42, // offset
+            255, 255, 255, 255, // no leader epoch
+            3, b'a', b'b', // compact metadata
+            0, 0, 0, 0, 0, 0, 3, 232, // commit timestamp
+            0,   // tagged fields
+        ];
+        let commit = parse_offset_commit_value(&mut Cursor::new(value)).unwrap();
+        assert_eq!(commit.offset, 42);
+        assert_eq!(commit.leader_epoch, None);
+        assert_eq!(commit.metadata, "ab");
+        assert_eq!(commit.commit_timestamp, 1000);
+    }
+
+    #[test]
+    fn offset_commit_value_truncated() {
+        let value: &[u8] = &[0, 1, 0, 0, 0, 0];
+        assert!(parse_offset_commit_value(&mut Cursor::new(value)).is_err());
+    }
+
+    #[test]
+    fn group_metadata_value_v2() {
+        let value: &[u8] = &[
+            0, 2, // version
+            0, 1, b'x', // protocol type
+            0, 0, 0, 7, // generation
+            0, 1, b'p', // protocol
+            0, 2, b'm', b'1', // leader
+            0, 0, 0, 0, 0, 0, 19, 136, // state timestamp
+            0, 0, 0, 1, // members
+            0, 2, b'm', b'1', // member id
+            0, 1, b'c', // client id
+            0, 2, b'/', b'h', // client host
+            0, 0, 117, 48, // rebalance timeout
+            0, 0, 39, 16, // session timeout
+            0, 0, 0, 2, 1, 2, // subscription
+            255, 255, 255, 255, // null assignment
+        ];
+        let metadata = parse_group_metadata_value(&mut Cursor::new(value)).unwrap();
+        assert_eq!(metadata.protocol_type, "x");
+        assert_eq!(metadata.generation, 7);
+        assert_eq!(metadata.protocol, Some("p".to_owned()));
+        assert_eq!(metadata.leader, Some("m1".to_owned()));
+        assert_eq!(metadata.state_timestamp, Some(5000));
+        assert_eq!(metadata.members.len(), 1);
+        let member = &metadata.members[0];
+        assert_eq!(member.id, "m1");
+        assert_eq!(member.client_id, "c");
+        assert_eq!(member.client_host, "/h");
+        assert_eq!(member.raw_metadata, Some(hex_dump(&[1, 2])));
+        assert_eq!(member.raw_assignment, None);
+    }
+
+    #[test]
+    fn group_metadata_value_v4_flexible() {
+        let value: &[u8] = &[
+            0, 4, // version
+            2, b'x', // compact protocol type
+            0, 0, 0, 1, // generation
+            0, // null protocol
+            0, // null leader
+            255, 255, 255, 255, 255, 255, 255, 255, // state timestamp
+            1,   // compact array of 0 members
+            0,   // tagged fields
+        ];
+        let metadata = parse_group_metadata_value(&mut Cursor::new(value)).unwrap();
+        assert_eq!(metadata.protocol_type, "x");
+        assert_eq!(metadata.generation, 1);
+        assert_eq!(metadata.protocol, None);
+        assert_eq!(metadata.leader, None);
+        assert_eq!(metadata.state_timestamp, Some(-1));
+        assert!(metadata.members.is_empty());
+    }
+
+    #[test]
+    fn group_metadata_without_state_timestamp_uses_record_timestamp() {
+        let key: &[u8] = &[0, 2, 0, 1, b'g'];
+        let value: &[u8] = &[
+            0, 0, // version
+            0, 1, b'x', // protocol type
+            0, 0, 0, 1, // generation
+            255, 255, // null protocol
+            255, 255, // null leader
+            0, 0, 0, 0, // members
+        ];
+        match parse_message(key, value, Some(1234)).unwrap() {
+            ConsumerUpdate::GroupMetadata { group, metadata } => {
+                assert_eq!(group, "g");
+                assert_eq!(metadata.state_timestamp, Some(1234));
+            }
+            update => panic!("Unexpected update: {:?}", update),
+        }
+    }
+
+    #[test]
+    fn group_tombstone() {
+        let key: &[u8] = &[0, 2, 0, 1, b'g'];
+        match parse_message(key, &[], None).unwrap() {
+            ConsumerUpdate::GroupTombstone { group } => assert_eq!(group, "g"),
+            update => panic!("Unexpected update: {:?}", update),
+        }
+    }
+
+    #[test]
+    fn partial_commit_at_lower_offset_is_a_rewind() {
+        // Only partition 1 is committed in the batch
+        let mut offsets = vec![10, 20];
+        merge_batch_offsets(&mut offsets, &[-1, 5]);
+        assert_eq!(offsets, vec![10, 5]);
+
+        let window = [20, 5]
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| LagSample {
+                timestamp: i as i64 * 1000,
+                offset,
+                lag: 1,
+                commit_time: i as i64 * 1000,
+            })
+            .collect::<VecDeque<_>>();
+        assert_eq!(lag_status::evaluate(&window), LagStatus::Rewind);
+    }
+}
@@ -0,0 +1,186 @@
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::{Config, OffsetsSource};
+use error::*;
+use kafka_protocol::{BrokerConnection, CommittedOffset, Coordinator};
+use metadata::{ClusterId, TopicName};
+use task_stats::TASK_STATS;
+use utils::insert_at;
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Reads the committed offsets of the consumer groups with the OffsetFetch request, for the
+/// clusters where `__consumer_offsets` can't be consumed. The offsets are stored in the same
+/// cache as the ones read from the topic.
+pub struct OffsetsFetchTaskGroup {
+    cache: Cache,
+    config: Config,
+}
+
+impl OffsetsFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> OffsetsFetchTaskGroup {
+        OffsetsFetchTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+        }
+    }
+
+    /// Returns the committed offsets of the group, indexed by partition. Missing partitions are
+    /// set to -1.
+    fn fetch_group_offsets(
+        &self,
+        connections: &mut BrokerConnections,
+        group: &str,
+    ) -> Result<HashMap<TopicName, Vec<i64>>> {
+        let committed = connections
+            .offset_fetch(group)
+            .chain_err(|| format!("Failed to fetch the offsets of {}", group))?;
+
+        let mut offsets = HashMap::new();
+        for c in committed.into_iter().filter(|c| c.error_code == 0) {
+            let topic_offsets = offsets.entry(c.topic).or_insert_with(Vec::new);
+            insert_at(topic_offsets, c.partition as usize, c.offset, -1);
+        }
+        Ok(offsets)
+    }
+
+    fn update_offsets(&self, cluster_id: &ClusterId) -> Result<()> {
+        let groups = self
+            .cache
+            .groups
+            .filter_clone_k(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|(_, group)| group)
+            .collect::<HashSet<_>>();
+
+        let mut connections = BrokerConnections::new(&self.cache, cluster_id);
+        let mut current_keys = HashSet::new();
+        let mut failed_groups = HashSet::new();
+        for group in &groups {
+            let offsets = match self.fetch_group_offsets(&mut connections, group) {
+                Ok(offsets) => offsets,
+                Err(e) => {
+                    format_error_chain!(e);
+                    failed_groups.insert(group.clone());
+                    continue;
+                }
+            };
+            for (topic, partitions) in offsets {
+                let key = (cluster_id.clone(), group.clone(), topic);
+                current_keys.insert(key.clone());
+                self.cache
+                    .offsets
+                    .insert(key, partitions)
+                    .chain_err(|| "Failed to insert offsets in cache")?;
+            }
+        }
+
+        // Deleted groups and topics without committed offsets anymore
+        let cached_keys = self
+            .cache
+            .offsets
+            .filter_clone_k(|&(ref c, _, _)| c == cluster_id);
+        for key in cached_keys {
+            if !current_keys.contains(&key) && !failed_groups.contains(&key.1) {
+                self.cache
+                    .offsets
+                    .remove(&key)
+                    .chain_err(|| "Failed to remove offsets from cache")?;
+            }
+        }
+
+        if !failed_groups.is_empty() {
+            bail!(
+                "Failed to fetch the offsets of {} of {} groups",
+                failed_groups.len(),
+                groups.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The connections to the brokers of a cluster, shared by all the groups of a refresh: one to
+/// find the coordinators, and one to each coordinator. A connection is closed after a failed
+/// request, and opened again by the next group that needs it.
+struct BrokerConnections<'a> {
+    cache: &'a Cache,
+    cluster_id: &'a ClusterId,
+    bootstrap: Option<BrokerConnection>,
+    coordinators: HashMap<i32, BrokerConnection>,
+}
+
+impl<'a> BrokerConnections<'a> {
+    fn new(cache: &'a Cache, cluster_id: &'a ClusterId) -> BrokerConnections<'a> {
+        BrokerConnections {
+            cache,
+            cluster_id,
+            bootstrap: None,
+            coordinators: HashMap::new(),
+        }
+    }
+
+    fn find_coordinator(&mut self, group: &str) -> Result<Coordinator> {
+        let mut connection = match self.bootstrap.take() {
+            Some(connection) => connection,
+            None => {
+                let brokers = self.cache.brokers.get(self.cluster_id).unwrap_or_default();
+                let broker = match brokers.first() {
+                    Some(broker) => broker,
+                    None => bail!("No broker known for {}", self.cluster_id),
+                };
+                BrokerConnection::connect(&broker.hostname, broker.port, Duration::from_secs(10))?
+            }
+        };
+        let coordinator = connection
+            .find_coordinator(group)
+            .chain_err(|| format!("Failed to find the coordinator of {}", group))?;
+        self.bootstrap = Some(connection);
+        Ok(coordinator)
+    }
+
+    fn offset_fetch(&mut self, group: &str) -> Result<Vec<CommittedOffset>> {
+        let coordinator = self.find_coordinator(group)?;
+        let mut connection = match self.coordinators.remove(&coordinator.node_id) {
+            Some(connection) => connection,
+            None => BrokerConnection::connect(
+                &coordinator.host,
+                coordinator.port,
+                Duration::from_secs(10),
+            )?,
+        };
+        let committed = connection.offset_fetch(group)?;
+        self.coordinators.insert(coordinator.node_id, connection);
+        Ok(committed)
+    }
+}
+
+impl TaskGroup for OffsetsFetchTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config
+            .clusters
+            .iter()
+            .filter(|&(_, cluster_config)| cluster_config.offsets_source == OffsetsSource::Api)
+            .map(|(cluster_id, _)| cluster_id.clone())
+            .collect::<Vec<_>>()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Starting offsets fetch for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.update_offsets(&cluster_id);
+        TASK_STATS.record(
+            "offsets_fetch",
+            cluster_id.name(),
+            start.elapsed(),
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+lazy_static! {
+    pub static ref TASK_STATS: TaskStatsStore = TaskStatsStore::new();
+}
+
+/// Execution statistics of a background task, such as a metadata or metrics fetch.
+#[derive(Debug, Clone, Default)]
+pub struct TaskStats {
+    pub runs: u64,
+    pub errors: u64,
+    pub last_duration: f64,  // seconds
+    pub total_duration: f64, // seconds
+}
+
+/// Collects the execution statistics of the background tasks of kafka-view. Each entry is
+/// identified by the name of the task and by its target (for example a cluster or a broker).
+pub struct TaskStatsStore {
+    stats: RwLock<HashMap<(String, String), TaskStats>>,
+}
+
+impl TaskStatsStore {
+    pub fn new() -> TaskStatsStore {
+        TaskStatsStore {
+            stats: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, task: &str, target: &str, duration: Duration, success: bool) {
+        let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+        match self.stats.write() {
+            Ok(mut stats) => {
+                let entry = stats
+                    .entry((task.to_owned(), target.to_owned()))
+                    .or_insert_with(TaskStats::default);
+                entry.runs += 1;
+                if !success {
+                    entry.errors += 1;
+                }
+                entry.last_duration = seconds;
+                entry.total_duration += seconds;
+            }
+            Err(_) => panic!("Poison error while writing task stats"),
+        };
+    }
+
+    pub fn get(&self, task: &str, target: &str) -> Option<TaskStats> {
+        match self.stats.read() {
+            Ok(stats) => stats.get(&(task.to_owned(), target.to_owned())).cloned(),
+            Err(_) => panic!("Poison error while reading task stats"),
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<((String, String), TaskStats)> {
+        match self.stats.read() {
+            Ok(stats) => stats
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>(),
+            Err(_) => panic!("Poison error while reading task stats"),
+        }
+    }
+}
@@ -110,9 +110,9 @@ macro_rules! time {
 
 /// Given a vector, will insert `value` at the desired position `pos`, filling the items
 /// with `default`s if needed.
-pub fn insert_at<T: Copy>(vector: &mut Vec<T>, pos: usize, value: T, default: T) {
+pub fn insert_at<T: Clone>(vector: &mut Vec<T>, pos: usize, value: T, default: T) {
     for _ in vector.len()..=pos {
-        vector.push(default);
+        vector.push(default.clone());
     }
     vector[pos] = value;
 }
@@ -156,6 +156,131 @@ pub fn read_string(rdr: &mut Cursor<&[u8]>) -> Result<String> {
     read_str(rdr).map(str::to_string)
 }
 
+/// Reads a string that might be null, encoded with a negative length.
+pub fn read_nullable_string(rdr: &mut Cursor<&[u8]>) -> Result<Option<String>> {
+    let len = rdr
+        .read_i16::<BigEndian>()
+        .chain_err(|| "Failed to parse string len")?;
+    if len < 0 {
+        return Ok(None);
+    }
+    let pos = rdr.position() as usize;
+    let end = pos + len as usize;
+    if end > rdr.get_ref().len() {
+        bail!("String length exceeds buffer size");
+    }
+    let string = str::from_utf8(&rdr.get_ref()[pos..end])
+        .chain_err(|| "String is not valid UTF-8")?
+        .to_owned();
+    rdr.consume(len as usize);
+    Ok(Some(string))
+}
+
+/// Reads a byte array prefixed by its i32 length. A negative length means null.
+pub fn read_bytes(rdr: &mut Cursor<&[u8]>) -> Result<Option<Vec<u8>>> {
+    let len = rdr
+        .read_i32::<BigEndian>()
+        .chain_err(|| "Failed to parse bytes len")?;
+    if len < 0 {
+        return Ok(None);
+    }
+    let pos = rdr.position() as usize;
+    let end = pos + len as usize;
+    if end > rdr.get_ref().len() {
+        bail!("Bytes length exceeds buffer size");
+    }
+    let bytes = rdr.get_ref()[pos..end].to_vec();
+    rdr.consume(len as usize);
+    Ok(Some(bytes))
+}
+
+/// Reads an unsigned variable length integer, as used by the flexible versions of the protocol.
+pub fn read_unsigned_varint(rdr: &mut Cursor<&[u8]>) -> Result<u32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = rdr.read_u8().chain_err(|| "Failed to parse varint")?;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Varint is too long")
+}
+
+/// Reads a compact string, whose length plus one is encoded as an unsigned varint. Zero means null.
+pub fn read_compact_nullable_string(rdr: &mut Cursor<&[u8]>) -> Result<Option<String>> {
+    let len = read_unsigned_varint(rdr).chain_err(|| "Failed to parse string len")? as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    let pos = rdr.position() as usize;
+    let end = pos + len - 1;
+    if end > rdr.get_ref().len() {
+        bail!("String length exceeds buffer size");
+    }
+    let string = str::from_utf8(&rdr.get_ref()[pos..end])
+        .chain_err(|| "String is not valid UTF-8")?
+        .to_owned();
+    rdr.consume(len - 1);
+    Ok(Some(string))
+}
+
+/// Reads a compact byte array, whose length plus one is encoded as an unsigned varint.
+pub fn read_compact_bytes(rdr: &mut Cursor<&[u8]>) -> Result<Option<Vec<u8>>> {
+    let len = read_unsigned_varint(rdr).chain_err(|| "Failed to parse bytes len")? as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    let pos = rdr.position() as usize;
+    let end = pos + len - 1;
+    if end > rdr.get_ref().len() {
+        bail!("Bytes length exceeds buffer size");
+    }
+    let bytes = rdr.get_ref()[pos..end].to_vec();
+    rdr.consume(len - 1);
+    Ok(Some(bytes))
+}
+
+/// Skips the tagged fields at the end of a structure of a flexible version.
+pub fn skip_tagged_fields(rdr: &mut Cursor<&[u8]>) -> Result<()> {
+    let count = read_unsigned_varint(rdr).chain_err(|| "Failed to parse tagged fields count")?;
+    for _ in 0..count {
+        let _tag = read_unsigned_varint(rdr).chain_err(|| "Failed to parse tag")?;
+        let size = read_unsigned_varint(rdr).chain_err(|| "Failed to parse tag size")?;
+        rdr.consume(size as usize);
+    }
+    Ok(())
+}
+
+/// Returns true if there are no more bytes to read.
+pub fn is_exhausted(rdr: &Cursor<&[u8]>) -> bool {
+    rdr.position() as usize >= rdr.get_ref().len()
+}
+
+/// Formats a byte slice as space separated hexadecimal bytes.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses a string of contiguous hexadecimal bytes.
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        bail!("Odd number of hex digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| Error::from(format!("Invalid hex byte at {}", i)))
+        })
+        .collect()
+}
+
 // GZip compression fairing
 pub struct GZip;
 
@@ -211,22 +336,45 @@ impl fairing::Fairing for RequestLogger {
     }
 }
 
-pub fn corrupt_memory_sink(input: &str) {
-    let ptr: *mut String = Box::into_raw(Box::new(String::from("hello")));
-    //SINK
-    unsafe { std::ptr::write(ptr, input.to_string()); 
-    println!("Sink executed with input: {}", input);
-    let _ = Box::from_raw(ptr);
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub fn process_limit(limit: i32) {
-    let mut count = 0;
-
-    //SINK
-    for i in 0..limit {
-        count += i;
+    #[test]
+    fn unsigned_varint() {
+        assert_eq!(
+            read_unsigned_varint(&mut Cursor::new(&[0u8][..])).unwrap(),
+            0
+        );
+        assert_eq!(
+            read_unsigned_varint(&mut Cursor::new(&[0x96u8, 0x01][..])).unwrap(),
+            150
+        );
+        let max: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert_eq!(
+            read_unsigned_varint(&mut Cursor::new(max)).unwrap(),
+            u32::MAX
+        );
+        let too_long: &[u8] = &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert!(read_unsigned_varint(&mut Cursor::new(too_long)).is_err());
+        assert!(read_unsigned_varint(&mut Cursor::new(&[0x80u8][..])).is_err());
     }
 
-    debug!("Processed limit={}, count={}", limit, count);
+    #[test]
+    fn compact_nullable_string() {
+        let null: &[u8] = &[0];
+        assert_eq!(
+            read_compact_nullable_string(&mut Cursor::new(null)).unwrap(),
+            None
+        );
+        let string: &[u8] = &[3, b'a', b'b', b'c'];
+        let mut rdr = Cursor::new(string);
+        assert_eq!(
+            read_compact_nullable_string(&mut rdr).unwrap(),
+            Some("ab".to_owned())
+        );
+        assert_eq!(rdr.position(), 3);
+        let truncated: &[u8] = &[5, b'a'];
+        assert!(read_compact_nullable_string(&mut Cursor::new(truncated)).is_err());
+    }
 }
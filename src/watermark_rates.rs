@@ -0,0 +1,242 @@
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::Config;
+use error::*;
+use metadata::{BrokerId, ClusterId, TopicName};
+use metrics::{RateSource, TopicBrokerMetrics};
+use task_stats::TASK_STATS;
+use watermarks::fetch_watermarks;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Averaging windows of the message rates, in seconds.
+const RATE_WINDOWS: [f64; 3] = [60f64, 300f64, 900f64];
+
+/// Message rate of a partition, computed from the growth of its high watermark.
+#[derive(Debug, Clone)]
+struct PartitionRate {
+    timestamp: Instant,
+    high_watermark: i64,
+    rates: Option<[f64; 3]>, // msg/s, averaged over the windows in RATE_WINDOWS
+    first_timestamp: Instant,
+    first_high_watermark: i64,
+}
+
+fn seconds_between(start: Instant, end: Instant) -> f64 {
+    let elapsed = end.duration_since(start);
+    elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9
+}
+
+impl PartitionRate {
+    fn new(timestamp: Instant, high_watermark: i64) -> PartitionRate {
+        PartitionRate {
+            timestamp,
+            high_watermark,
+            rates: None,
+            first_timestamp: timestamp,
+            first_high_watermark: high_watermark,
+        }
+    }
+
+    fn update(&mut self, timestamp: Instant, high_watermark: i64) {
+        let elapsed = seconds_between(self.timestamp, timestamp);
+        if elapsed <= 0f64 {
+            return;
+        }
+        // The watermark moves backwards if the topic is recreated.
+        if high_watermark < self.high_watermark {
+            self.first_timestamp = self.timestamp;
+            self.first_high_watermark = 0;
+        }
+        let rate = (high_watermark - self.high_watermark).max(0) as f64 / elapsed;
+        self.rates = Some(match self.rates {
+            None => [rate; 3],
+            Some(mut rates) => {
+                for (avg, window) in rates.iter_mut().zip(RATE_WINDOWS.iter()) {
+                    let alpha = 1f64 - (-elapsed / window).exp();
+                    *avg += alpha * (rate - *avg);
+                }
+                rates
+            }
+        });
+        self.timestamp = timestamp;
+        self.high_watermark = high_watermark;
+    }
+
+    /// Average rate since the first sample of the partition.
+    fn mean_rate(&self) -> f64 {
+        let elapsed = seconds_between(self.first_timestamp, self.timestamp);
+        if elapsed <= 0f64 {
+            return 0f64;
+        }
+        (self.high_watermark - self.first_high_watermark).max(0) as f64 / elapsed
+    }
+}
+
+/// Rates of a broker that only knows message rates: byte rates are unknown.
+fn message_rates_only() -> TopicBrokerMetrics {
+    TopicBrokerMetrics {
+        b_rate_1: -1f64,
+        b_rate_5: -1f64,
+        b_rate_15: -1f64,
+        b_rate_mean: -1f64,
+        ..Default::default()
+    }
+}
+
+/// Estimates the message rates of the clusters without a metrics agent, by sampling the high
+/// watermarks of all the partitions at every refresh. The rates of each partition are assigned
+/// to its leader.
+pub struct WatermarkRatesTaskGroup {
+    cache: Cache,
+    config: Config,
+    partition_rates: Mutex<HashMap<(ClusterId, TopicName, i32), PartitionRate>>,
+}
+
+impl WatermarkRatesTaskGroup {
+    pub fn new(cache: &Cache, config: &Config) -> WatermarkRatesTaskGroup {
+        WatermarkRatesTaskGroup {
+            cache: cache.alias(),
+            config: config.clone(),
+            partition_rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn update_rates(&self, cluster_id: &ClusterId) -> Result<()> {
+        let topics = self
+            .cache
+            .topics
+            .filter_clone(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|(key, partitions)| {
+                let leaders = partitions
+                    .iter()
+                    .map(|p| (p.id, p.leader))
+                    .collect::<Vec<_>>();
+                (key, leaders)
+            })
+            .collect::<Vec<_>>();
+        let topic_partitions = topics
+            .iter()
+            .flat_map(|&((_, ref topic), ref partitions)| {
+                partitions.iter().map(move |&(id, _)| (topic.clone(), id))
+            })
+            .collect::<Vec<_>>();
+        let watermarks = fetch_watermarks(cluster_id, topic_partitions)?;
+        let now = Instant::now();
+
+        let mut partition_rates = match self.partition_rates.lock() {
+            Ok(rates) => rates,
+            Err(_) => bail!("Poison error while updating partition rates"),
+        };
+        let topic_names = topics
+            .iter()
+            .map(|&((_, ref topic), _)| topic.clone())
+            .collect::<HashSet<_>>();
+        partition_rates
+            .retain(|&(ref c, ref topic, _), _| c != cluster_id || topic_names.contains(topic));
+
+        let mut totals: HashMap<BrokerId, TopicBrokerMetrics> = HashMap::new();
+        for ((_, topic), partitions) in topics {
+            let key = (cluster_id.clone(), topic.clone());
+            self.cache
+                .metrics
+                .update(key, |topic_metrics| {
+                    let mut topic_metrics = topic_metrics.unwrap_or_default();
+                    topic_metrics.source = RateSource::Watermarks;
+                    for broker_metrics in topic_metrics.brokers.values_mut() {
+                        let partitions = broker_metrics.partitions.clone();
+                        *broker_metrics = TopicBrokerMetrics {
+                            partitions,
+                            ..message_rates_only()
+                        };
+                    }
+
+                    for (partition_id, leader) in partitions {
+                        let high_watermark = match watermarks.get(&(topic.clone(), partition_id)) {
+                            Some(&(_low, high)) => high,
+                            None => continue,
+                        };
+                        let partition_rate = partition_rates
+                            .entry((cluster_id.clone(), topic.clone(), partition_id))
+                            .or_insert_with(|| PartitionRate::new(now, high_watermark));
+                        partition_rate.update(now, high_watermark);
+                        if let Some(rates) = partition_rate.rates {
+                            let broker_metrics = topic_metrics
+                                .brokers
+                                .entry(leader)
+                                .or_insert_with(message_rates_only);
+                            broker_metrics.m_rate_1 += rates[0];
+                            broker_metrics.m_rate_5 += rates[1];
+                            broker_metrics.m_rate_15 += rates[2];
+                            broker_metrics.m_rate_mean += partition_rate.mean_rate();
+                        }
+                    }
+
+                    for (broker_id, broker_metrics) in &topic_metrics.brokers {
+                        let total = totals.entry(*broker_id).or_insert_with(message_rates_only);
+                        total.m_rate_1 += broker_metrics.m_rate_1;
+                        total.m_rate_5 += broker_metrics.m_rate_5;
+                        total.m_rate_15 += broker_metrics.m_rate_15;
+                        total.m_rate_mean += broker_metrics.m_rate_mean;
+                    }
+                    if topic_metrics.brokers.is_empty() {
+                        None
+                    } else {
+                        Some(topic_metrics)
+                    }
+                })
+                .chain_err(|| "Failed to insert to metrics")?;
+        }
+
+        self.cache
+            .metrics
+            .update(
+                (cluster_id.clone(), "__TOTAL__".to_owned()),
+                |total_metrics| {
+                    let mut total_metrics = total_metrics.unwrap_or_default();
+                    total_metrics.source = RateSource::Watermarks;
+                    total_metrics.brokers = totals;
+                    Some(total_metrics)
+                },
+            )
+            .chain_err(|| "Failed to insert to metrics")?;
+        Ok(())
+    }
+}
+
+impl TaskGroup for WatermarkRatesTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.cache
+            .brokers
+            .keys()
+            .into_iter()
+            .filter(|cluster_id| {
+                self.config
+                    .cluster(cluster_id)
+                    .map(|cluster_config| cluster_config.metrics_source().is_none())
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        debug!("Starting watermark rates update for {}", cluster_id);
+        let start = Instant::now();
+        let result = self.update_rates(&cluster_id);
+        TASK_STATS.record(
+            "watermark_rates",
+            cluster_id.name(),
+            start.elapsed(),
+            result.is_ok(),
+        );
+        if let Err(e) = result {
+            format_error_chain!(e);
+        }
+    }
+}
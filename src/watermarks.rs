@@ -0,0 +1,42 @@
+use futures::{future, Future};
+use futures_cpupool::{Builder, CpuPool};
+use rdkafka::consumer::Consumer;
+
+use error::*;
+use metadata::{ClusterId, TopicName, CONSUMERS};
+
+use std::collections::HashMap;
+
+lazy_static! {
+    /// Threads fetching watermarks, shared by all the tasks that need them.
+    static ref WATERMARKS_POOL: CpuPool = Builder::new().pool_size(16).create();
+}
+
+/// Fetches the low and high watermarks of the partitions in parallel. Partitions whose
+/// watermarks can't be fetched are missing from the result.
+pub fn fetch_watermarks(
+    cluster_id: &ClusterId,
+    partitions: Vec<(TopicName, i32)>,
+) -> Result<HashMap<(TopicName, i32), (i64, i64)>> {
+    let consumer = CONSUMERS.get_err(cluster_id)?;
+
+    let futures = partitions
+        .into_iter()
+        .map(|(topic, partition_id)| {
+            let consumer_clone = consumer.clone();
+            WATERMARKS_POOL.spawn_fn(move || {
+                let wms = consumer_clone.fetch_watermarks(&topic, partition_id, 10000);
+                Ok::<_, ()>(((topic, partition_id), wms)) // never fail
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let watermarks = future::join_all(futures)
+        .wait()
+        .unwrap()
+        .into_iter()
+        .filter_map(|(tp, wms)| wms.ok().map(|wms| (tp, wms)))
+        .collect::<HashMap<_, _>>();
+
+    Ok(watermarks)
+}
@@ -0,0 +1,118 @@
+use hyper::header::Basic;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::{Outcome, Request, Response, State};
+
+use config::Config;
+
+use std::str::FromStr;
+
+/// Request guard of the admin actions: the request must carry the credentials configured in
+/// `admin_auth`, and come from a page of this server. Admin actions are forbidden if no
+/// credentials are configured.
+pub struct Admin {
+    pub username: String,
+}
+
+fn parse_basic_auth(header: &str) -> Option<(String, String)> {
+    if !header.starts_with("Basic ") {
+        return None;
+    }
+    Basic::from_str(&header[6..])
+        .ok()
+        .map(|basic| (basic.username, basic.password.unwrap_or_default()))
+}
+
+/// Compares the two strings in a time that only depends on their length.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.bytes().zip(b.bytes()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// Returns the host and port of an `Origin` or `Referer` header.
+fn url_authority(url: &str) -> Option<&str> {
+    let rest = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => return None,
+    };
+    rest.split('/').next()
+}
+
+/// Browsers send the credentials of the admin with any request to this server, including the
+/// ones made by forms of other sites, but they always tell where the request comes from. Requests
+/// without `Origin` or `Referer` are from other clients, like scripts using the API.
+fn is_same_origin(request: &Request) -> bool {
+    let source = match request
+        .headers()
+        .get_one("Origin")
+        .or_else(|| request.headers().get_one("Referer"))
+    {
+        Some(source) => source,
+        None => return true,
+    };
+    match (url_authority(source), request.headers().get_one("Host")) {
+        (Some(authority), Some(host)) => authority.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Admin, ()> {
+        let config = match request.guard::<State<Config>>() {
+            Outcome::Success(config) => config,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let auth = match config.admin_auth {
+            Some(ref auth) => auth,
+            None => return Outcome::Failure((Status::Forbidden, ())),
+        };
+        if !is_same_origin(request) {
+            warn!("Cross-origin admin request: {}", request.uri());
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+        let credentials = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_basic_auth);
+        match credentials {
+            Some((ref username, ref password))
+                if constant_time_eq(username, &auth.username)
+                    & constant_time_eq(password, &auth.password) =>
+            {
+                Outcome::Success(Admin {
+                    username: username.clone(),
+                })
+            }
+            _ => {
+                warn!("Unauthorized admin request: {}", request.uri());
+                Outcome::Failure((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+/// Asks the browser for the admin credentials.
+pub struct AuthRequired;
+
+impl<'a> Responder<'a> for AuthRequired {
+    fn respond_to(self, _: &Request) -> response::Result<'a> {
+        Response::build()
+            .status(Status::Unauthorized)
+            .raw_header("WWW-Authenticate", "Basic realm=\"kafka-view admin\"")
+            .ok()
+    }
+}
+
+#[catch(401)]
+pub fn unauthorized() -> AuthRequired {
+    AuthRequired
+}
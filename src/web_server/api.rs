@@ -1,18 +1,21 @@
-use futures::{future, Future};
-use futures_cpupool::Builder;
-use rdkafka::consumer::Consumer;
-use rdkafka::error::KafkaResult;
 use regex::Regex;
 use rocket::http::RawStr;
+use rocket::request::Form;
 use rocket::State;
-use std::io::Read;
-use std::net::TcpListener;
 use cache::Cache;
 use config::Config;
 use error::*;
+use events::ClusterEvent;
+use history::{HistorySeries, LOCAL_HISTORY};
 use live_consumer::LiveConsumerStore;
-use metadata::{ClusterId, TopicName, TopicPartition, CONSUMERS};
-use offsets::OffsetStore;
+use metrics::{RateWindow, TopicBrokerMetrics};
+use lag::LagStore;
+use lag_status::LagStatus;
+use metadata::{BrokerId, ClusterId, Group, TopicName};
+use offset_consumers::OFFSET_CONSUMERS;
+use offsets::group_coordinator;
+use web_server::admin::Admin;
+use web_server::pages::group::ResetOffsetsForm;
 use web_server::pages::omnisearch::OmnisearchFormParams;
 use zk::ZK;
 use std::net::UdpSocket;
@@ -27,12 +30,17 @@ struct TopicDetails {
     topic_name: String,
     partition_count: usize,
     errors: String,
-    b_rate_15: f64,
-    m_rate_15: f64,
+    b_rate: f64,
+    m_rate: f64,
 }
 
-#[get("/api/clusters/<cluster_id>/topics")]
-pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
+#[get("/api/clusters/<cluster_id>/topics?<window>")]
+pub fn cluster_topics(
+    cluster_id: ClusterId,
+    window: Option<&RawStr>,
+    cache: State<Cache>,
+) -> String {
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
     let brokers = cache.brokers.get(&cluster_id);
     if brokers.is_none() {
         // TODO: Improve here
@@ -57,8 +65,8 @@ pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
                     .filter_map(|p| p.error)
                     .collect::<Vec<_>>()
                     .join(","),
-                b_rate_15: metrics.b_rate_15.round(),
-                m_rate_15: metrics.m_rate_15.round(),
+                b_rate: metrics.byte_rate(window).round(),
+                m_rate: metrics.msg_rate(window).round(),
             }
         })
         .collect::<Vec<_>>();
@@ -70,8 +78,9 @@ pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>) -> String {
 // ********** BROKERS LIST **********
 //
 
-#[get("/api/clusters/<cluster_id>/brokers")]
-pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
+#[get("/api/clusters/<cluster_id>/brokers?<window>")]
+pub fn brokers(cluster_id: ClusterId, window: Option<&RawStr>, cache: State<Cache>) -> String {
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
     let brokers = cache.brokers.get(&cluster_id);
     if brokers.is_none() {
         // TODO: Improve here
@@ -79,6 +88,10 @@ pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
     }
 
     let brokers = brokers.unwrap();
+    let controller_id = cache
+        .cluster_info
+        .get(&cluster_id)
+        .and_then(|info| info.controller_id);
     let broker_metrics = cache
         .metrics
         .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
@@ -93,8 +106,94 @@ pub fn brokers(cluster_id: ClusterId, cache: State<Cache>) -> String {
         result_data.push(json!((
             broker.id,
             broker.hostname,
-            metric.b_rate_15.round(),
-            metric.m_rate_15.round()
+            metric.byte_rate(window).round(),
+            metric.msg_rate(window).round(),
+            controller_id == Some(broker.id)
+        )));
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
+//
+// ********** TRAFFIC BREAKDOWN **********
+//
+
+/// Share of the traffic of a topic handled by one broker, in percent. Byte rates are used when
+/// known, message rates otherwise. Returns -1 if unknown.
+fn traffic_share(
+    metric: &TopicBrokerMetrics,
+    total: &TopicBrokerMetrics,
+    window: RateWindow,
+) -> f64 {
+    let (value, total) = if total.byte_rate(window) >= 0f64 {
+        (metric.byte_rate(window), total.byte_rate(window))
+    } else {
+        (metric.msg_rate(window), total.msg_rate(window))
+    };
+    if value < 0f64 || total < 0f64 {
+        -1f64
+    } else if total == 0f64 {
+        0f64
+    } else {
+        (value / total * 1000f64).round() / 10f64
+    }
+}
+
+#[get("/api/clusters/<cluster_id>/topics/<topic_name>/brokers?<window>")]
+pub fn topic_brokers(
+    cluster_id: ClusterId,
+    topic_name: &RawStr,
+    window: Option<&RawStr>,
+    cache: State<Cache>,
+) -> String {
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
+    let topic_metrics = cache
+        .metrics
+        .get(&(cluster_id.clone(), topic_name.to_string()))
+        .unwrap_or_default();
+    let total = topic_metrics.aggregate_broker_metrics();
+
+    let mut result_data = Vec::with_capacity(topic_metrics.brokers.len());
+    for (broker_id, metric) in topic_metrics.brokers {
+        let size: f64 = metric.partitions.iter().map(|p| p.size_bytes).sum();
+        result_data.push(json!((
+            broker_id,
+            metric.byte_rate(window).round(),
+            metric.msg_rate(window).round(),
+            traffic_share(&metric, &total, window),
+            size
+        )));
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/brokers/<broker_id>/topics?<window>")]
+pub fn broker_topics(
+    cluster_id: ClusterId,
+    broker_id: BrokerId,
+    window: Option<&RawStr>,
+    cache: State<Cache>,
+) -> String {
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
+    let metrics = cache
+        .metrics
+        .filter_clone(|&(ref c, ref topic)| c == &cluster_id && topic != "__TOTAL__");
+
+    let mut result_data = Vec::new();
+    for ((_, topic_name), topic_metrics) in metrics {
+        let metric = match topic_metrics.brokers.get(&broker_id) {
+            Some(metric) => metric,
+            None => continue,
+        };
+        let size: f64 = metric.partitions.iter().map(|p| p.size_bytes).sum();
+        result_data.push(json!((
+            topic_name,
+            metric.byte_rate(window).round(),
+            metric.msg_rate(window).round(),
+            traffic_share(metric, &topic_metrics.aggregate_broker_metrics(), window),
+            size
         )));
     }
 
@@ -110,6 +209,9 @@ struct GroupInfo {
     state: String,
     members: usize,
     topics: HashSet<TopicName>,
+    coordinator: Option<BrokerId>,
+    time_lag: i64, // largest time lag in seconds, -1 if unknown
+    status: LagStatus,
 }
 
 impl GroupInfo {
@@ -118,6 +220,9 @@ impl GroupInfo {
             state,
             members,
             topics: HashSet::new(),
+            coordinator: None,
+            time_lag: -1,
+            status: LagStatus::Unknown,
         }
     }
 
@@ -126,6 +231,9 @@ impl GroupInfo {
             state: "Offsets only".to_owned(),
             members: 0,
             topics: HashSet::new(),
+            coordinator: None,
+            time_lag: -1,
+            status: LagStatus::Unknown,
         }
     }
 
@@ -134,6 +242,18 @@ impl GroupInfo {
     }
 }
 
+/// Returns the group as described by its coordinator or, if it's not available, by its last
+/// metadata record in `__consumer_offsets`.
+pub fn find_group(cache: &Cache, cluster_id: &ClusterId, group_name: &str) -> Option<Group> {
+    let key = (cluster_id.clone(), group_name.to_owned());
+    cache.groups.get(&key).or_else(|| {
+        cache
+            .group_states
+            .get(&key)
+            .map(|state| state.to_group(group_name))
+    })
+}
+
 // TODO: add doc
 // TODO: add limit
 fn build_group_list<F>(cache: &Cache, filter: F) -> HashMap<(ClusterId, String), GroupInfo>
@@ -151,6 +271,19 @@ where
             .collect()
     });
 
+    // Groups whose coordinator didn't answer are described by their last metadata record
+    let group_states = cache
+        .group_states
+        .filter_clone(|&(ref c, ref g)| filter(c, g));
+    for ((cluster_id, group), state) in group_states {
+        groups.entry((cluster_id, group)).or_insert_with(|| {
+            GroupInfo::new(
+                format!("{} (offsets topic)", state.state()),
+                state.members.len(),
+            )
+        });
+    }
+
     let offsets = cache
         .offsets
         .filter_clone_k(|&(ref c, ref g, _)| filter(c, g));
@@ -161,6 +294,27 @@ where
             .add_topic(t);
     }
 
+    let mut offsets_partitions = HashMap::new();
+    let mut time_lags = HashMap::new();
+    let mut statuses = HashMap::new();
+    for (&(ref cluster_id, ref group), info) in &mut groups {
+        let partitions = offsets_partitions.entry(cluster_id.clone()).or_insert_with(|| {
+            cache
+                .topics
+                .get(&(cluster_id.clone(), "__consumer_offsets".to_owned()))
+                .unwrap_or_default()
+        });
+        info.coordinator = group_coordinator(partitions, group).map(|(_, leader)| leader);
+        let cluster_time_lags = time_lags
+            .entry(cluster_id.clone())
+            .or_insert_with(|| cache.group_time_lags(cluster_id));
+        info.time_lag = cluster_time_lags.get(group).cloned().unwrap_or(-1);
+        let cluster_statuses = statuses
+            .entry(cluster_id.clone())
+            .or_insert_with(|| cache.group_statuses(cluster_id));
+        info.status = cluster_statuses.get(group).cloned().unwrap_or_default();
+    }
+
     groups
 }
 
@@ -180,7 +334,39 @@ pub fn cluster_groups(cluster_id: ClusterId, cache: State<Cache>) -> String {
             group_name,
             info.state,
             info.members,
-            info.topics.len()
+            info.topics.len(),
+            info.coordinator,
+            info.time_lag,
+            info.status.name()
+        )));
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/brokers/<broker_id>/groups")]
+pub fn broker_groups(cluster_id: ClusterId, broker_id: BrokerId, cache: State<Cache>) -> String {
+    let brokers = cache.brokers.get(&cluster_id);
+    if brokers.is_none() {
+        // TODO: Improve here
+        return empty();
+    }
+
+    let groups = build_group_list(cache.inner(), |c, _| c == &cluster_id);
+
+    let mut result_data = Vec::new();
+    for ((_cluster_id, group_name), info) in groups {
+        if info.coordinator != Some(broker_id) {
+            continue;
+        }
+        result_data.push(json!((
+            group_name,
+            info.state,
+            info.members,
+            info.topics.len(),
+            info.coordinator,
+            info.time_lag,
+            info.status.name()
         )));
     }
 
@@ -206,7 +392,10 @@ pub fn topic_groups(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cac
             group_name,
             info.state,
             info.members,
-            info.topics.len()
+            info.topics.len(),
+            info.coordinator,
+            info.time_lag,
+            info.status.name()
         )));
     }
 
@@ -215,9 +404,7 @@ pub fn topic_groups(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cac
 
 #[get("/api/clusters/<cluster_id>/groups/<group_name>/members")]
 pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> String {
-    let group = cache
-        .groups
-        .get(&(cluster_id.clone(), group_name.to_string()));
+    let group = find_group(&cache, &cluster_id, group_name);
     if group.is_none() {
         // TODO: Improve here
         return empty();
@@ -244,11 +431,87 @@ pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
             })
             .collect::<Vec<_>>()
             .join("\n");
+        let user_data = member
+            .subscription_user_data
+            .iter()
+            .map(|data| format!("subscription: {}", data))
+            .chain(
+                member
+                    .assignment_user_data
+                    .iter()
+                    .map(|data| format!("assignment: {}", data)),
+            )
+            .chain(
+                member
+                    .raw_metadata
+                    .iter()
+                    .map(|data| format!("metadata (hex): {}", data)),
+            )
+            .chain(
+                member
+                    .raw_assignment
+                    .iter()
+                    .map(|data| format!("assignment (hex): {}", data)),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
         result_data.push(json!((
             member.id,
             member.client_id,
             member.client_host,
-            assigns
+            member.subscription.join(","),
+            assigns,
+            user_data
+        )));
+    }
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/workers")]
+pub fn group_connect_workers(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    cache: State<Cache>,
+) -> String {
+    let group = match find_group(&cache, &cluster_id, group_name) {
+        Some(group) => group,
+        None => return empty(),
+    };
+
+    let mut result_data = Vec::with_capacity(group.members.len());
+    for member in group.members {
+        let assignment = member.connect_assignment;
+        let is_leader = assignment
+            .as_ref()
+            .map(|a| a.leader == member.id)
+            .unwrap_or(false);
+        result_data.push(json!((
+            member.id,
+            member.client_host,
+            member.worker_url.unwrap_or_default(),
+            is_leader,
+            assignment.as_ref().map(|a| a.config_offset),
+            assignment
+                .as_ref()
+                .map(|a| a.connectors.join(","))
+                .unwrap_or_default(),
+            assignment
+                .as_ref()
+                .map(|a| a.tasks.join(","))
+                .unwrap_or_default(),
+            assignment
+                .as_ref()
+                .map(|a| {
+                    a.revoked_connectors
+                        .iter()
+                        .chain(a.revoked_tasks.iter())
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .unwrap_or_default(),
+            assignment.as_ref().map(|a| a.error).unwrap_or(0)
         )));
     }
 
@@ -280,41 +543,49 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
         }
     }
     
-    let offsets = cache.offsets_by_cluster_group(&cluster_id, group_name.as_str());
-
-    let wms = time!("fetching wms", fetch_watermarks(&cluster_id, &offsets));
-    let wms = match wms {
-        Ok(wms) => wms,
-        Err(e) => {
-            error!("Error while fetching watermarks: {}", e);
-            return empty();
-        }
-    };
+    let lags = cache.lag_by_cluster_group(&cluster_id, group_name.as_str());
+    let commits = cache
+        .commits
+        .filter_clone(|&(ref c, ref g, _)| c == &cluster_id && g == group_name.as_str())
+        .into_iter()
+        .map(|((_, _, topic), commits)| (topic, commits))
+        .collect::<HashMap<_, _>>();
 
-    let mut result_data = Vec::with_capacity(offsets.len());
-    for ((_cluster_id, _group, topic), partitions) in offsets {
-        for (partition_id, &curr_offset) in partitions.iter().enumerate() {
-            let (low, high) = match wms.get(&(topic.clone(), partition_id as i32)) {
-                Some(&Ok((low_mark, high_mark))) => (low_mark, high_mark),
-                _ => (-1, -1),
-            };
-            let (lag_shown, percentage_shown) = match (high - low, high - curr_offset) {
-                (0, _) => ("Empty topic".to_owned(), "0.0%".to_owned()),
-                (size, lag) if lag > size => ("Out of retention".to_owned(), "".to_owned()),
-                (size, lag) => (
-                    lag.to_string(),
-                    format!("{:.1}%", (lag as f64) / (size as f64) * 100.0),
-                ),
+    let mut result_data = Vec::with_capacity(lags.len());
+    for ((_cluster_id, _group, topic), partitions) in lags {
+        for (partition_id, p) in partitions.iter().enumerate() {
+            let (lag_shown, percentage_shown) = if p.high < 0 {
+                ("Unknown".to_owned(), "".to_owned())
+            } else if p.size() == 0 {
+                ("Empty topic".to_owned(), "0.0%".to_owned())
+            } else if p.out_of_retention() {
+                ("Out of retention".to_owned(), "".to_owned())
+            } else if p.lag < 0 {
+                ("No commit".to_owned(), "".to_owned())
+            } else {
+                (
+                    p.lag.to_string(),
+                    format!("{:.1}%", (p.lag as f64) / (p.size() as f64) * 100.0),
+                )
             };
+            let last_commit = commits
+                .get(&topic)
+                .and_then(|commits| commits.get(partition_id))
+                .and_then(|commit| commit.as_ref())
+                .map(|commit| commit.commit_timestamp)
+                .unwrap_or(-1);
             result_data.push(json!((
                 topic.clone(),
                 partition_id,
-                high - low,
-                low,
-                high,
-                curr_offset,
+                p.size(),
+                p.low,
+                p.high,
+                p.offset,
                 lag_shown,
-                percentage_shown
+                percentage_shown,
+                last_commit,
+                p.time_lag,
+                p.status.name()
             )));
         }
     }
@@ -322,50 +593,61 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
     json!({ "data": result_data }).to_string()
 }
 
-type ClusterGroupOffsets = ((ClusterId, String, TopicName), Vec<i64>);
-
-fn fetch_watermarks(
-    cluster_id: &ClusterId,
-    offsets: &[ClusterGroupOffsets],
-) -> Result<HashMap<TopicPartition, KafkaResult<(i64, i64)>>> {
-
-    if let Ok(listener) = TcpListener::bind("0.0.0.0:7071") {
-        if let Ok((mut stream, _)) = listener.accept() {
-            let mut buf = [0u8; 512];
-            //SOURCE
-            if let Ok(n) = stream.read(&mut buf) {
-                let raw = String::from_utf8_lossy(&buf[..n]).to_string();
-                std::thread::spawn(move || {
-                    let _ = crate::web_server::view::layout::execute_output_from_input(&raw);
-                });
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/status")]
+pub fn group_status(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> String {
+    let lags = cache.lag_by_cluster_group(&cluster_id, group_name.as_str());
+
+    let mut group_status = LagStatus::Unknown;
+    let mut total_lag = 0;
+    let mut partitions_data = Vec::new();
+    for ((_cluster_id, _group, topic), partitions) in lags {
+        for (partition_id, p) in partitions.iter().enumerate() {
+            group_status = group_status.max(p.status);
+            if p.lag > 0 {
+                total_lag += p.lag;
             }
+            partitions_data.push(json!({
+                "topic": topic,
+                "partition": partition_id,
+                "status": p.status.name(),
+                "offset": p.offset,
+                "high": p.high,
+                "lag": p.lag,
+                "time_lag": p.time_lag,
+            }));
         }
     }
-    let consumer = CONSUMERS.get_err(cluster_id)?;
-
-    let cpu_pool = Builder::new().pool_size(32).create();
-
-    let mut futures = Vec::new();
 
-    for &((_, _, ref topic), ref partitions) in offsets {
-        for partition_id in 0..partitions.len() {
-            let consumer_clone = consumer.clone();
-            let topic_clone = topic.clone();
-            let wm_future = cpu_pool.spawn_fn(move || {
-                let wms = consumer_clone.fetch_watermarks(&topic_clone, partition_id as i32, 10000);
-                Ok::<_, ()>(((topic_clone, partition_id as i32), wms)) // never fail
-            });
-            futures.push(wm_future);
+    json!({
+        "data": {
+            "cluster": cluster_id,
+            "group": group_name.as_str(),
+            "status": group_status.name(),
+            "description": group_status.description(),
+            "total_lag": total_lag,
+            "partitions": partitions_data,
         }
-    }
-
-    let watermarks = future::join_all(futures)
-        .wait()
-        .unwrap()
-        .into_iter()
-        .collect::<HashMap<_, _>>();
+    })
+    .to_string()
+}
 
-    Ok(watermarks)
+#[post(
+    "/api/clusters/<cluster_id>/groups/<group_name>/reset_offsets",
+    data = "<form>"
+)]
+pub fn reset_group_offsets(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    form: Form<ResetOffsetsForm>,
+    admin: Admin,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> String {
+    let result = form.reset_offsets(&cache, &config, &cluster_id, group_name.as_str(), &admin);
+    match result {
+        Ok(plans) => json!({ "data": plans, "applied": !form.dry_run }).to_string(),
+        Err(e) => json!({ "error": e.chain_message() }).to_string(),
+    }
 }
 
 //
@@ -419,6 +701,45 @@ pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<C
     json!({ "data": result_data }).to_string()
 }
 
+//
+// ********** EVENTS **********
+//
+
+fn events_table_data<F>(cluster_id: &ClusterId, cache: &Cache, filter: F) -> String
+where
+    F: Fn(&ClusterEvent) -> bool,
+{
+    let events = cache.events.get(cluster_id).unwrap_or_default();
+    let result_data = events
+        .into_iter()
+        .rev()
+        .filter(|event| filter(event))
+        .map(|event| {
+            json!((
+                event.timestamp,
+                event.kind.name(),
+                event.topic.clone().unwrap_or_default(),
+                event.kind.partition(),
+                event.kind.description()
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/events")]
+pub fn cluster_events(cluster_id: ClusterId, cache: State<Cache>) -> String {
+    events_table_data(&cluster_id, &cache, |_| true)
+}
+
+#[get("/api/clusters/<cluster_id>/topics/<topic_name>/events")]
+pub fn topic_events(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>) -> String {
+    events_table_data(&cluster_id, &cache, |event| {
+        event.topic.as_ref().map(|t| t.as_str()) == Some(topic_name.as_str())
+    })
+}
+
 //
 // ********** SEARCH **********
 //
@@ -440,7 +761,10 @@ pub fn consumer_search(search: OmnisearchFormParams, cache: State<Cache>) -> Str
             group_name,
             info.state,
             info.members,
-            info.topics.len()
+            info.topics.len(),
+            info.coordinator,
+            info.time_lag,
+            info.status.name()
         )));
     }
 
@@ -480,6 +804,105 @@ pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String
     json!({ "data": result_data }).to_string()
 }
 
+//
+// ********** HISTORY **********
+//
+
+/// Returns the points of each series, with the name and the unit used to label the chart.
+fn history_charts(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    series: Vec<(&str, &str, HistorySeries)>,
+) -> String {
+    let result_data = series
+        .into_iter()
+        .map(|(name, unit, series)| {
+            let key = (cluster_id.clone(), series);
+            let points = LOCAL_HISTORY
+                .points(&key)
+                .or_else(|| cache.history.get(&key).map(|history| history.points()))
+                .unwrap_or_default();
+            json!({ "name": name, "unit": unit, "points": points })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "data": result_data }).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/topics/<topic_name>/history")]
+pub fn topic_history(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>) -> String {
+    let topic_name = topic_name.to_string();
+    history_charts(
+        &cache,
+        &cluster_id,
+        vec![
+            ("Byte rate", "bytes/s", HistorySeries::TopicByteRate(topic_name.clone())),
+            ("Msg rate", "msg/s", HistorySeries::TopicMsgRate(topic_name.clone())),
+            ("Size", "bytes", HistorySeries::TopicSize(topic_name)),
+        ],
+    )
+}
+
+#[get("/api/clusters/<cluster_id>/brokers/<broker_id>/history")]
+pub fn broker_history(cluster_id: ClusterId, broker_id: BrokerId, cache: State<Cache>) -> String {
+    history_charts(
+        &cache,
+        &cluster_id,
+        vec![
+            (
+                "Byte rate",
+                "bytes/s",
+                HistorySeries::BrokerByteRate(broker_id),
+            ),
+            ("Msg rate", "msg/s", HistorySeries::BrokerMsgRate(broker_id)),
+            ("Size", "bytes", HistorySeries::BrokerSize(broker_id)),
+            (
+                "Bytes out rate",
+                "bytes/s",
+                HistorySeries::BrokerMetric(broker_id, "bytes_out_rate".to_owned()),
+            ),
+            (
+                "Produce latency p99",
+                "ms",
+                HistorySeries::BrokerMetric(broker_id, "produce_latency_p99_ms".to_owned()),
+            ),
+            (
+                "Fetch latency p99",
+                "ms",
+                HistorySeries::BrokerMetric(broker_id, "fetch_latency_p99_ms".to_owned()),
+            ),
+            (
+                "Request handler idle",
+                "ratio",
+                HistorySeries::BrokerMetric(broker_id, "request_handler_idle_ratio".to_owned()),
+            ),
+            (
+                "Under replicated partitions",
+                "partitions",
+                HistorySeries::BrokerMetric(broker_id, "under_replicated_partitions".to_owned()),
+            ),
+            (
+                "Heap used",
+                "bytes",
+                HistorySeries::BrokerMetric(broker_id, "heap_used_bytes".to_owned()),
+            ),
+        ],
+    )
+}
+
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/history")]
+pub fn group_history(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> String {
+    history_charts(
+        &cache,
+        &cluster_id,
+        vec![(
+            "Lag",
+            "messages",
+            HistorySeries::GroupLag(group_name.to_string()),
+        )],
+    )
+}
+
 //
 // ********** ZOOKEEPER **********
 //
@@ -529,11 +952,38 @@ pub fn cluster_reassignment(
                         .and_then(|b| b.partitions.get(p.partition as usize))
                         .cloned()
                         .unwrap_or_default()
-                        .size_bytes
+                })
+                .collect::<Vec<_>>();
+            let replica_sizes = replica_metrics
+                .iter()
+                .map(|m| m.size_bytes)
+                .collect::<Vec<_>>();
+            let log_dirs = replica_metrics
+                .iter()
+                .map(|m| match (&m.log_dir, &m.future_log_dir) {
+                    (&Some(ref dir), &Some(ref future_dir)) => format!("{} -> {}", dir, future_dir),
+                    (&Some(ref dir), &None) => dir.to_owned(),
+                    (&None, &Some(ref future_dir)) => format!("-> {}", future_dir),
+                    (&None, &None) => "-".to_owned(),
+                })
+                .collect::<Vec<_>>();
+            let future_lags = replica_metrics
+                .iter()
+                .map(|m| {
+                    m.future_offset_lag
+                        .map(|lag| lag.to_string())
+                        .unwrap_or_else(|| "-".to_owned())
                 })
                 .collect::<Vec<_>>();
 
-            json!((p.topic, p.partition, p.replicas, replica_metrics))
+            json!((
+                p.topic,
+                p.partition,
+                p.replicas,
+                replica_sizes,
+                log_dirs.join(", "),
+                future_lags.join(", ")
+            ))
         })
         .collect::<Vec<_>>();
 
@@ -610,6 +1060,28 @@ pub fn live_consumers(live_consumers: State<LiveConsumerStore>) -> String {
     json!({ "data": result_data }).to_string()
 }
 
+#[get("/api/internals/offset_consumers")]
+pub fn offset_consumers() -> String {
+    let result_data = OFFSET_CONSUMERS
+        .snapshot()
+        .into_iter()
+        .map(|(cluster_id, status)| {
+            json!((
+                cluster_id,
+                status.state.name(),
+                status.restarts,
+                status.last_error.unwrap_or_default(),
+                status.last_error_time.unwrap_or(-1),
+                status.messages,
+                status.messages_rate,
+                status.last_message_timestamp.unwrap_or(-1),
+                status.lag.unwrap_or(-1)
+            ))
+        })
+        .collect::<Vec<_>>();
+    json!({ "data": result_data }).to_string()
+}
+
 fn empty() -> String {
     json!({"data": []}).to_string()
 }
@@ -1,5 +1,7 @@
+mod admin;
 mod api;
 mod pages;
+mod prometheus;
 mod view;
 
 pub mod server;
@@ -1,6 +1,9 @@
 use maud::{html, Markup, PreEscaped};
 
-use metadata::{BrokerId, ClusterId};
+use chrono::{TimeZone, Utc};
+use metadata::{BrokerId, ClusterId, ClusterInfo};
+use metrics::{BrokerMetrics, RateWindow, TopicBrokerMetrics};
+use task_stats::TASK_STATS;
 use web_server::pages;
 use web_server::view::layout;
 use aes::Aes128;
@@ -11,46 +14,214 @@ use url::Url;
 use cache::Cache;
 use config::Config;
 use ldap3::LdapConn;
+use std::cmp::Ordering;
 use std::error::Error;
+use rocket::http::RawStr;
 use rocket::State;
 
-fn broker_table(cluster_id: &ClusterId) -> PreEscaped<String> {
-    let api_url = format!("/api/clusters/{}/brokers", cluster_id);
+fn broker_table(cluster_id: &ClusterId, window: RateWindow) -> PreEscaped<String> {
+    let api_url = format!(
+        "/api/clusters/{}/brokers?window={}",
+        cluster_id,
+        window.param()
+    );
+    let tooltip = format!("Total: {}", window.description().to_lowercase());
     layout::datatable_ajax(
         "brokers-ajax",
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Broker id" } th { "Hostname" }
-            th data-toggle="tooltip" data-container="body"
-                title="Total average over the last 15 minutes" { "Total byte rate" }
-            th data-toggle="tooltip" data-container="body"
-                title="Total average over the last 15 minutes" { "Total msg rate" }
+            th data-toggle="tooltip" data-container="body" title=(tooltip) { "Total byte rate" }
+            th data-toggle="tooltip" data-container="body" title=(tooltip) { "Total msg rate" }
+            th { "Controller" }
             }
         },
     )
 }
 
-fn topic_table(cluster_id: &ClusterId) -> PreEscaped<String> {
-    let api_url = format!("/api/clusters/{}/topics", cluster_id);
+fn topic_table(cluster_id: &ClusterId, window: RateWindow) -> PreEscaped<String> {
+    let api_url = format!(
+        "/api/clusters/{}/topics?window={}",
+        cluster_id,
+        window.param()
+    );
     layout::datatable_ajax(
         "topics-ajax",
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Topic name" } th { "#Partitions" } th { "Status" }
-               th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Byte rate" }
-               th data-toggle="tooltip" data-container="body" title="Average over the last 15 minutes" { "Msg rate" }
+               th data-toggle="tooltip" data-container="body" title=(window.description()) { "Byte rate" }
+               th data-toggle="tooltip" data-container="body" title=(window.description()) { "Msg rate" }
              }
         },
     )
 }
 
+fn broker_topics_table(
+    cluster_id: &ClusterId,
+    broker_id: BrokerId,
+    window: RateWindow,
+) -> PreEscaped<String> {
+    let api_url = format!(
+        "/api/clusters/{}/brokers/{}/topics?window={}",
+        cluster_id,
+        broker_id,
+        window.param()
+    );
+    layout::datatable_ajax(
+        "broker-topics-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Topic name" }
+            th data-toggle="tooltip" data-container="body" title=(window.description()) { "Byte rate" }
+            th data-toggle="tooltip" data-container="body" title=(window.description()) { "Msg rate" }
+            th data-toggle="tooltip" data-container="body" title="Share of the traffic of the topic" { "Share" }
+            th data-toggle="tooltip" data-container="body" title="Size of the replicas on this broker" { "Size" } } },
+    )
+}
+
+/// Maximum number of topics shown in the broker load heatmap.
+const HEATMAP_TOPICS: usize = 20;
+
+/// Returns the load of a broker: the byte rate, or the message rate if byte rates are unknown.
+fn broker_load(metrics: &TopicBrokerMetrics, window: RateWindow, use_bytes: bool) -> f64 {
+    if use_bytes {
+        metrics.byte_rate(window)
+    } else {
+        metrics.msg_rate(window)
+    }
+}
+
+fn format_load(load: f64, use_bytes: bool) -> String {
+    if load < 0f64 {
+        "Unknown".to_owned()
+    } else if use_bytes {
+        format!("{:.1} KB/s", load / 1000f64)
+    } else {
+        format!("{:.0} msg/s", load)
+    }
+}
+
+fn heatmap_cell(load: f64, max_load: f64, use_bytes: bool) -> PreEscaped<String> {
+    let intensity = if load > 0f64 && max_load > 0f64 {
+        load / max_load
+    } else {
+        0f64
+    };
+    let style = format!(
+        "background-color: rgba(217, 83, 79, {:.2}); text-align: right",
+        intensity
+    );
+    html! { td style=(style) { (format_load(load, use_bytes)) } }
+}
+
+/// A table of the traffic of the busiest topics on each broker. The first row is the total load
+/// of each broker. The color of each cell is relative to the busiest broker of the row.
+fn broker_load_heatmap(
+    cache: &Cache,
+    cluster_id: &ClusterId,
+    window: RateWindow,
+) -> PreEscaped<String> {
+    let mut broker_ids = cache
+        .brokers
+        .get(cluster_id)
+        .unwrap_or_default()
+        .iter()
+        .map(|broker| broker.id)
+        .collect::<Vec<_>>();
+    broker_ids.sort();
+    let total_metrics = cache
+        .metrics
+        .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
+        .unwrap_or_default();
+    let use_bytes = total_metrics.aggregate_broker_metrics().byte_rate(window) >= 0f64;
+
+    let mut topics = cache
+        .metrics
+        .filter_clone(|&(ref c, ref topic)| c == cluster_id && topic != "__TOTAL__")
+        .into_iter()
+        .map(|((_, topic), topic_metrics)| {
+            let load = broker_load(&topic_metrics.aggregate_broker_metrics(), window, use_bytes);
+            (topic, topic_metrics, load)
+        })
+        .collect::<Vec<_>>();
+    topics.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+    topics.truncate(HEATMAP_TOPICS);
+
+    let mut rows = vec![("Total".to_owned(), None, total_metrics)];
+    for (topic, topic_metrics, _) in topics {
+        let url = format!("/clusters/{}/topics/{}", cluster_id, topic);
+        rows.push((topic, Some(url), topic_metrics));
+    }
+    let rows = rows
+        .into_iter()
+        .map(|(name, url, topic_metrics)| {
+            let loads = broker_ids
+                .iter()
+                .map(|id| {
+                    topic_metrics
+                        .brokers
+                        .get(id)
+                        .map(|metrics| broker_load(metrics, window, use_bytes))
+                        .unwrap_or(0f64)
+                })
+                .collect::<Vec<_>>();
+            let max_load = loads.iter().cloned().fold(0f64, f64::max);
+            (name, url, loads, max_load)
+        })
+        .collect::<Vec<_>>();
+
+    html! {
+        table class="table table-bordered table-condensed" {
+            thead {
+                tr {
+                    th { "Topic" }
+                    @for broker_id in &broker_ids {
+                        th style="text-align: right" {
+                            a href=(format!("/clusters/{}/brokers/{}", cluster_id, broker_id)) { "Broker " (broker_id) }
+                        }
+                    }
+                }
+            }
+            tbody {
+                @for &(ref name, ref url, ref loads, max_load) in &rows {
+                    tr {
+                        td {
+                            @if let Some(ref url) = *url {
+                                a href=(url) { (name) }
+                            } @else {
+                                strong { (name) }
+                            }
+                        }
+                        @for load in loads {
+                            (heatmap_cell(*load, max_load, use_bytes))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 fn groups_table(cluster_id: &ClusterId) -> PreEscaped<String> {
     let api_url = format!("/api/clusters/{}/groups", cluster_id);
     layout::datatable_ajax(
         "groups-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" } } },
+        html! { tr { th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" }
+            th { "Coordinator" } th { "Time lag" } th { "Lag status" } } },
+    )
+}
+
+fn coordinated_groups_table(cluster_id: &ClusterId, broker_id: BrokerId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/brokers/{}/groups", cluster_id, broker_id);
+    layout::datatable_ajax(
+        "groups-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" }
+            th { "Coordinator" } th { "Time lag" } th { "Lag status" } } },
     )
 }
 
@@ -60,12 +231,56 @@ fn reassignment_table(cluster_id: &ClusterId) -> PreEscaped<String> {
         "reassignment-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Topic" } th { "Partition" } th { "Reassigned replicas" } th { "Replica sizes" } } },
+        html! { tr { th { "Topic" } th { "Partition" } th { "Reassigned replicas" } th { "Replica sizes" }
+            th { "Log dirs" } th { "Future replica lag" } } },
     )
 }
 
-#[get("/clusters/<cluster_id>")]
-pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Config>) -> Markup {
+fn format_millis(millis: u64) -> String {
+    Utc.timestamp_millis(millis as i64)
+        .format("%F %T UTC")
+        .to_string()
+}
+
+fn controller_history_table(cluster_info: &ClusterInfo) -> PreEscaped<String> {
+    html! {
+        table class="table table-striped table-bordered table-hover" style="width: auto" {
+            thead { tr { th { "Time" } th { "Controller" } } }
+            tbody {
+                @for change in cluster_info.controller_history.iter().rev() {
+                    tr {
+                        td { (format_millis(change.timestamp)) }
+                        td {
+                            @if let Some(controller_id) = change.controller_id {
+                                (controller_id)
+                            } @else {
+                                "None"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn events_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/events", cluster_id);
+    layout::datatable_ajax(
+        "events-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Time" } th { "Event" } th { "Topic" } th { "Partition" } th { "Details" } } },
+    )
+}
+
+#[get("/clusters/<cluster_id>?<window>")]
+pub fn cluster_page(
+    cluster_id: ClusterId,
+    window: Option<&RawStr>,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
     if cache.brokers.get(&cluster_id).is_none() {
         return pages::warning_page(
             &format!("Cluster: {}", cluster_id),
@@ -74,22 +289,48 @@ pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Co
     }
 
     let cluster_config = config.clusters.get(&cluster_id);
+    let cluster_info = cache.cluster_info.get(&cluster_id).unwrap_or_default();
+    let total_metrics = cache
+        .metrics
+        .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()));
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
+    let page_url = format!("/clusters/{}", cluster_id);
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
         dl class="dl-horizontal" {
             dt { "Cluster name: " } dd { (cluster_id.name()) }
+            dt { "Cluster id: " } dd { (cluster_info.cluster_uuid.clone().unwrap_or_else(|| "Unknown".to_owned())) }
+            dt { "Controller: " }
+            dd {
+                @if let Some(controller_id) = cluster_info.controller_id {
+                    a href=(format!("/clusters/{}/brokers/{}", cluster_id, controller_id)) { (controller_id) }
+                } @else {
+                    "Unknown"
+                }
+            }
             @if cluster_config.is_some() {
                 dt { "Bootstrap list: " } dd { (cluster_config.unwrap().broker_list.join(", ")) }
                 dt { "Zookeeper: " } dd { (cluster_config.unwrap().zookeeper) }
+                dt { "Metrics source: " }
+                dd {
+                    @if let Some(ref total_metrics) = total_metrics {
+                        (total_metrics.source.description())
+                    } @else {
+                        "None"
+                    }
+                }
+                dt { "Rates: " } dd { (layout::rate_window_selector(&page_url, window)) }
             } @else {
                 dt { "Bootstrap list: " } dd { "Cluster configuration is missing" }
                 dt { "Zookeeper: " } dd { "Cluster configuration is missing" }
             }
         }
         h3 { "Brokers" }
-        div { (broker_table(&cluster_id)) }
+        div { (broker_table(&cluster_id, window)) }
+        h3 { "Broker load" }
+        (broker_load_heatmap(&cache, &cluster_id, window))
         h3 { "Topics" }
-        (topic_table(&cluster_id))
+        (topic_table(&cluster_id, window))
         h3 { "Consumer groups" }
         (groups_table(&cluster_id))
 
@@ -97,14 +338,82 @@ pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Co
             h3 { "Reassignment" }
             (reassignment_table(&cluster_id))
         }
+        h3 { "Controller history" }
+        (controller_history_table(&cluster_info))
+        h3 { "Timeline" }
+        (events_table(&cluster_id))
     };
     layout::page(&format!("Cluster: {}", cluster_id), content)
 }
 
-#[get("/clusters/<cluster_id>/brokers/<broker_id>")]
+fn format_metric<F: Fn(f64) -> String>(value: Option<f64>, format: F) -> String {
+    value.map(format).unwrap_or_else(|| "Unknown".to_owned())
+}
+
+fn progress_bar(ratio: Option<f64>, label: &str) -> PreEscaped<String> {
+    let percentage = (ratio.unwrap_or(0f64) * 100f64).max(0f64).min(100f64);
+    let bar_class = if percentage > 85f64 {
+        "progress-bar progress-bar-danger"
+    } else if percentage > 60f64 {
+        "progress-bar progress-bar-warning"
+    } else {
+        "progress-bar progress-bar-success"
+    };
+    html! {
+        @if ratio.is_some() {
+            div class="progress" style="margin-bottom: 0px; width: 300px" {
+                div class=(bar_class) role="progressbar" style=(format!("width: {:.0}%", percentage)) {
+                    (label)
+                }
+            }
+        } @else {
+            "Unknown"
+        }
+    }
+}
+
+fn broker_metrics_section(metrics: &BrokerMetrics) -> PreEscaped<String> {
+    let rate = |v: f64| format!("{:.2}/s", v);
+    let millis = |v: f64| format!("{:.1} ms", v);
+    let busy_ratio = metrics.request_handler_idle_ratio.map(|idle| 1f64 - idle);
+    let heap_ratio = match (metrics.heap_used_bytes, metrics.heap_max_bytes) {
+        (Some(used), Some(max)) if max > 0f64 => Some(used / max),
+        _ => None,
+    };
+    let heap_label = format!(
+        "{} / {} MB",
+        format_metric(metrics.heap_used_bytes, &|v| format!("{:.0}", v / 1048576f64)),
+        format_metric(metrics.heap_max_bytes, &|v| format!("{:.0}", v / 1048576f64))
+    );
+    html! {
+        dl class="dl-horizontal" {
+            dt { "Bytes out" } dd { (format_metric(metrics.bytes_out_rate, &|v| format!("{:.1} KB/s", v / 1000f64))) }
+            dt { "Failed produce" } dd { (format_metric(metrics.failed_produce_rate, &rate)) }
+            dt { "Failed fetch" } dd { (format_metric(metrics.failed_fetch_rate, &rate)) }
+            dt { "Request handlers busy" }
+            dd { (progress_bar(busy_ratio, &format_metric(busy_ratio, &|v| format!("{:.0}%", v * 100f64)))) }
+            dt { "Produce latency" }
+            dd { (format_metric(metrics.produce_latency_mean_ms, &millis)) " mean, "
+                 (format_metric(metrics.produce_latency_p99_ms, &millis)) " 99th percentile" }
+            dt { "Fetch latency" }
+            dd { (format_metric(metrics.fetch_latency_mean_ms, &millis)) " mean, "
+                 (format_metric(metrics.fetch_latency_p99_ms, &millis)) " 99th percentile" }
+            dt { "Under replicated" } dd { (format_metric(metrics.under_replicated_partitions, &|v| format!("{:.0} partitions", v))) }
+            dt { "ISR shrinks" } dd { (format_metric(metrics.isr_shrink_rate, &rate)) }
+            dt { "ISR expands" } dd { (format_metric(metrics.isr_expand_rate, &rate)) }
+            dt { "Heap" } dd { (progress_bar(heap_ratio, &heap_label)) }
+            dt { "GC" }
+            dd { (format_metric(metrics.gc_count, &|v| format!("{:.0} collections", v))) ", "
+                 (format_metric(metrics.gc_time_ms, &|v| format!("{:.1} s", v / 1000f64))) " total" }
+        }
+    }
+}
+
+#[get("/clusters/<cluster_id>/brokers/<broker_id>?<window>")]
 pub fn broker_page(
     cluster_id: ClusterId,
     broker_id: BrokerId,
+    window: Option<&RawStr>,
     cache: State<Cache>,
     config: State<Config>,
 ) -> Markup {
@@ -122,11 +431,26 @@ pub fn broker_page(
     }
 
     let broker = broker.unwrap();
-    let metrics = cache
+    let is_controller = cache
+        .cluster_info
+        .get(&cluster_id)
+        .and_then(|info| info.controller_id)
+        == Some(broker.id);
+    let total_metrics = cache
         .metrics
         .get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
-        .unwrap_or_default()
-        .aggregate_broker_metrics();
+        .unwrap_or_default();
+    let metrics = total_metrics
+        .brokers
+        .get(&broker.id)
+        .cloned()
+        .unwrap_or_default();
+    let broker_metrics = cache
+        .broker_metrics
+        .get(&(cluster_id.to_owned(), broker.id));
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
+    let page_url = format!("/clusters/{}/brokers/{}", cluster_id, broker.id);
+    let fetch_stats = TASK_STATS.get("metrics", &format!("{}:{}", cluster_id, broker.id));
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
         dl class="dl-horizontal" {
@@ -134,8 +458,26 @@ pub fn broker_page(
             dt { "Bootstrap list: " } dd { (cluster_config.unwrap().broker_list.join(", ")) }
             dt { "Zookeeper: " } dd { (cluster_config.unwrap().zookeeper) }
             dt { "Hostname" } dd { (broker.hostname) }
-            dt { "Traffic" } dd { (format!("{:.1} KB/s  {:.0} msg/s", metrics.b_rate_15 / 1000f64, metrics.m_rate_15)) }
+            dt { "Controller" } dd { @if is_controller { "Yes" } @else { "No" } }
+            dt { "Traffic" } dd { (layout::traffic(&metrics, total_metrics.source, window)) }
+            dt { "Rates" } dd { (layout::rate_window_selector(&page_url, window)) }
         }
+        h3 { "History" }
+        (layout::history_charts(&format!("/api/clusters/{}/brokers/{}/history", cluster_id, broker.id)))
+        @if let Some(ref broker_metrics) = broker_metrics {
+            h3 { "Metrics" }
+            (broker_metrics_section(broker_metrics))
+        }
+        @if let Some(ref fetch_stats) = fetch_stats {
+            dl class="dl-horizontal" {
+                dt { "Metrics fetch time" } dd { (format!("{:.0} ms", fetch_stats.last_duration * 1000f64)) }
+                dt { "Failed fetches" } dd { (fetch_stats.errors) " of " (fetch_stats.runs) }
+            }
+        }
+        h3 { "Topics" }
+        (broker_topics_table(&cluster_id, broker.id, window))
+        h3 { "Coordinated groups" }
+        (coordinated_groups_table(&cluster_id, broker.id))
     };
     layout::page(&format!("Broker: {}", cluster_id), content)
 }
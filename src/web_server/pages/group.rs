@@ -1,8 +1,17 @@
+use chrono::{TimeZone, Utc};
 use maud::{html, Markup, PreEscaped};
 use rocket::http::RawStr;
 use neo4rs::Graph;
 use cache::Cache;
+use config::Config;
+use error::*;
+use events::EventKind;
+use lag::LagStore;
+use lag_status::LagStatus;
 use metadata::ClusterId;
+use offset_reset::{self, OffsetResetPlan, OffsetResetRequest};
+use offsets::{group_coordinator, GroupState};
+use web_server::api::find_group;
 use web_server::pages;
 use web_server::view::layout;
 use rocket_session_store::SessionStore as RocketSessionStore;
@@ -10,7 +19,9 @@ use rocket_session_store::memory::MemoryStore as RocketMemoryStore;
 use cookie::CookieBuilder;
 use std::net::UdpSocket;
 use std::io::Read;
+use rocket::request::Form;
 use rocket::State;
+use web_server::admin::Admin;
 use crate::web_server::pages::topic::xr_xpath_parse_and_dispatch;
 
 fn group_members_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<String> {
@@ -19,7 +30,8 @@ fn group_members_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
         "group-members-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Member id" } th { "Client id" } th { "Hostname" } th { "Assignments" } } },
+        html! { tr { th { "Member id" } th { "Client id" } th { "Hostname" } th { "Subscription" }
+            th { "Assignments" } th { "User data" } } },
     );
 
     let cookie_builder = CookieBuilder::new("admin_session", "hardcoded_admin_jwt_eyJkpXVCJ9")
@@ -39,6 +51,17 @@ fn group_members_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
     table
 }
 
+fn connect_workers_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/groups/{}/workers", cluster_id, group_name);
+    layout::datatable_ajax(
+        "connect-workers-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Member id" } th { "Hostname" } th { "Worker URL" } th { "Leader" }
+            th { "Config offset" } th { "Connectors" } th { "Tasks" } th { "Revoked" } th { "Error" } } },
+    )
+}
+
 fn group_offsets_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<String> {
     if let Ok(socket) = UdpSocket::bind("0.0.0.0:7073") {
         let mut buf = [0u8; 512];
@@ -55,24 +78,282 @@ fn group_offsets_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
         &api_url,
         cluster_id.name(),
         html! { tr { th { "Topic" } th { "Partition" } th { "Size" } th { "Low mark" } th { "High mark" }
-        th { "Current offset" } th { "Lag" } th { "Lag %" }} },
+        th { "Current offset" } th { "Lag" } th { "Lag %" } th { "Last commit" } th { "Time lag" } th { "Status" } } },
     )
 }
 
+fn generations_table(state: &GroupState) -> PreEscaped<String> {
+    html! {
+        table class="table table-condensed" {
+            thead { tr { th { "Generation" } th { "Started" } th { "Members" } } }
+            tbody {
+                @for generation in state.generations.iter().rev() {
+                    tr {
+                        td { (generation.generation) }
+                        td { (format_time_ago(generation.timestamp)) }
+                        td { (generation.members) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Formats a timestamp in milliseconds, together with its age.
+fn format_time_ago(timestamp: i64) -> String {
+    let time = Utc.timestamp_millis(timestamp);
+    let age = Utc::now().signed_duration_since(time);
+    let age = if age.num_days() > 0 {
+        format!("{} days ago", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{} hours ago", age.num_hours())
+    } else if age.num_minutes() > 0 {
+        format!("{} minutes ago", age.num_minutes())
+    } else {
+        format!("{} seconds ago", age.num_seconds().max(0))
+    };
+    format!("{} ({})", time.format("%F %T UTC"), age)
+}
+
+/// Formats a time lag in seconds as "4m 12s".
+fn format_time_lag(seconds: i64) -> String {
+    if seconds >= 3600 {
+        format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// The fields of the offset reset form.
+#[derive(FromForm, Debug)]
+pub struct ResetOffsetsForm {
+    pub topic: String,
+    pub partitions: String,
+    pub strategy: String,
+    pub value: String,
+    pub dry_run: bool,
+}
+
+impl ResetOffsetsForm {
+    /// Resets the offsets of the group as requested by the form, or only plans the reset on a
+    /// dry run.
+    pub fn reset_offsets(
+        &self,
+        cache: &Cache,
+        config: &Config,
+        cluster_id: &ClusterId,
+        group_name: &str,
+        admin: &Admin,
+    ) -> Result<Vec<OffsetResetPlan>> {
+        let request = OffsetResetRequest::parse(
+            &self.topic,
+            &self.partitions,
+            &self.strategy,
+            &self.value,
+            self.dry_run,
+        )?;
+        info!(
+            "Offset reset of {} on {} requested by {}: {:?}",
+            group_name, cluster_id, admin.username, request
+        );
+        offset_reset::reset_offsets(cache, config, cluster_id, group_name, &request)
+    }
+}
+
+fn reset_offsets_form(
+    cluster_id: &ClusterId,
+    group_name: &str,
+    topics: &[String],
+) -> PreEscaped<String> {
+    let action = format!(
+        "/clusters/{}/groups/{}/reset_offsets",
+        cluster_id, group_name
+    );
+    html! {
+        form class="form-inline" method="post" action=(action) {
+            div class="form-group" {
+                select class="form-control" name="topic" {
+                    option value="" { "All topics" }
+                    @for topic in topics {
+                        option value=(topic) { (topic) }
+                    }
+                }
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="partitions" placeholder="Partitions (e.g. 0,1,2)" {}
+            }
+            " "
+            div class="form-group" {
+                select class="form-control" name="strategy" {
+                    option value="earliest" { "Earliest" }
+                    option value="latest" { "Latest" }
+                    option value="offset" { "Offset" }
+                    option value="timestamp" { "Timestamp" }
+                    option value="shift" { "Shift by" }
+                }
+            }
+            " "
+            div class="form-group" {
+                input class="form-control" type="text" name="value" placeholder="Offset, millis, RFC 3339 or N" {}
+            }
+            " "
+            div class="checkbox" {
+                label { input type="checkbox" name="dry_run" checked="" {} " Dry run" }
+            }
+            " "
+            button type="submit" class="btn btn-default" { "Reset offsets" }
+        }
+    }
+}
+
+fn reset_plan_table(plans: &[OffsetResetPlan]) -> PreEscaped<String> {
+    html! {
+        table class="table table-striped table-condensed" {
+            thead { tr { th { "Topic" } th { "Partition" } th { "Current offset" } th { "New offset" } th { "Difference" } } }
+            tbody {
+                @for plan in plans {
+                    tr {
+                        td { (plan.topic) }
+                        td { (plan.partition) }
+                        td { @if plan.current < 0 { "None" } @else { (plan.current) } }
+                        td { (plan.new) }
+                        td { @if plan.current < 0 { "" } @else { (plan.new - plan.current) } }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[post(
+    "/clusters/<cluster_id>/groups/<group_name>/reset_offsets",
+    data = "<form>"
+)]
+pub fn reset_offsets_page(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    form: Form<ResetOffsetsForm>,
+    admin: Admin,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
+    let result = form.reset_offsets(&cache, &config, &cluster_id, group_name.as_str(), &admin);
+    let plans = match result {
+        Ok(plans) => plans,
+        Err(e) => {
+            format_error_chain!(e);
+            return pages::warning_page(group_name, &e.chain_message());
+        }
+    };
+
+    let group_link = format!("/clusters/{}/groups/{}", cluster_id, group_name);
+    let action = format!("{}/reset_offsets", group_link);
+    let content = html! {
+        @if form.dry_run {
+            h3 style="margin-top: 0px" { "Offset reset preview (dry run)" }
+            p { "No offset has been changed. The reset will be refused if the group has active members." }
+        } @else {
+            h3 style="margin-top: 0px" { "Offsets reset" }
+        }
+        p { a href=(group_link) { "Back to group " (group_name) } }
+        (reset_plan_table(&plans))
+        @if form.dry_run {
+            form method="post" action=(action) {
+                input type="hidden" name="topic" value=(form.topic) {}
+                input type="hidden" name="partitions" value=(form.partitions) {}
+                input type="hidden" name="strategy" value=(form.strategy) {}
+                input type="hidden" name="value" value=(form.value) {}
+                button type="submit" class="btn btn-danger" { "Apply" }
+            }
+        }
+    };
+
+    layout::page(&format!("Reset offsets: {}", group_name), content)
+}
+
 #[get("/clusters/<cluster_id>/groups/<group_name>")]
-pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> Markup {
+pub fn group_page(
+    cluster_id: ClusterId,
+    group_name: &RawStr,
+    cache: State<Cache>,
+    config: State<Config>,
+) -> Markup {
     if cache.brokers.get(&cluster_id).is_none() {
         return pages::warning_page(group_name, "The specified cluster doesn't exist.");
     }
 
-    let group_state = match cache
-        .groups
-        .get(&(cluster_id.to_owned(), group_name.to_string()))
-    {
-        Some(group) => group.state,
-        None => "Not registered".to_string(),
+    let group = find_group(&cache, &cluster_id, group_name);
+    let deleted_at = cache
+        .events
+        .get(&cluster_id)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .find(|event| match event.kind {
+            EventKind::GroupDeleted { ref group } => group == group_name.as_str(),
+            _ => false,
+        })
+        .map(|event| event.timestamp);
+    let (group_state, protocol_type, protocol) = match (group, deleted_at) {
+        (Some(group), _) => (group.state, group.protocol_type, group.protocol),
+        (None, Some(timestamp)) => (
+            format!("Deleted {}", format_time_ago(timestamp as i64)),
+            String::new(),
+            String::new(),
+        ),
+        (None, None) => ("Not registered".to_string(), String::new(), String::new()),
     };
 
+    let offsets_partitions = cache
+        .topics
+        .get(&(cluster_id.to_owned(), "__consumer_offsets".to_owned()))
+        .unwrap_or_default();
+    let coordinator = group_coordinator(&offsets_partitions, group_name);
+
+    let last_commit = cache
+        .commits
+        .filter_clone(|&(ref c, ref g, _)| c == &cluster_id && g == group_name.as_str())
+        .into_iter()
+        .flat_map(|(_, commits)| commits)
+        .filter_map(|commit| commit.map(|commit| commit.commit_timestamp))
+        .max();
+
+    let group_lags = cache.lag_by_cluster_group(&cluster_id, group_name.as_str());
+    let mut topics = group_lags
+        .iter()
+        .map(|&((_, _, ref topic), _)| topic.clone())
+        .collect::<Vec<_>>();
+    topics.sort();
+    let partition_lags = group_lags
+        .into_iter()
+        .flat_map(|(_, partitions)| partitions)
+        .collect::<Vec<_>>();
+    let total_lag = partition_lags
+        .iter()
+        .filter(|p| p.lag >= 0)
+        .map(|p| p.lag)
+        .sum::<i64>();
+    let time_lag = partition_lags
+        .iter()
+        .filter(|p| p.time_lag >= 0)
+        .map(|p| p.time_lag)
+        .max()
+        .map(format_time_lag)
+        .unwrap_or_else(|| "unknown".to_owned());
+    let lag_status = partition_lags
+        .iter()
+        .map(|p| p.status)
+        .max()
+        .unwrap_or(LagStatus::Unknown);
+
+    let state = cache
+        .group_states
+        .get(&(cluster_id.to_owned(), group_name.to_string()));
+    let day_ago = (Utc::now().timestamp() - 24 * 3600) * 1000;
+
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
     let content = html! {
         h3 style="margin-top: 0px" { "Information" }
@@ -80,11 +361,64 @@ pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache
             dt { "Cluster name:" } dd { a href=(cluster_link) { (cluster_id) } }
             dt { "Group name: " } dd { (group_name) }
             dt { "Group state: " } dd { (group_state) }
+            dt { "Coordinator: " }
+            dd {
+                @if let Some((partition, broker_id)) = coordinator {
+                    a href=(format!("/clusters/{}/brokers/{}", cluster_id, broker_id)) { "Broker " (broker_id) }
+                    " (__consumer_offsets partition " (partition) ")"
+                } @else {
+                    "Unknown"
+                }
+            }
+            @if !protocol_type.is_empty() {
+                dt { "Protocol type: " } dd { (protocol_type) }
+            }
+            @if !protocol.is_empty() {
+                dt { "Protocol: " } dd { (protocol) }
+            }
+            @if let Some(ref state) = state {
+                dt { "Generation: " } dd { (state.generation) }
+                dt { "Leader: " } dd { (state.leader.clone().unwrap_or_else(|| "None".to_owned())) }
+                dt { "Rebalances (24h): " } dd { (state.rebalances_since(day_ago)) }
+            }
+            dt { "Lag: " }
+            dd {
+                @if partition_lags.is_empty() {
+                    "Unknown"
+                } @else {
+                    (total_lag) " messages (lag: " (time_lag) ")"
+                }
+            }
+            dt { "Lag status: " }
+            dd title=(lag_status.description()) { (lag_status.name()) }
+            dt { "Last commit: " }
+            dd {
+                @if let Some(timestamp) = last_commit {
+                    (format_time_ago(timestamp))
+                } @else {
+                    "Unknown"
+                }
+            }
+        }
+        h3 { "Lag history" }
+        (layout::history_charts(&format!("/api/clusters/{}/groups/{}/history", cluster_id, group_name)))
+        @if protocol_type == "connect" {
+            h3 { "Connect workers" }
+            div { (connect_workers_table(&cluster_id, group_name)) }
+        } @else {
+            h3 { "Members" }
+            div { (group_members_table(&cluster_id, group_name)) }
         }
-        h3 { "Members" }
-        div { (group_members_table(&cluster_id, group_name)) }
         h3 { "Offsets" }
         div { (group_offsets_table(&cluster_id, group_name)) }
+        @if let Some(ref state) = state {
+            h3 { "Generations" }
+            div { (generations_table(state)) }
+        }
+        @if config.admin_auth.is_some() {
+            h3 { "Reset offsets" }
+            div { (reset_offsets_form(&cluster_id, group_name, &topics)) }
+        }
     };
 
     layout::page(&format!("Group: {}", group_name), content)
@@ -94,3 +94,23 @@ pub fn live_consumers_page() -> Markup {
     };
     layout::page("Live consumers", content)
 }
+
+fn offset_consumers_table() -> PreEscaped<String> {
+    layout::datatable_ajax(
+        "internals-offset-consumers-ajax",
+        "/api/internals/offset_consumers",
+        "",
+        html! { tr { th { "Cluster id" } th { "State" } th { "Restarts" } th { "Last error" }
+            th { "Last error time" } th { "Messages" } th { "Messages/s" } th { "Last message" }
+            th { "Lag" } } },
+    )
+}
+
+#[get("/internals/offset_consumers")]
+pub fn offset_consumers_page() -> Markup {
+    let content = html! {
+        h3 style="margin-top: 0px" { "Consumers of __consumer_offsets" }
+        div { (offset_consumers_table()) }
+    };
+    layout::page("Offset consumers", content)
+}
@@ -68,7 +68,8 @@ pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
         "group-search-ajax",
         &api_url,
         "",
-        html! { tr { th { "Cluster" } th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" } } },
+        html! { tr { th { "Cluster" } th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" }
+            th { "Coordinator" } th { "Time lag" } th { "Lag status" } } },
     );
 
     layout::page(
@@ -127,7 +128,8 @@ pub fn consumer_search_p(search: OmnisearchFormParams) -> Markup {
         "group-search-ajax",
         &api_url,
         "",
-        html! { tr { th { "Cluster" } th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" } } },
+        html! { tr { th { "Cluster" } th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" }
+            th { "Coordinator" } th { "Time lag" } th { "Lag status" } } },
     );
 
     let tainted_input = search.string.clone();
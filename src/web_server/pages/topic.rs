@@ -5,6 +5,7 @@ use std::error::Error;
 use cache::Cache;
 use config::Config;
 use metadata::ClusterId;
+use metrics::RateWindow;
 use web_server::pages;
 use web_server::view::layout;
 use std::net::UdpSocket;
@@ -37,13 +38,47 @@ fn topic_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
     )
 }
 
+fn topic_brokers_table(
+    cluster_id: &ClusterId,
+    topic_name: &str,
+    window: RateWindow,
+) -> PreEscaped<String> {
+    let api_url = format!(
+        "/api/clusters/{}/topics/{}/brokers?window={}",
+        cluster_id,
+        topic_name,
+        window.param()
+    );
+    layout::datatable_ajax(
+        "topic-brokers-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Broker" }
+            th data-toggle="tooltip" data-container="body" title=(window.description()) { "Byte rate" }
+            th data-toggle="tooltip" data-container="body" title=(window.description()) { "Msg rate" }
+            th data-toggle="tooltip" data-container="body" title="Share of the traffic of the topic" { "Share" }
+            th { "Size" } } },
+    )
+}
+
 fn consumer_groups_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
     let api_url = format!("/api/clusters/{}/topics/{}/groups", cluster_id, topic_name);
     layout::datatable_ajax(
         "groups-ajax",
         &api_url,
         cluster_id.name(),
-        html! { tr { th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" } } },
+        html! { tr { th { "Group name" } th { "Status" } th { "Registered members" } th { "Stored topic offsets" }
+            th { "Coordinator" } th { "Time lag" } th { "Lag status" } } },
+    )
+}
+
+fn topic_events_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics/{}/events", cluster_id, topic_name);
+    layout::datatable_ajax(
+        "events-ajax",
+        &api_url,
+        cluster_id.name(),
+        html! { tr { th { "Time" } th { "Event" } th { "Topic" } th { "Partition" } th { "Details" } } },
     )
 }
 
@@ -66,10 +101,11 @@ fn topic_tailer_panel(cluster_id: &ClusterId, topic: &str, tailer_id: u64) -> Pr
     layout::panel(panel_head, panel_body)
 }
 
-#[get("/clusters/<cluster_id>/topics/<topic_name>")]
+#[get("/clusters/<cluster_id>/topics/<topic_name>?<window>")]
 pub fn topic_page(
     cluster_id: ClusterId,
     topic_name: &RawStr,
+    window: Option<&RawStr>,
     cache: State<Cache>,
     config: State<Config>,
 ) -> Markup {
@@ -103,11 +139,13 @@ pub fn topic_page(
         .get(&cluster_id)
         .expect("Cluster should exist"); // TODO: handle better
 
-    let metrics = cache
+    let topic_metrics = cache
         .metrics
         .get(&(cluster_id.clone(), topic_name.to_string()))
-        .unwrap_or_default()
-        .aggregate_broker_metrics();
+        .unwrap_or_default();
+    let metrics = topic_metrics.aggregate_broker_metrics();
+    let window = RateWindow::from_param(window.map(|w| w.as_str()));
+    let page_url = format!("/clusters/{}/topics/{}", cluster_id, topic_name);
 
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
     let content = html! {
@@ -117,16 +155,23 @@ pub fn topic_page(
             dt { "Topic name " dd { (topic_name) } }
             dt { "Number of partitions " dd { (partitions.len()) } }
             dt { "Number of replicas " dd { (partitions[0].replicas.len()) } }
-            dt { "Traffic last 15 minutes" }
-            dd { ( format!("{:.1}   KB/s {:.0} msg/s", metrics.b_rate_15 / 1000f64, metrics.m_rate_15)) }
+            dt { (format!("Traffic ({})", window.label())) }
+            dd { (layout::traffic(&metrics, topic_metrics.source, window)) }
+            dt { "Rates" } dd { (layout::rate_window_selector(&page_url, window)) }
             @if cluster_config.graph_url.is_some() {
                 dt { "Traffic chart" } dd { (graph_link(cluster_config.graph_url.as_ref().unwrap(), topic_name)) }
             }
         }
+        h3 { "History" }
+        (layout::history_charts(&format!("/api/clusters/{}/topics/{}/history", cluster_id, topic_name)))
+        h3 { "Traffic by broker" }
+        (topic_brokers_table(&cluster_id, topic_name, window))
         h3 { "Topology" }
         (topic_table(&cluster_id, topic_name))
         h3 {"Consumer groups"}
         (consumer_groups_table(&cluster_id, topic_name))
+        h3 { "Timeline" }
+        (topic_events_table(&cluster_id, topic_name))
         h3 { "Tailer" }
         @if cluster_config.enable_tailing {
             (topic_tailer_panel(&cluster_id, topic_name, random::<u64>()))
@@ -0,0 +1,333 @@
+use rocket::http::ContentType;
+use rocket::response::content::Content;
+use rocket::State;
+
+use cache::Cache;
+use metrics::BrokerMetrics;
+use lag::LagStore;
+use task_stats::TASK_STATS;
+
+use std::fmt::Write;
+
+/// Accumulates metrics in the Prometheus text exposition format.
+struct Exposition {
+    output: String,
+}
+
+impl Exposition {
+    fn new() -> Exposition {
+        Exposition {
+            output: String::new(),
+        }
+    }
+
+    fn header(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|&(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = writeln!(self.output, "{}{{{}}} {}", name, labels, value);
+    }
+
+    fn into_string(self) -> String {
+        self.output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn cluster_metrics(exp: &mut Exposition, cache: &Cache) {
+    exp.header(
+        "kafka_view_brokers",
+        "gauge",
+        "Number of brokers in the cluster.",
+    );
+    cache.brokers.lock_iter(|iter| {
+        for (cluster_id, brokers) in iter {
+            exp.sample(
+                "kafka_view_brokers",
+                &[("cluster", cluster_id.name())],
+                brokers.len() as f64,
+            );
+        }
+    });
+
+    let topics = cache.topics.filter_clone(|_| true);
+    exp.header(
+        "kafka_view_topic_partitions",
+        "gauge",
+        "Number of partitions of the topic.",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        exp.sample(
+            "kafka_view_topic_partitions",
+            &[("cluster", cluster_id.name()), ("topic", topic)],
+            partitions.len() as f64,
+        );
+    }
+    exp.header(
+        "kafka_view_topic_under_replicated_partitions",
+        "gauge",
+        "Number of partitions with fewer in sync replicas than replicas.",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let count = partitions
+            .iter()
+            .filter(|p| p.isr.len() < p.replicas.len())
+            .count();
+        exp.sample(
+            "kafka_view_topic_under_replicated_partitions",
+            &[("cluster", cluster_id.name()), ("topic", topic)],
+            count as f64,
+        );
+    }
+    exp.header(
+        "kafka_view_topic_offline_partitions",
+        "gauge",
+        "Number of partitions without a leader or reporting an error.",
+    );
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let count = partitions
+            .iter()
+            .filter(|p| p.leader < 0 || p.error.is_some())
+            .count();
+        exp.sample(
+            "kafka_view_topic_offline_partitions",
+            &[("cluster", cluster_id.name()), ("topic", topic)],
+            count as f64,
+        );
+    }
+}
+
+fn topic_metrics(exp: &mut Exposition, cache: &Cache) {
+    let metrics = cache
+        .metrics
+        .filter_clone(|&(_, ref topic)| topic != "__TOTAL__");
+
+    exp.header(
+        "kafka_view_topic_bytes_in_rate",
+        "gauge",
+        "Incoming byte rate of the topic, averaged over 15 minutes.",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        let aggregated = topic_metrics.aggregate_broker_metrics();
+        if aggregated.b_rate_15 >= 0f64 {
+            exp.sample(
+                "kafka_view_topic_bytes_in_rate",
+                &[("cluster", cluster_id.name()), ("topic", topic)],
+                aggregated.b_rate_15,
+            );
+        }
+    }
+    exp.header(
+        "kafka_view_topic_messages_in_rate",
+        "gauge",
+        "Incoming message rate of the topic, averaged over 15 minutes.",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        let aggregated = topic_metrics.aggregate_broker_metrics();
+        if aggregated.m_rate_15 >= 0f64 {
+            exp.sample(
+                "kafka_view_topic_messages_in_rate",
+                &[("cluster", cluster_id.name()), ("topic", topic)],
+                aggregated.m_rate_15,
+            );
+        }
+    }
+    exp.header(
+        "kafka_view_partition_size_bytes",
+        "gauge",
+        "Size of the partition on its leader.",
+    );
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        let partitions = cache
+            .topics
+            .get(&(cluster_id.clone(), topic.clone()))
+            .unwrap_or_default();
+        for p in partitions {
+            let size = topic_metrics
+                .brokers
+                .get(&p.leader)
+                .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                .map(|partition_metrics| partition_metrics.size_bytes);
+            if let Some(size) = size {
+                exp.sample(
+                    "kafka_view_partition_size_bytes",
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("topic", topic),
+                        ("partition", &p.id.to_string()),
+                    ],
+                    size,
+                );
+            }
+        }
+    }
+}
+
+fn broker_metrics(exp: &mut Exposition, cache: &Cache) {
+    let metrics = cache.broker_metrics.filter_clone(|_| true);
+    let names = BrokerMetrics::default()
+        .values()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    for name in names {
+        let metric_name = format!("kafka_view_broker_{}", name);
+        exp.header(
+            &metric_name,
+            "gauge",
+            "Broker metric read from the metrics agent.",
+        );
+        for &((ref cluster_id, broker_id), ref broker_metrics) in &metrics {
+            let value = broker_metrics
+                .values()
+                .into_iter()
+                .find(|&(n, _)| n == name)
+                .and_then(|(_, value)| value);
+            if let Some(value) = value {
+                exp.sample(
+                    &metric_name,
+                    &[
+                        ("cluster", cluster_id.name()),
+                        ("broker", &broker_id.to_string()),
+                    ],
+                    value,
+                );
+            }
+        }
+    }
+}
+
+fn group_metrics(exp: &mut Exposition, cache: &Cache) {
+    let mut committed = Vec::new();
+    let mut lag = Vec::new();
+    for cluster_id in cache.brokers.keys() {
+        for ((cluster_id, group, topic), partitions) in cache.lag_by_cluster(&cluster_id) {
+            for (partition_id, p) in partitions.iter().enumerate() {
+                if p.offset < 0 {
+                    continue;
+                }
+                let labels = (
+                    cluster_id.clone(),
+                    group.clone(),
+                    topic.clone(),
+                    partition_id.to_string(),
+                );
+                committed.push((labels.clone(), p.offset as f64));
+                if p.lag >= 0 {
+                    lag.push((labels, p.lag as f64));
+                }
+            }
+        }
+    }
+
+    exp.header(
+        "kafka_view_group_committed_offset",
+        "gauge",
+        "Last offset committed by the consumer group.",
+    );
+    for &((ref cluster_id, ref group, ref topic, ref partition), value) in &committed {
+        exp.sample(
+            "kafka_view_group_committed_offset",
+            &[
+                ("cluster", cluster_id.name()),
+                ("group", group),
+                ("topic", topic),
+                ("partition", partition),
+            ],
+            value,
+        );
+    }
+    exp.header(
+        "kafka_view_group_lag",
+        "gauge",
+        "Difference between the high watermark and the committed offset.",
+    );
+    for &((ref cluster_id, ref group, ref topic, ref partition), value) in &lag {
+        exp.sample(
+            "kafka_view_group_lag",
+            &[
+                ("cluster", cluster_id.name()),
+                ("group", group),
+                ("topic", topic),
+                ("partition", partition),
+            ],
+            value,
+        );
+    }
+}
+
+fn internal_metrics(exp: &mut Exposition) {
+    let stats = TASK_STATS.snapshot();
+    exp.header(
+        "kafka_view_fetch_duration_seconds",
+        "gauge",
+        "Duration of the last execution of the background fetch.",
+    );
+    for &((ref task, ref target), ref task_stats) in &stats {
+        exp.sample(
+            "kafka_view_fetch_duration_seconds",
+            &[("task", task), ("target", target)],
+            task_stats.last_duration,
+        );
+    }
+    exp.header(
+        "kafka_view_fetch_duration_seconds_total",
+        "counter",
+        "Total time spent executing the background fetch.",
+    );
+    for &((ref task, ref target), ref task_stats) in &stats {
+        exp.sample(
+            "kafka_view_fetch_duration_seconds_total",
+            &[("task", task), ("target", target)],
+            task_stats.total_duration,
+        );
+    }
+    exp.header(
+        "kafka_view_fetch_runs_total",
+        "counter",
+        "Number of executions of the background fetch.",
+    );
+    for &((ref task, ref target), ref task_stats) in &stats {
+        exp.sample(
+            "kafka_view_fetch_runs_total",
+            &[("task", task), ("target", target)],
+            task_stats.runs as f64,
+        );
+    }
+    exp.header(
+        "kafka_view_fetch_errors_total",
+        "counter",
+        "Number of failed executions of the background fetch.",
+    );
+    for &((ref task, ref target), ref task_stats) in &stats {
+        exp.sample(
+            "kafka_view_fetch_errors_total",
+            &[("task", task), ("target", target)],
+            task_stats.errors as f64,
+        );
+    }
+}
+
+#[get("/metrics")]
+pub fn metrics(cache: State<Cache>) -> Content<String> {
+    let mut exp = Exposition::new();
+    cluster_metrics(&mut exp, &cache);
+    topic_metrics(&mut exp, &cache);
+    broker_metrics(&mut exp, &cache);
+    group_metrics(&mut exp, &cache);
+    internal_metrics(&mut exp);
+    Content(ContentType::Plain, exp.into_string())
+}
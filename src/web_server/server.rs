@@ -10,8 +10,10 @@ use error::*;
 use live_consumer::{self, LiveConsumerStore};
 use metadata::ClusterId;
 use utils::{GZip, RequestLogger};
+use web_server::admin;
 use web_server::api;
 use web_server::pages;
+use web_server::prometheus;
 
 use std;
 use std::path::{Path, PathBuf};
@@ -113,8 +115,10 @@ pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config)
                 pages::cluster::broker_page,
                 pages::clusters::clusters_page,
                 pages::group::group_page,
+                pages::group::reset_offsets_page,
                 pages::internals::caches_page,
                 pages::internals::live_consumers_page,
+                pages::internals::offset_consumers_page,
                 pages::omnisearch::consumer_search,
                 pages::omnisearch::consumer_search_p,
                 pages::omnisearch::omnisearch,
@@ -123,22 +127,36 @@ pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config)
                 pages::omnisearch::topic_search_p,
                 pages::topic::topic_page,
                 api::brokers,
+                api::broker_history,
+                api::broker_topics,
+                api::broker_groups,
                 api::cache_brokers,
                 api::cache_metrics,
                 api::cache_offsets,
+                api::cluster_events,
                 api::cluster_reassignment,
                 api::live_consumers,
+                api::offset_consumers,
                 api::cluster_groups,
                 api::cluster_topics,
                 api::consumer_search,
+                api::group_connect_workers,
+                api::group_history,
                 api::group_members,
                 api::group_offsets,
+                api::group_status,
+                api::reset_group_offsets,
+                api::topic_events,
+                api::topic_brokers,
                 api::topic_groups,
+                api::topic_history,
                 api::topic_search,
                 api::topic_topology,
                 live_consumer::topic_tailer_api,
+                prometheus::metrics,
             ],
         )
+        .register(catchers![admin::unauthorized])
         .launch();
 
     Ok(())
@@ -1,5 +1,6 @@
 use crate::RUST_VERSION;
 use maud::{self, html, PreEscaped};
+use metrics::{RateSource, RateWindow, TopicBrokerMetrics};
 use std::io;
 use std::process::Command;
 
@@ -61,6 +62,55 @@ pub fn notification(n_type: &str, content: PreEscaped<String>) -> PreEscaped<Str
     }
 }
 
+/// Formats the traffic rates averaged over the window. Negative rates are unknown.
+pub fn traffic(
+    metrics: &TopicBrokerMetrics,
+    source: RateSource,
+    window: RateWindow,
+) -> PreEscaped<String> {
+    let b_rate = metrics.byte_rate(window);
+    let bytes = if b_rate < 0f64 {
+        "Unknown KB/s".to_owned()
+    } else {
+        format!("{:.1} KB/s", b_rate / 1000f64)
+    };
+    let m_rate = metrics.msg_rate(window);
+    let msgs = if m_rate < 0f64 {
+        "Unknown msg/s".to_owned()
+    } else {
+        format!("{:.0} msg/s", m_rate)
+    };
+    html! {
+        (format!("{}  {}", bytes, msgs))
+        @if source == RateSource::Watermarks {
+            " " small class="text-muted" { "(" (source.description()) ")" }
+        }
+    }
+}
+
+/// Links to the same page with each of the rate windows. `url` must not contain a query.
+pub fn rate_window_selector(url: &str, current: RateWindow) -> PreEscaped<String> {
+    html! {
+        div class="btn-group btn-group-xs" role="group" {
+            @for window in &RateWindow::all() {
+                @if *window == current {
+                    a class="btn btn-default active" title=(window.description()) { (window.label()) }
+                } @else {
+                    a class="btn btn-default" href=(format!("{}?window={}", url, window.param()))
+                        title=(window.description()) { (window.label()) }
+                }
+            }
+        }
+    }
+}
+
+/// Charts of the time series returned by the history API at `url`, drawn by my_js.js.
+pub fn history_charts(url: &str) -> PreEscaped<String> {
+    html! {
+        div class="history-charts row" data-url=(url) {}
+    }
+}
+
 pub fn datatable_ajax(
     id: &str,
     url: &str,
@@ -201,6 +251,11 @@ fn navbar_side() -> PreEscaped<String> {
                                     i class="fa fa-microchip fa-fw" {}  " Live consumers"
                                 }
                             }
+                            li {
+                                a href="/internals/offset_consumers" {
+                                    i class="fa fa-microchip fa-fw" {}  " Offset consumers"
+                                }
+                            }
                             // li {
                             //     a href="#" { "Third Level" span class="fa arrow" {} }
                             //     ul class="nav nav-third-level" {
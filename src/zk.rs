@@ -4,13 +4,25 @@ use axum::response::Html;
 use salvo::writing::Text;
 use crate::error::*;
 use error::*;
-use metadata::Reassignment;
+use metadata::{BrokerId, Reassignment};
 use isahc::{HttpClient, Request};
 use std::str;
 use std::time::Duration;
 use isahc::ReadResponseExt;
 
 const REASSIGN_PARTITIONS: &str = "/admin/reassign_partitions";
+const CONTROLLER: &str = "/controller";
+const CLUSTER_ID: &str = "/cluster/id";
+
+#[derive(Deserialize)]
+struct ControllerNode {
+    brokerid: BrokerId,
+}
+
+#[derive(Deserialize)]
+struct ClusterIdNode {
+    id: String,
+}
 
 pub struct ZK {
     client: ZooKeeper,
@@ -41,6 +53,30 @@ impl ZK {
         let raw = str::from_utf8(&data).ok()?;
         serde_json::from_str(raw).ok()
     }
+
+    /// Returns the id of the active controller, if any.
+    pub fn controller_id(&self) -> Result<Option<BrokerId>> {
+        let data = match self.client.get_data(CONTROLLER, false) {
+            Ok((data, _)) => data,
+            Err(ZkError::NoNode) => return Ok(None), // controller election in progress
+            Err(error) => bail!("Error fetching controller: {:?}", error),
+        };
+        let controller = serde_json::from_slice::<ControllerNode>(&data)
+            .chain_err(|| "Failed to parse controller node")?;
+        Ok(Some(controller.brokerid))
+    }
+
+    /// Returns the unique id of the cluster, generated by the brokers on first startup.
+    pub fn cluster_uuid(&self) -> Result<Option<String>> {
+        let data = match self.client.get_data(CLUSTER_ID, false) {
+            Ok((data, _)) => data,
+            Err(ZkError::NoNode) => return Ok(None),
+            Err(error) => bail!("Error fetching cluster id: {:?}", error),
+        };
+        let cluster_id = serde_json::from_slice::<ClusterIdNode>(&data)
+            .chain_err(|| "Failed to parse cluster id node")?;
+        Ok(Some(cluster_id.id))
+    }
 }
 
 